@@ -0,0 +1,73 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reference signer daemon that holds the anchoring keys outside of the node.
+
+extern crate clap;
+extern crate exonum;
+extern crate exonum_btc_anchoring as anchoring;
+#[macro_use]
+extern crate log;
+extern crate toml;
+
+use std::fs::File;
+use std::io::Read;
+
+use clap::{App, Arg};
+use exonum::helpers;
+
+use anchoring::details::btc;
+use anchoring::details::signer::{self, LocalSigner, PolicySigner, SignerDaemonConfig,
+                                 SignerEndpoint};
+
+fn main() {
+    helpers::init_logger().unwrap();
+    let matches = App::new("anchoring-signer")
+        .about("Signs the anchoring transactions by the keys from the given config")
+        .arg(
+            Arg::with_name("CONFIG")
+                .long("config")
+                .takes_value(true)
+                .required(true)
+                .help("Path to the signer config in toml"),
+        )
+        .get_matches();
+
+    let mut content = String::new();
+    File::open(matches.value_of("CONFIG").unwrap())
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .expect("Unable to read signer config");
+    let mut config: SignerDaemonConfig =
+        toml::from_str(&content).expect("Incorrect signer config");
+    config
+        .unlock_keys()
+        .expect("Unable to unlock the signer keystore");
+    let endpoint: SignerEndpoint = config.endpoint.parse().expect("Incorrect signer endpoint");
+
+    let auth_token = config.auth_token.as_ref().map(String::as_str);
+    let signer = LocalSigner::new(&config.private_keys);
+    if config.policy.enabled {
+        let addresses = config
+            .private_keys
+            .keys()
+            .map(|addr| addr.parse::<btc::Address>().expect("Incorrect anchoring address"))
+            .collect();
+        let signer = PolicySigner::new(signer, addresses, config.policy.clone())
+            .expect("Unable to load signer state");
+        signer::listen(&endpoint, &signer, auth_token).expect("Signer daemon failed");
+    } else {
+        warn!("Signing policy is disabled, any transaction of the anchoring keys is signed");
+        signer::listen(&endpoint, &signer, auth_token).expect("Signer daemon failed");
+    }
+}
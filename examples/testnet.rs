@@ -26,7 +26,7 @@ use exonum::storage::{RocksDB, RocksDBOptions};
 use exonum::helpers::{generate_testnet_config, init_logger};
 
 use exonum_btc_anchoring::{gen_anchoring_testnet_config, AnchoringRpcConfig, AnchoringService,
-                           BitcoinNetwork, RpcClient};
+                           BitcoinNetwork};
 
 fn main() {
     // Init crypto engine and pretty logger.
@@ -41,6 +41,7 @@ fn main() {
             .unwrap(),
         username: env::var("ANCHORING_USER").ok(),
         password: env::var("ANCHORING_PASSWORD").ok(),
+        ..Default::default()
     };
 
     // Blockchain params
@@ -52,7 +53,9 @@ fn main() {
     let destdir = tmpdir_handle.path();
 
     // Generate blockchain configuration
-    let client = RpcClient::from(rpc_config.clone());
+    let client = rpc_config
+        .client()
+        .expect("Unable to read bitcoind rpc credentials");
     let (anchoring_common, anchoring_nodes) =
         gen_anchoring_testnet_config(&client, BitcoinNetwork::Testnet, count, total_funds);
     let node_cfgs = generate_testnet_config(count, start_port);
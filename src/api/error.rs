@@ -21,12 +21,18 @@ use exonum::api::ApiError;
 #[derive(Debug)]
 pub enum Error {
     UnknownValidatorId(u32),
+    IncorrectDigest(String),
+    IncorrectRequest(String),
+    Bitcoin(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::UnknownValidatorId(id) => write!(f, "Unknown validator id={}", id),
+            Error::IncorrectDigest(ref digest) => write!(f, "Incorrect digest={}", digest),
+            Error::IncorrectRequest(ref msg) => write!(f, "Incorrect request: {}", msg),
+            Error::Bitcoin(ref msg) => write!(f, "Bitcoin error: {}", msg),
         }
     }
 }
@@ -35,6 +41,9 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::UnknownValidatorId(_) => "UnknownValidatorId",
+            Error::IncorrectDigest(_) => "IncorrectDigest",
+            Error::IncorrectRequest(_) => "IncorrectRequest",
+            Error::Bitcoin(_) => "Bitcoin",
         }
     }
 }
@@ -45,6 +54,15 @@ impl Into<ApiError> for Error {
             Error::UnknownValidatorId(id) => {
                 ApiError::Storage(StorageError::new(format!("Unknown validator id={}", id)))
             }
+            Error::IncorrectDigest(digest) => {
+                ApiError::BadRequest(format!("Incorrect digest={}", digest))
+            }
+            Error::IncorrectRequest(msg) => {
+                ApiError::BadRequest(format!("Incorrect request: {}", msg))
+            }
+            Error::Bitcoin(msg) => {
+                ApiError::Storage(StorageError::new(format!("Bitcoin error: {}", msg)))
+            }
         }
     }
 }
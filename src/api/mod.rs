@@ -14,21 +14,37 @@
 
 //! Anchoring rest api implementation.
 
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
 use router::Router;
 use iron::prelude::*;
-use bitcoin::util::base58::ToBase58;
+use bitcoin::blockdata::transaction::SigHashType;
+use serde_json;
 
-use exonum::blockchain::Blockchain;
-use exonum::crypto::Hash;
+use exonum::blockchain::{Blockchain, Schema, Transaction};
+use exonum::crypto::{CryptoHash, Hash, PublicKey, SecretKey};
+use exonum::encoding::serialize::{encode_hex, FromHex};
+use exonum::helpers::Height;
+use exonum::node::{ApiSender, TransactionSend};
 use exonum::api::{Api, ApiError};
 
 use details::btc;
 use details::btc::TxId;
-use details::btc::transactions::{AnchoringTx, BitcoinTx, TxKind};
+use details::btc::transactions::{parse_payload, AnchoringTx, BitcoinTx, TxKind};
+use details::cache::CacheStats;
+use details::ots;
+use details::psbt::Psbt;
+use details::rpc::{AnchoringRpcConfig, RpcConnection};
+use details::spv;
+use blockchain::consensus_storage::AnchoringConfig;
 use blockchain::schema::AnchoringSchema;
-use blockchain::dto::LectContent;
+use blockchain::dto::{LectContent, MsgAnchoringNotarize, MsgAnchoringRequest,
+                      MsgAnchoringSignature};
+use handler::AnchoringHandler;
 
 pub use details::btc::payload::Payload;
+pub use details::mmr::MmrProof;
 
 mod error;
 
@@ -37,6 +53,26 @@ mod error;
 pub struct PublicApi {
     /// Exonum blockchain instance.
     pub blockchain: Blockchain,
+    /// Channel for the transactions submitted via api.
+    pub sender: ApiSender,
+    /// Rpc configuration, which is used to request inclusion proofs from `bitcoind`.
+    pub rpc: Option<AnchoringRpcConfig>,
+}
+
+/// Private api implementation, which allows to sign the anchoring proposal
+/// by the external wallets.
+#[derive(Debug, Clone)]
+pub struct PrivateApi {
+    /// Exonum blockchain instance.
+    pub blockchain: Blockchain,
+    /// Channel for the transactions submitted via api.
+    pub sender: ApiSender,
+    /// Anchoring handler that keeps the current proposal.
+    pub handler: Arc<Mutex<AnchoringHandler>>,
+    /// Service public key of the node.
+    pub public_key: PublicKey,
+    /// Service secret key of the node, which signs the `MsgAnchoringSignature` messages.
+    pub secret_key: SecretKey,
 }
 
 /// Public information about the anchoring transaction in bitcoin.
@@ -57,6 +93,117 @@ pub struct LectInfo {
     pub content: AnchoringInfo,
 }
 
+/// Committed anchoring configuration that has been rejected by the validation.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RejectedConfig {
+    /// Height from which the configuration should have been actual.
+    pub actual_from: Height,
+    /// Hash of the stored configuration.
+    pub cfg_hash: Hash,
+    /// Reason of the rejection.
+    pub reason: String,
+}
+
+/// Proof that the block is covered by the blocks root in the anchoring transaction payload.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct BlockProof {
+    /// Height of the proven block.
+    pub block_height: Height,
+    /// Hash of the proven block.
+    pub block_hash: Hash,
+    /// Anchoring transaction that contains the blocks root.
+    pub anchoring_tx: AnchoringTx,
+    /// Payload of the anchoring transaction.
+    pub payload: Payload,
+    /// Proof of the block hash inclusion into the `payload.blocks_root`.
+    pub proof: MmrProof,
+}
+
+/// Proof that the digest is covered by the notary root in the anchoring transaction payload.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct NotaryProof {
+    /// Notarized digest.
+    pub digest: Hash,
+    /// Height of the block that contains the digest.
+    pub block_height: Height,
+    /// Anchoring transaction that contains the notary root.
+    pub anchoring_tx: AnchoringTx,
+    /// Payload of the anchoring transaction.
+    pub payload: Payload,
+    /// Proof of the digest inclusion into the `payload.notary_root`.
+    pub proof: MmrProof,
+}
+
+/// OpenTimestamps proof of the anchored block.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct OtsTimestamp {
+    /// Height of the anchored block.
+    pub block_height: Height,
+    /// Hash of the anchored block, which is the digest of the timestamp.
+    pub block_hash: Hash,
+    /// `Txid` of the anchoring transaction.
+    pub txid: TxId,
+    /// Height of the bitcoin block that contains the anchoring transaction.
+    pub bitcoin_height: u64,
+    /// Hex representation of the detached `.ots` timestamp file.
+    pub ots: String,
+}
+
+/// Response to the submitted notarization.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct NotarizeResponse {
+    /// Hash of the `MsgAnchoringNotarize` transaction.
+    pub tx_hash: Hash,
+}
+
+/// Classification of the bitcoin transaction by the anchoring service.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DecodedTransaction {
+    /// `Txid` of the transaction.
+    pub txid: TxId,
+    /// Kind of the transaction.
+    pub kind: DecodedTransactionKind,
+    /// Payload of the anchoring transaction.
+    pub payload: Option<Payload>,
+    /// Reason why the transaction payload is not recognized.
+    pub payload_error: Option<String>,
+    /// Whether the payload prefix is the same as in the actual configuration.
+    pub actual_prefix: Option<bool>,
+}
+
+/// Kind of the bitcoin transaction from the anchoring service point of view.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodedTransactionKind {
+    /// Transaction with the anchoring payload.
+    Anchoring,
+    /// Transaction that can fund the anchoring address.
+    Funding,
+    /// Any other transaction.
+    Other,
+}
+
+/// Response to the submitted anchoring request.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnchoringRequestResponse {
+    /// Hash of the `MsgAnchoringRequest` transaction.
+    pub tx_hash: Hash,
+}
+
+/// Partially signed anchoring proposal.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PsbtRequest {
+    /// Base64 representation of the BIP174 partially signed transaction.
+    pub psbt: String,
+}
+
+/// Response to the imported partially signed anchoring proposal.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PsbtImportResponse {
+    /// Hashes of the `MsgAnchoringSignature` transactions made from the signatures.
+    pub tx_hashes: Vec<Hash>,
+}
+
 impl From<BitcoinTx> for AnchoringInfo {
     fn from(tx: BitcoinTx) -> AnchoringInfo {
         match TxKind::from(tx) {
@@ -118,6 +265,13 @@ impl PublicApi {
         Ok(schema.actual_anchoring_config().redeem_script().1)
     }
 
+    /// Returns the actual anchoring address in the format of the anchored chain.
+    pub fn actual_address_string(&self) -> Result<String, ApiError> {
+        let snapshot = self.blockchain.snapshot();
+        let cfg = AnchoringSchema::new(snapshot).actual_anchoring_config();
+        Ok(cfg.redeem_script().1.to_chain_string(&cfg.chain_params()))
+    }
+
     /// Returns the following anchoring address if the node is in a transition state.
     ///
     /// `GET /{api_prefix}/v1/address/following`
@@ -130,6 +284,35 @@ impl PublicApi {
         Ok(following_addr)
     }
 
+    /// Returns the following anchoring address in the format of the anchored chain.
+    pub fn following_address_string(&self) -> Result<Option<String>, ApiError> {
+        let snapshot = self.blockchain.snapshot();
+        let schema = AnchoringSchema::new(snapshot);
+        let following_addr = schema
+            .following_anchoring_config()
+            .map(|cfg| cfg.redeem_script().1.to_chain_string(&cfg.chain_params()));
+        Ok(following_addr)
+    }
+
+    /// Returns the committed configurations that are ignored by the anchoring service,
+    /// because they do not pass the validation.
+    ///
+    /// `GET /{api_prefix}/v1/config/rejected`
+    pub fn rejected_configs(&self) -> Result<Vec<RejectedConfig>, ApiError> {
+        let snapshot = self.blockchain.snapshot();
+        let schema = AnchoringSchema::new(snapshot);
+        let rejected = schema
+            .rejected_anchoring_configs()
+            .into_iter()
+            .map(|(actual_from, cfg_hash, e)| RejectedConfig {
+                actual_from,
+                cfg_hash,
+                reason: e.to_string(),
+            })
+            .collect();
+        Ok(rejected)
+    }
+
     /// Returns hex of the anchoring transaction for the nearest block with a height greater
     /// or equal than the given.
     ///
@@ -147,6 +330,311 @@ impl PublicApi {
         }
         Ok(None)
     }
+
+    /// Returns proof that the block at the given height is covered by the blocks root
+    /// of the nearest anchoring transaction with such payload.
+    ///
+    /// `GET /{api_prefix}/v1/block_proof/:height`
+    pub fn block_proof(&self, height: u64) -> Result<Option<BlockProof>, ApiError> {
+        let snapshot = self.blockchain.snapshot();
+        let anchoring_schema = AnchoringSchema::new(&snapshot);
+        let tx_chain = anchoring_schema.anchoring_tx_chain();
+
+        for (tx_height, tx) in &tx_chain {
+            let payload = tx.payload();
+            if tx_height < height || payload.blocks_root.is_none() {
+                continue;
+            }
+
+            let block_height = Height(height);
+            let anchored_height = payload.block_height;
+            let proof = anchoring_schema.block_proof(block_height, anchored_height);
+            let block_hash = Schema::new(&snapshot).block_hash_by_height(block_height);
+            if let (Some(proof), Some(block_hash)) = (proof, block_hash) {
+                return Ok(Some(BlockProof {
+                    block_height,
+                    block_hash,
+                    anchoring_tx: tx,
+                    payload,
+                    proof,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns proof that the given digest is covered by the notary root
+    /// of the nearest anchoring transaction with such payload.
+    ///
+    /// `GET /{api_prefix}/v1/notary/:digest`
+    pub fn notary_proof(&self, digest: &Hash) -> Result<Option<NotaryProof>, ApiError> {
+        let snapshot = self.blockchain.snapshot();
+        let anchoring_schema = AnchoringSchema::new(&snapshot);
+        let block_height = match anchoring_schema.notary_indexes().get(digest) {
+            Some(index) => Height(anchoring_schema.notary_heights().get(index).unwrap()),
+            None => return Ok(None),
+        };
+
+        let tx_chain = anchoring_schema.anchoring_tx_chain();
+        for (tx_height, tx) in &tx_chain {
+            let payload = tx.payload();
+            if tx_height < block_height.0 || payload.notary_root.is_none() {
+                continue;
+            }
+
+            let proof = anchoring_schema.notary_proof(digest, payload.block_height);
+            if let Some(proof) = proof {
+                return Ok(Some(NotaryProof {
+                    digest: *digest,
+                    block_height,
+                    anchoring_tx: tx,
+                    payload,
+                    proof,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the OpenTimestamps proof of the block anchored at the given height.
+    /// The anchoring transaction should be already confirmed in bitcoin.
+    ///
+    /// `GET /{api_prefix}/v1/ots/:height`
+    pub fn ots_timestamp(&self, height: u64) -> Result<Option<OtsTimestamp>, ApiError> {
+        let tx = {
+            let snapshot = self.blockchain.snapshot();
+            match AnchoringSchema::new(&snapshot).anchoring_tx_chain().get(&height) {
+                Some(tx) => tx,
+                None => return Ok(None),
+            }
+        };
+        let rpc = self.rpc.clone().ok_or_else(|| -> ApiError {
+            error::Error::Bitcoin("Rpc config is not setted".to_owned()).into()
+        })?;
+
+        let payload = tx.payload();
+        let inclusion = spv::tx_inclusion(&RpcConnection::new(rpc), tx.id())
+            .map_err(|e| -> ApiError { error::Error::Bitcoin(e.to_string()).into() })?;
+        Ok(ots::timestamp(&tx, &payload, &inclusion).map(|ots| OtsTimestamp {
+            block_height: payload.block_height,
+            block_hash: payload.block_hash,
+            txid: tx.id(),
+            bitcoin_height: inclusion.block_height,
+            ots: encode_hex(ots),
+        }))
+    }
+
+    /// Explains how the service classifies the bitcoin transaction with the given hex.
+    ///
+    /// `GET /{api_prefix}/v1/decode_transaction/:hex`
+    pub fn decode_transaction(&self, hex: &str) -> Result<DecodedTransaction, ApiError> {
+        let tx = BitcoinTx::from_hex(hex).map_err(|e| -> ApiError {
+            error::Error::IncorrectRequest(e.to_string()).into()
+        })?;
+        let payload = parse_payload(&tx);
+        let actual_prefix = payload.as_ref().ok().map(|payload| {
+            let snapshot = self.blockchain.snapshot();
+            let schema = AnchoringSchema::new(snapshot);
+            schema.actual_anchoring_config().payload_prefix == payload.prefix
+        });
+        let kind = match TxKind::from(tx.clone()) {
+            TxKind::Anchoring(_) => DecodedTransactionKind::Anchoring,
+            TxKind::FundingTx(_) => DecodedTransactionKind::Funding,
+            TxKind::Other(_) => DecodedTransactionKind::Other,
+        };
+        Ok(DecodedTransaction {
+            txid: tx.id(),
+            kind,
+            payload_error: payload.as_ref().err().map(ToString::to_string),
+            payload: payload.ok(),
+            actual_prefix,
+        })
+    }
+
+    /// Submits the signed notarization transaction.
+    ///
+    /// `POST /{api_prefix}/v1/notary`
+    pub fn notarize(&self, tx: MsgAnchoringNotarize) -> Result<NotarizeResponse, ApiError> {
+        if !tx.verify() {
+            let msg = "Incorrect notarization signature".to_owned();
+            return Err(error::Error::IncorrectRequest(msg).into());
+        }
+        tx.validate(&self.blockchain.fork()).map_err(|e| -> ApiError {
+            error::Error::IncorrectRequest(e.to_string()).into()
+        })?;
+        let tx_hash = tx.hash();
+        self.sender.send(Box::new(tx))?;
+        Ok(NotarizeResponse { tx_hash })
+    }
+
+    /// Submits the signed request for the out-of-schedule anchoring.
+    ///
+    /// `POST /{api_prefix}/v1/request`
+    pub fn request_anchoring(
+        &self,
+        tx: MsgAnchoringRequest,
+    ) -> Result<AnchoringRequestResponse, ApiError> {
+        let tx_hash = tx.hash();
+        self.sender.send(Box::new(tx))?;
+        Ok(AnchoringRequestResponse { tx_hash })
+    }
+}
+
+impl PrivateApi {
+    /// Returns the current anchoring proposal as the BIP174 partially signed transaction
+    /// with the redeem scripts and the spent transactions, if there is one. The proposal
+    /// can be signed by any wallet that holds the anchoring key of the node.
+    ///
+    /// `GET /{api_prefix}/v1/psbt/proposal`
+    pub fn proposal_psbt(&self) -> Result<Option<String>, ApiError> {
+        let proposal = self.handler.lock().unwrap().proposal_tx.clone();
+        let proposal = match proposal {
+            Some(proposal) => proposal,
+            None => return Ok(None),
+        };
+
+        let snapshot = self.blockchain.snapshot();
+        let schema = AnchoringSchema::new(snapshot);
+        let spent_txs = schema.spent_txs(&proposal).ok_or_else(|| -> ApiError {
+            error::Error::IncorrectRequest("Unknown transaction spent by proposal".to_owned())
+                .into()
+        })?;
+        let configs = schema
+            .previous_anchoring_config()
+            .into_iter()
+            .chain(Some(schema.actual_anchoring_config()))
+            .chain(schema.following_anchoring_config())
+            .collect::<Vec<_>>();
+
+        let mut psbt = Psbt::from_unsigned_tx(proposal.0.clone());
+        for (input, (txin, spent_tx)) in psbt.inputs
+            .iter_mut()
+            .zip(proposal.0.input.iter().zip(spent_txs))
+        {
+            let spent_output = spent_tx.0.output[txin.prev_index as usize].clone();
+            if let Some(cfg) = find_config(&configs, &spent_output.script_pubkey) {
+                let (redeem_script, _) = cfg.redeem_script();
+                if redeem_script.is_witness_script() {
+                    input.redeem_script = Some(redeem_script.witness_program());
+                    input.witness_script = Some(redeem_script);
+                    input.witness_utxo = Some(spent_output);
+                } else {
+                    input.redeem_script = Some(redeem_script);
+                }
+            }
+            input.non_witness_utxo = Some(spent_tx.0);
+            input.sighash_type = Some(SigHashType::All.as_u32());
+        }
+        for (output, txout) in psbt.outputs.iter_mut().zip(&proposal.0.output) {
+            if let Some(cfg) = find_config(&configs, &txout.script_pubkey) {
+                let (redeem_script, _) = cfg.redeem_script();
+                if redeem_script.is_witness_script() {
+                    output.redeem_script = Some(redeem_script.witness_program());
+                    output.witness_script = Some(redeem_script);
+                } else {
+                    output.redeem_script = Some(redeem_script);
+                }
+            }
+        }
+        Ok(Some(psbt.to_base64()))
+    }
+
+    /// Imports the signatures of the anchoring key of the node from the partially signed
+    /// proposal and submits them as the `MsgAnchoringSignature` transactions.
+    ///
+    /// `POST /{api_prefix}/v1/psbt/signatures`
+    pub fn import_psbt(&self, request: &PsbtRequest) -> Result<PsbtImportResponse, ApiError> {
+        let incorrect_request = |msg: String| -> ApiError {
+            error::Error::IncorrectRequest(msg).into()
+        };
+
+        let psbt = Psbt::from_base64(&request.psbt).map_err(|e| incorrect_request(e.to_string()))?;
+        let proposal = self.handler
+            .lock()
+            .unwrap()
+            .proposal_tx
+            .clone()
+            .ok_or_else(|| incorrect_request("No anchoring proposal".to_owned()))?;
+        if psbt.unsigned_tx.txid() != proposal.0.txid() {
+            return Err(incorrect_request(
+                "PSBT does not match the anchoring proposal".to_owned(),
+            ));
+        }
+
+        let snapshot = self.blockchain.snapshot();
+        let validators = Schema::new(&snapshot).actual_configuration().validator_keys;
+        let schema = AnchoringSchema::new(&snapshot);
+        let cfg = schema.actual_anchoring_config();
+        let signer_id = cfg.signer_id(&self.public_key, &validators)
+            .ok_or_else(|| incorrect_request("The node is not an anchoring signer".to_owned()))?;
+        let anchoring_key = &cfg.anchoring_keys[signer_id.0 as usize];
+        let (redeem_script, _) = cfg.redeem_script();
+        let spent_values = if redeem_script.is_witness_script() {
+            let values = schema.spent_values(&proposal).ok_or_else(|| {
+                incorrect_request("Unknown transaction spent by proposal".to_owned())
+            })?;
+            Some(values)
+        } else {
+            None
+        };
+
+        let mut tx_hashes = Vec::new();
+        for input in proposal.inputs() {
+            let signature = match psbt.partial_sig(input as usize, anchoring_key) {
+                Some(signature) => signature,
+                None => continue,
+            };
+            let is_valid = if signature.last() != Some(&(SigHashType::All.as_u32() as u8)) {
+                false
+            } else if let Some(ref values) = spent_values {
+                proposal.verify_witness_input(
+                    &redeem_script,
+                    input,
+                    values[input as usize],
+                    anchoring_key,
+                    signature,
+                )
+            } else {
+                proposal.verify_input(&redeem_script, input, anchoring_key, signature)
+            };
+            if !is_valid {
+                return Err(incorrect_request(
+                    format!("Incorrect signature for input {}", input),
+                ));
+            }
+
+            let sign_msg = MsgAnchoringSignature::new(
+                &self.public_key,
+                signer_id,
+                proposal.clone(),
+                input,
+                signature,
+                &self.secret_key,
+            );
+            tx_hashes.push(sign_msg.hash());
+            self.sender.send(Box::new(sign_msg))?;
+        }
+        Ok(PsbtImportResponse { tx_hashes })
+    }
+
+    /// Returns the hits and misses statistics of the bitcoin relay cache
+    /// if the cache is enabled.
+    ///
+    /// `GET /{api_prefix}/v1/cache_stats`
+    pub fn cache_stats(&self) -> Result<Option<CacheStats>, ApiError> {
+        let handler = self.handler.lock().unwrap();
+        Ok(handler.client.as_ref().and_then(|client| client.cache_stats()))
+    }
+}
+
+// Returns the configuration which address has the given `script_pubkey`.
+fn find_config<'a>(
+    configs: &'a [AnchoringConfig],
+    script_pubkey: &::bitcoin::blockdata::script::Script,
+) -> Option<&'a AnchoringConfig> {
+    configs
+        .iter()
+        .find(|cfg| cfg.redeem_script().1.script_pubkey() == *script_pubkey)
 }
 
 impl Api for PublicApi {
@@ -166,13 +654,13 @@ impl Api for PublicApi {
 
         let api = self.clone();
         let actual_address = move |_: &mut Request| -> IronResult<Response> {
-            let addr = api.actual_address()?.to_base58check();
+            let addr = api.actual_address_string()?;
             api.ok_response(&json!(addr))
         };
 
         let api = self.clone();
         let following_address = move |_: &mut Request| -> IronResult<Response> {
-            let addr = api.following_address()?.map(|addr| addr.to_base58check());
+            let addr = api.following_address_string()?;
             api.ok_response(&json!(addr))
         };
 
@@ -183,6 +671,65 @@ impl Api for PublicApi {
             api.ok_response(&json!(lect))
         };
 
+        let api = self.clone();
+        let rejected_configs = move |_: &mut Request| -> IronResult<Response> {
+            let rejected = api.rejected_configs()?;
+            api.ok_response(&json!(rejected))
+        };
+
+        let api = self.clone();
+        let block_proof = move |req: &mut Request| -> IronResult<Response> {
+            let height = api.url_fragment(req, "height")?;
+            let proof = api.block_proof(height)?;
+            api.ok_response(&json!(proof))
+        };
+
+        let api = self.clone();
+        let ots_timestamp = move |req: &mut Request| -> IronResult<Response> {
+            let height = api.url_fragment(req, "height")?;
+            let timestamp = api.ots_timestamp(height)?;
+            api.ok_response(&json!(timestamp))
+        };
+
+        let api = self.clone();
+        let notary_proof = move |req: &mut Request| -> IronResult<Response> {
+            let digest: String = api.url_fragment(req, "digest")?;
+            let digest = Hash::from_hex(&digest).map_err(|_| -> ApiError {
+                error::Error::IncorrectDigest(digest.clone()).into()
+            })?;
+            let proof = api.notary_proof(&digest)?;
+            api.ok_response(&json!(proof))
+        };
+
+        let api = self.clone();
+        let decode_transaction = move |req: &mut Request| -> IronResult<Response> {
+            let hex: String = api.url_fragment(req, "hex")?;
+            let decoded = api.decode_transaction(&hex)?;
+            api.ok_response(&json!(decoded))
+        };
+
+        let api = self.clone();
+        let notarize = move |req: &mut Request| -> IronResult<Response> {
+            let mut body = String::new();
+            req.body.read_to_string(&mut body).map_err(ApiError::from)?;
+            let tx: MsgAnchoringNotarize = serde_json::from_str(&body).map_err(|e| -> ApiError {
+                error::Error::IncorrectRequest(e.to_string()).into()
+            })?;
+            let response = api.notarize(tx)?;
+            api.ok_response(&json!(response))
+        };
+
+        let api = self.clone();
+        let request_anchoring = move |req: &mut Request| -> IronResult<Response> {
+            let mut body = String::new();
+            req.body.read_to_string(&mut body).map_err(ApiError::from)?;
+            let tx: MsgAnchoringRequest = serde_json::from_str(&body).map_err(|e| -> ApiError {
+                error::Error::IncorrectRequest(e.to_string()).into()
+            })?;
+            let response = api.request_anchoring(tx)?;
+            api.ok_response(&json!(response))
+        };
+
         router.get("/v1/address/actual", actual_address, "actual_address");
         router.get(
             "/v1/address/following",
@@ -196,5 +743,47 @@ impl Api for PublicApi {
             "current_lect_of_validator",
         );
         router.get("/v1/nearest_lect/:height", nearest_lect, "nearest_lect");
+        router.get("/v1/config/rejected", rejected_configs, "rejected_configs");
+        router.get("/v1/block_proof/:height", block_proof, "block_proof");
+        router.get("/v1/ots/:height", ots_timestamp, "ots_timestamp");
+        router.get("/v1/notary/:digest", notary_proof, "notary_proof");
+        router.get(
+            "/v1/decode_transaction/:hex",
+            decode_transaction,
+            "decode_transaction",
+        );
+        router.post("/v1/notary", notarize, "notarize");
+        router.post("/v1/request", request_anchoring, "request_anchoring");
+    }
+}
+
+impl Api for PrivateApi {
+    fn wire(&self, router: &mut Router) {
+        let api = self.clone();
+        let proposal_psbt = move |_: &mut Request| -> IronResult<Response> {
+            let psbt = api.proposal_psbt()?;
+            api.ok_response(&json!(psbt))
+        };
+
+        let api = self.clone();
+        let import_psbt = move |req: &mut Request| -> IronResult<Response> {
+            let mut body = String::new();
+            req.body.read_to_string(&mut body).map_err(ApiError::from)?;
+            let request: PsbtRequest = serde_json::from_str(&body).map_err(|e| -> ApiError {
+                error::Error::IncorrectRequest(e.to_string()).into()
+            })?;
+            let response = api.import_psbt(&request)?;
+            api.ok_response(&json!(response))
+        };
+
+        let api = self.clone();
+        let cache_stats = move |_: &mut Request| -> IronResult<Response> {
+            let stats = api.cache_stats()?;
+            api.ok_response(&json!(stats))
+        };
+
+        router.get("/v1/psbt/proposal", proposal_psbt, "proposal_psbt");
+        router.post("/v1/psbt/signatures", import_psbt, "import_psbt");
+        router.get("/v1/cache_stats", cache_stats, "cache_stats");
     }
 }
@@ -17,18 +17,56 @@ use std::borrow::Cow;
 use serde_json;
 use serde::{Deserialize, Deserializer};
 
+use exonum::blockchain::ValidatorKeys;
 use exonum::storage::StorageValue;
-use exonum::crypto::{hash, CryptoHash, Hash};
-use exonum::helpers::Height;
+use exonum::crypto::{hash, CryptoHash, Hash, PublicKey};
+use exonum::helpers::{Height, ValidatorId};
 
 use details::btc;
+use details::btc::chain::{ChainParams, KNOWN_CHAINS};
+use details::btc::payload::{self, DEFAULT_PAYLOAD_PREFIX};
 use details::btc::transactions::FundingTx;
 
+const DEFAULT_PAYLOAD_VERSION: u8 = 1;
+const DEFAULT_REQUEST_INTERVAL: u64 = 100;
+/// Consensus time in seconds after which the next validator marks the time interval
+/// if it is still not marked.
+pub const TIME_MARK_TIMEOUT: u64 = 30;
+/// The maximum number of the keys in the standard `OP_CHECKMULTISIG` redeem script.
+pub const MAX_MULTISIG_KEYS: usize = 15;
+/// The maximum size of the P2SH redeem script in bytes.
+pub const MAX_REDEEM_SCRIPT_SIZE: usize = 520;
+/// The maximum number of the keys in the chained `OP_CHECKSIG` witness script, which is
+/// limited by 201 non-push operations of the script.
+pub const MAX_CHECKSIG_KEYS: usize = 67;
+
 /// Public part of anchoring service configuration stored in blockchain.
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct AnchoringConfig {
     /// Validators' public keys from which the current anchoring address can be calculated.
     pub anchoring_keys: Vec<btc::PublicKey>,
+    /// Holders of the anchoring keys, which are used instead of the validators if the list
+    /// is not empty. In this case the `anchoring_keys` must be the keys of the signers
+    /// in the same order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signers: Vec<AnchoringSigner>,
+    /// The script of the anchoring address.
+    #[serde(default, skip_serializing_if = "AnchoringScript::is_default")]
+    pub script: AnchoringScript,
+    /// The time-locked recovery path of the anchoring address, which is supported only
+    /// by the `multisig` script.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<AnchoringRecovery>,
+    /// Sort the anchoring keys in the redeem script lexicographically as specified by BIP67
+    /// instead of the validators order. In this mode the anchoring address does not change
+    /// if the validators are reordered.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub sort_keys: bool,
+    /// The number of signatures that is required to spend the anchoring address and
+    /// the number of validators that must agree on the lect, the `2/3 + 1` of the
+    /// `anchoring_keys` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u8>,
     /// The transaction that funds anchoring address.
     /// If the anchoring transactions chain is empty, it will be the first transaction in the chain.
     /// Note: you must specify a suitable transaction before the network launching.
@@ -38,23 +76,265 @@ pub struct AnchoringConfig {
     /// The frequency in blocks with which the generation of new anchoring
     /// transactions in the chain occurs.
     pub frequency: u64,
+    /// The schedule of the anchoring transactions generation.
+    #[serde(default, skip_serializing_if = "AnchoringSchedule::is_default")]
+    pub schedule: AnchoringSchedule,
     /// The minimum number of confirmations in bitcoin network for the transition to a
     /// new anchoring address.
     pub utxo_confirmations: u64,
     /// The current bitcoin network type.
     #[serde(serialize_with = "btc_network_to_str", deserialize_with = "btc_network_from_str")]
     pub network: btc::Network,
+    /// Name of the bitcoin-like chain that is used for anchoring, the bitcoin chain of
+    /// the `network` by default. The `network` must be the same as the network of the chain.
+    #[serde(default, skip_serializing_if = "Option::is_none",
+            deserialize_with = "chain_from_str")]
+    pub chain: Option<String>,
+    /// Prefix of the anchoring transactions payload, which distinguishes the anchoring
+    /// transactions of the different deployments. It must consist of 1 to 6 ASCII
+    /// alphanumeric characters.
+    #[serde(default = "payload::default_payload_prefix",
+            skip_serializing_if = "is_default_payload_prefix",
+            deserialize_with = "payload_prefix_from_str")]
+    pub payload_prefix: String,
+    /// Version of the anchoring transactions payload, the supported versions are 1 and 2.
+    /// The payload v.2 additionally commits to the block state hash and the network identifier.
+    #[serde(default = "default_payload_version",
+            skip_serializing_if = "is_default_payload_version")]
+    pub payload_version: u8,
+    /// Data that the payload v.2 commits to in addition to the block hash.
+    #[serde(default, skip_serializing_if = "PayloadCommitment::is_default")]
+    pub payload_commitment: PayloadCommitment,
+    /// Anchor the height at which a new configuration takes effect
+    /// in addition to the multiples of the `frequency`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub anchor_config_changes: bool,
+    /// Anchor the heights flagged by other services in addition to the multiples
+    /// of the `frequency`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub anchor_flagged_heights: bool,
+    /// Keys that are allowed to request the out-of-schedule anchoring
+    /// in addition to the validators' service keys.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub anchoring_requesters: Vec<PublicKey>,
+    /// The minimum interval in blocks between the accepted anchoring requests.
+    #[serde(default = "default_request_interval",
+            skip_serializing_if = "is_default_request_interval")]
+    pub request_interval: u64,
+    /// Total fee in satoshis that may be spent on the requested anchoring transactions
+    /// while this configuration is actual. Each accepted request takes the `fee`
+    /// from the budget, zero disables the anchoring requests.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub request_fee_budget: u64,
+    /// Keys that are allowed to notarize digests in addition to the validators' service keys.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notary_submitters: Vec<PublicKey>,
+}
+
+/// The script of the anchoring address.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchoringScript {
+    /// P2SH address with the `OP_CHECKMULTISIG` redeem script, which allows at most
+    /// 15 anchoring keys.
+    Multisig,
+    /// P2SH-wrapped P2WSH address with the witness script of the chained `OP_CHECKSIG`
+    /// operations, which allows up to 67 anchoring keys.
+    ChainedChecksig,
+}
+
+impl Default for AnchoringScript {
+    fn default() -> AnchoringScript {
+        AnchoringScript::Multisig
+    }
+}
+
+impl AnchoringScript {
+    fn is_default(&self) -> bool {
+        *self == AnchoringScript::default()
+    }
+
+    /// Returns the maximum number of the anchoring keys that allows to produce
+    /// the standard transactions.
+    pub fn max_keys(&self) -> usize {
+        match *self {
+            AnchoringScript::Multisig => MAX_MULTISIG_KEYS,
+            AnchoringScript::ChainedChecksig => MAX_CHECKSIG_KEYS,
+        }
+    }
+}
+
+/// The time-locked recovery path of the anchoring address, which allows to sweep the funds
+/// if the anchoring keys have been lost.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AnchoringRecovery {
+    /// Bitcoin public keys of the recovery key holders.
+    pub keys: Vec<btc::PublicKey>,
+    /// The number of the recovery signatures that is required to spend the anchoring output.
+    pub threshold: u8,
+    /// The number of bitcoin blocks since the confirmation of the anchoring output after
+    /// which it can be spent by the recovery keys.
+    pub timelock: u16,
+}
+
+impl AnchoringRecovery {
+    /// Returns the number of confirmations of the latest anchoring transaction after which
+    /// the service anchors the latest anchored height once again, so the anchoring output
+    /// is refreshed well before the timelock expires.
+    pub fn refresh_confirmations(&self) -> u64 {
+        u64::from(self.timelock / 2)
+    }
+}
+
+/// Holder of the anchoring key, which may be a validator or a dedicated
+/// non-consensus node.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AnchoringSigner {
+    /// Service public key that signs the anchoring messages of the signer.
+    pub service_key: PublicKey,
+    /// Bitcoin public key of the signer.
+    pub anchoring_key: btc::PublicKey,
+}
+
+/// Reason why the anchoring configuration is incorrect.
+#[derive(Debug, Display, Fail, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The list of the anchoring keys is empty.
+    #[display(fmt = "Anchoring keys are not specified")]
+    NoAnchoringKeys,
+    /// The number of the anchoring keys exceeds the limit of the anchoring script.
+    #[display(fmt = "Too many anchoring keys {}, the anchoring script allows at most {}", _0, _1)]
+    TooManyKeys(usize, usize),
+    /// The threshold is zero or exceeds the number of the anchoring keys.
+    #[display(fmt = "Incorrect threshold {} for {} anchoring keys", _0, _1)]
+    IncorrectThreshold(u8, usize),
+    /// The redeem script exceeds the P2SH limit of 520 bytes.
+    #[display(fmt = "Redeem script of {} bytes exceeds the P2SH limit of 520 bytes", _0)]
+    ScriptTooLarge(usize),
+    /// The recovery path is incorrect.
+    #[display(fmt = "Incorrect recovery path: {}", _0)]
+    IncorrectRecovery(String),
+    /// The `anchoring_keys` are not the keys of the `signers`.
+    #[display(fmt = "Anchoring keys do not match the keys of the signers")]
+    SignersMismatch,
+    /// The payload version is not supported by this service.
+    #[display(fmt = "Unsupported payload version {}", _0)]
+    UnsupportedPayloadVersion(u8),
+    /// The payload commitment other than the state hash requires the payload v.2.
+    #[display(fmt = "Payload commitment {:?} requires the payload v.2", _0)]
+    CommitmentRequiresPayloadV2(PayloadCommitment),
+    /// The anchoring section of the stored configuration can not be deserialized.
+    #[display(fmt = "Malformed anchoring config: {}", _0)]
+    Malformed(String),
+}
+
+/// Data that the payload v.2 commits to in addition to the block hash.
+///
+/// The payload has room for a single commitment, so the blocks root and the notary root
+/// can not be anchored at the same time.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadCommitment {
+    /// State hash of the anchored block.
+    StateHash,
+    /// Root of the Merkle mountain range of all block hashes up to the anchored height.
+    BlocksRoot,
+    /// Root of the Merkle mountain range of all notarized digests up to the anchored height.
+    NotaryRoot,
+}
+
+impl Default for PayloadCommitment {
+    fn default() -> PayloadCommitment {
+        PayloadCommitment::StateHash
+    }
+}
+
+impl PayloadCommitment {
+    fn is_default(&self) -> bool {
+        *self == PayloadCommitment::default()
+    }
+}
+
+/// The schedule of the anchoring transactions generation.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchoringSchedule {
+    /// Anchor the multiples of the `frequency`.
+    Blocks,
+    /// Anchor the first block after each `interval` seconds of the consensus time
+    /// provided by the `exonum-time` service instead of the multiples of the `frequency`.
+    Time {
+        /// Interval between the anchored blocks in seconds.
+        interval: u64,
+    },
+    /// Anchor the multiples of the `frequency`, but at most once per bitcoin block.
+    /// The next anchoring transaction is proposed only after the previous one
+    /// has been confirmed.
+    BitcoinBlocks,
+}
+
+impl Default for AnchoringSchedule {
+    fn default() -> AnchoringSchedule {
+        AnchoringSchedule::Blocks
+    }
+}
+
+impl AnchoringSchedule {
+    fn is_default(&self) -> bool {
+        *self == AnchoringSchedule::default()
+    }
+
+    /// Returns the number of the time interval that contains the given consensus `time`
+    /// in seconds or `None` if the schedule is not based on time or the interval is zero.
+    pub fn time_epoch(&self, time: u64) -> Option<u64> {
+        match *self {
+            AnchoringSchedule::Time { interval } => time.checked_div(interval),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of the turn to mark the time interval that contains the given
+    /// consensus `time` and the index of the validator whose turn it is. The validators
+    /// take turns every `TIME_MARK_TIMEOUT` seconds, starting from the validator with
+    /// the index equal to the interval number modulo the `validators_count`.
+    pub fn time_mark_turn(&self, time: u64, validators_count: usize) -> Option<(u64, ValidatorId)> {
+        let interval = match *self {
+            AnchoringSchedule::Time { interval } if interval > 0 && validators_count > 0 => {
+                interval
+            }
+            _ => return None,
+        };
+        let turn = time % interval / TIME_MARK_TIMEOUT;
+        let validator = (time / interval + turn) % validators_count as u64;
+        Some((turn, ValidatorId(validator as u16)))
+    }
 }
 
 impl Default for AnchoringConfig {
     fn default() -> AnchoringConfig {
         AnchoringConfig {
             anchoring_keys: vec![],
+            signers: vec![],
+            script: AnchoringScript::default(),
+            recovery: None,
+            sort_keys: false,
+            threshold: None,
             funding_tx: None,
             fee: 1000,
             frequency: 500,
+            schedule: AnchoringSchedule::default(),
             utxo_confirmations: 5,
             network: btc::Network::Testnet,
+            chain: None,
+            payload_prefix: DEFAULT_PAYLOAD_PREFIX.to_owned(),
+            payload_version: DEFAULT_PAYLOAD_VERSION,
+            payload_commitment: PayloadCommitment::default(),
+            anchor_config_changes: false,
+            anchor_flagged_heights: false,
+            anchoring_requesters: vec![],
+            request_interval: DEFAULT_REQUEST_INTERVAL,
+            request_fee_budget: 0,
+            notary_submitters: vec![],
         }
     }
 }
@@ -76,6 +356,21 @@ impl AnchoringConfig {
         }
     }
 
+    /// Creates anchoring configuration in which the anchoring keys are held by
+    /// the given `signers` instead of the validators.
+    pub fn new_with_signers<I>(network: btc::Network, signers: I) -> AnchoringConfig
+    where
+        I: IntoIterator<Item = AnchoringSigner>,
+    {
+        let signers = signers.into_iter().collect::<Vec<_>>();
+        AnchoringConfig {
+            anchoring_keys: signers.iter().map(|signer| signer.anchoring_key).collect(),
+            signers,
+            network,
+            ..Default::default()
+        }
+    }
+
     /// Creates default anchoring configuration from given public keys and funding transaction
     /// which were created earlier by other way.
     pub fn new_with_funding_tx<I>(
@@ -95,25 +390,178 @@ impl AnchoringConfig {
     }
 
     #[doc(hidden)]
-    /// Creates compressed `RedeemScript` from public keys in config.
+    /// Creates compressed `RedeemScript` from public keys in config. For the chained
+    /// `OP_CHECKSIG` script it is the witness script of the P2SH-wrapped P2WSH address.
     pub fn redeem_script(&self) -> (btc::RedeemScript, btc::Address) {
         let majority_count = self.majority_count();
-        let redeem_script =
-            btc::RedeemScript::from_pubkeys(self.anchoring_keys.iter(), majority_count)
-                .compressed(self.network);
-        let addr = btc::Address::from_script(&redeem_script, self.network);
-        (redeem_script, addr)
+        let mut keys = self.anchoring_keys.iter().collect::<Vec<_>>();
+        if self.sort_keys {
+            keys.sort_by(|a, b| a.serialize()[..].cmp(&b.serialize()[..]));
+        }
+        match self.script {
+            AnchoringScript::Multisig => {
+                let redeem_script = if let Some(ref recovery) = self.recovery {
+                    btc::RedeemScript::from_pubkeys_with_recovery(
+                        keys,
+                        majority_count,
+                        &recovery.keys,
+                        recovery.threshold,
+                        recovery.timelock,
+                    )
+                } else {
+                    btc::RedeemScript::from_pubkeys(keys, majority_count)
+                };
+                let redeem_script = redeem_script.compressed(self.network);
+                let addr = btc::Address::from_script(&redeem_script, self.network);
+                (redeem_script, addr)
+            }
+            AnchoringScript::ChainedChecksig => {
+                let witness_script =
+                    btc::RedeemScript::chained_checksig_from_pubkeys(keys, majority_count);
+                let addr =
+                    btc::Address::from_script(&witness_script.witness_program(), self.network);
+                (witness_script, addr)
+            }
+        }
+    }
+
+    /// Returns the position of the key of the validator with the given index
+    /// in the redeem script. The signatures in the anchoring transaction inputs
+    /// must be ordered by these positions.
+    pub fn script_key_position(&self, validator: usize) -> Option<usize> {
+        let key = self.anchoring_keys.get(validator)?;
+        if !self.sort_keys {
+            return Some(validator);
+        }
+        let key = key.serialize();
+        let position = self.anchoring_keys
+            .iter()
+            .enumerate()
+            .filter(|&(idx, other)| {
+                let other = other.serialize();
+                other[..] < key[..] || (other[..] == key[..] && idx < validator)
+            })
+            .count();
+        Some(position)
     }
 
     #[doc(hidden)]
-    /// Returns the latest height below the given `height` which needs to be anchored.
+    /// Returns the latest multiple of the `frequency` below the given `height`.
+    /// See `AnchoringSchema::latest_anchoring_height` for the complete anchoring rule.
     pub fn latest_anchoring_height(&self, height: Height) -> Height {
         Height(height.0 - height.0 % self.frequency as u64)
     }
 
+    /// Returns the parameters of the chain that is used for anchoring.
+    pub fn chain_params(&self) -> ChainParams {
+        self.chain
+            .as_ref()
+            .and_then(|name| ChainParams::by_name(name))
+            .unwrap_or_else(|| ChainParams::bitcoin(self.network))
+    }
+
     #[doc(hidden)]
+    /// Returns the `threshold` if it is specified or the `2/3 + 1` of the `anchoring_keys`.
     pub fn majority_count(&self) -> u8 {
-        ::majority_count(self.anchoring_keys.len() as u8)
+        self.threshold
+            .unwrap_or_else(|| ::majority_count(self.anchoring_keys.len() as u8))
+    }
+
+    /// Checks that the anchoring address can be built from this configuration.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let keys_count = self.anchoring_keys.len();
+        if keys_count == 0 {
+            return Err(ConfigError::NoAnchoringKeys);
+        }
+        let max_keys = self.script.max_keys();
+        if keys_count > max_keys {
+            return Err(ConfigError::TooManyKeys(keys_count, max_keys));
+        }
+        // The redeem script is built with the threshold, so it is checked in advance.
+        if let Some(threshold) = self.threshold {
+            if threshold == 0 || threshold as usize > keys_count {
+                return Err(ConfigError::IncorrectThreshold(threshold, keys_count));
+            }
+        }
+        if let Some(ref recovery) = self.recovery {
+            if self.script != AnchoringScript::Multisig {
+                let reason = "it is supported only by the multisig script";
+                return Err(ConfigError::IncorrectRecovery(reason.to_owned()));
+            }
+            if recovery.threshold == 0 || recovery.threshold as usize > recovery.keys.len() {
+                let reason = format!(
+                    "threshold {} for {} recovery keys",
+                    recovery.threshold,
+                    recovery.keys.len()
+                );
+                return Err(ConfigError::IncorrectRecovery(reason));
+            }
+            if recovery.timelock == 0 {
+                let reason = "timelock must be positive";
+                return Err(ConfigError::IncorrectRecovery(reason.to_owned()));
+            }
+        }
+        if self.script == AnchoringScript::Multisig {
+            let (redeem_script, _) = self.redeem_script();
+            let script_len = redeem_script.0.into_vec().len();
+            if script_len > MAX_REDEEM_SCRIPT_SIZE {
+                return Err(ConfigError::ScriptTooLarge(script_len));
+            }
+        }
+        if !self.signers.is_empty() {
+            let signer_keys = self.signers.iter().map(|signer| &signer.anchoring_key);
+            if !signer_keys.eq(self.anchoring_keys.iter()) {
+                return Err(ConfigError::SignersMismatch);
+            }
+        }
+        if !payload::is_supported_version(self.payload_version) {
+            return Err(ConfigError::UnsupportedPayloadVersion(self.payload_version));
+        }
+        // The payload v.1 does not contain the commitment, so the roots would be dropped.
+        let commitment = self.payload_commitment;
+        if self.payload_version != 2 && commitment != PayloadCommitment::StateHash {
+            return Err(ConfigError::CommitmentRequiresPayloadV2(commitment));
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the signer with the given `service_key` in the `anchoring_keys`.
+    /// If the `signers` list is empty, the validators with the given `validator_keys`
+    /// are the signers.
+    pub fn signer_id(
+        &self,
+        service_key: &PublicKey,
+        validator_keys: &[ValidatorKeys],
+    ) -> Option<ValidatorId> {
+        let position = if self.signers.is_empty() {
+            validator_keys
+                .iter()
+                .take(self.anchoring_keys.len())
+                .position(|keys| keys.service_key == *service_key)
+        } else {
+            self.signers
+                .iter()
+                .position(|signer| signer.service_key == *service_key)
+        };
+        position.map(|id| ValidatorId(id as u16))
+    }
+
+    /// Returns the service key of the signer with the given index in the `anchoring_keys`.
+    /// If the `signers` list is empty, the validators with the given `validator_keys`
+    /// are the signers.
+    pub fn signer_service_key(
+        &self,
+        id: ValidatorId,
+        validator_keys: &[ValidatorKeys],
+    ) -> Option<PublicKey> {
+        let id = id.0 as usize;
+        if !self.signers.is_empty() {
+            self.signers.get(id).map(|signer| signer.service_key)
+        } else if id < self.anchoring_keys.len() {
+            validator_keys.get(id).map(|keys| keys.service_key)
+        } else {
+            None
+        }
     }
 
     /// Returns the funding transaction.
@@ -129,7 +577,67 @@ impl AnchoringConfig {
     }
 }
 
-fn btc_network_to_str<S>(network: &btc::Network, ser: S) -> Result<S::Ok, S::Error>
+fn is_default_payload_prefix(prefix: &str) -> bool {
+    prefix == DEFAULT_PAYLOAD_PREFIX
+}
+
+fn payload_prefix_from_str<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let prefix: String = Deserialize::deserialize(deserializer)?;
+    if payload::is_valid_prefix(prefix.as_bytes()) {
+        Ok(prefix)
+    } else {
+        Err(::serde::de::Error::custom(format!(
+            "Payload prefix `{}` must consist of 1 to {} ASCII alphanumeric characters",
+            prefix,
+            payload::MAX_PAYLOAD_PREFIX_LEN
+        )))
+    }
+}
+
+fn chain_from_str<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name: String = Deserialize::deserialize(deserializer)?;
+    if ChainParams::by_name(&name).is_some() {
+        Ok(Some(name))
+    } else {
+        let variants = KNOWN_CHAINS.iter().map(|chain| chain.name).collect::<Vec<_>>();
+        Err(::serde::de::Error::custom(format!(
+            "Unknown chain `{}`, expected one of {:?}",
+            name, variants
+        )))
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn default_request_interval() -> u64 {
+    DEFAULT_REQUEST_INTERVAL
+}
+
+fn is_default_request_interval(interval: &u64) -> bool {
+    *interval == DEFAULT_REQUEST_INTERVAL
+}
+
+fn default_payload_version() -> u8 {
+    DEFAULT_PAYLOAD_VERSION
+}
+
+fn is_default_payload_version(version: &u8) -> bool {
+    *version == DEFAULT_PAYLOAD_VERSION
+}
+
+pub(crate) fn btc_network_to_str<S>(network: &btc::Network, ser: S) -> Result<S::Ok, S::Error>
 where
     S: ::serde::Serializer,
 {
@@ -139,7 +647,7 @@ where
     }
 }
 
-fn btc_network_from_str<'de, D>(deserializer: D) -> Result<btc::Network, D::Error>
+pub(crate) fn btc_network_from_str<'de, D>(deserializer: D) -> Result<btc::Network, D::Error>
 where
     D: Deserializer<'de>,
 {
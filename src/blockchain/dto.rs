@@ -23,6 +23,10 @@ use service::ANCHORING_SERVICE_ID;
 
 pub const ANCHORING_MESSAGE_SIGNATURE: u16 = 0;
 pub const ANCHORING_MESSAGE_LATEST: u16 = 1;
+pub const ANCHORING_MESSAGE_NOTARIZE: u16 = 2;
+pub const ANCHORING_MESSAGE_REQUEST: u16 = 3;
+pub const ANCHORING_MESSAGE_TIME_MARK: u16 = 4;
+pub const ANCHORING_MESSAGE_BLOCKS_MMR: u16 = 5;
 
 transactions! {
     Messages {
@@ -30,7 +34,7 @@ transactions! {
 
         /// Exonum message with the signature for the new anchoring transaction.
         struct MsgAnchoringSignature {
-            /// Public key of validator.
+            /// Service public key of the anchoring signer.
             from: &PublicKey,
             /// Public key index in anchoring public keys list.
             validator: ValidatorId,
@@ -43,7 +47,7 @@ transactions! {
         }
         /// Exonum message with the updated validator's lect.
         struct MsgAnchoringUpdateLatest {
-            /// Public key of validator.
+            /// Service public key of the anchoring signer.
             from: &PublicKey,
             /// Public key index in anchoring public keys list.
             validator: ValidatorId,
@@ -52,6 +56,41 @@ transactions! {
             /// Current lects count in the `lects` table for the current validator.
             lect_count: u64,
         }
+        /// Exonum message with the user digest that should be anchored.
+        struct MsgAnchoringNotarize {
+            /// Public key of the author.
+            from: &PublicKey,
+            /// Notarized digest.
+            digest: &Hash,
+        }
+        /// Exonum message with the request to anchor the given block out of schedule.
+        struct MsgAnchoringRequest {
+            /// Public key of the author.
+            from: &PublicKey,
+            /// Height of the block that should be anchored.
+            height: u64,
+        }
+        /// Exonum message that marks the block at which the next interval of the
+        /// time-based anchoring schedule has begun. The mark keeps the consensus time
+        /// of the block in which the message is executed.
+        struct MsgAnchoringTimeMark {
+            /// Public key of validator.
+            from: &PublicKey,
+            /// Public key index in anchoring public keys list.
+            validator: ValidatorId,
+            /// Index of the marked interval of the time-based anchoring schedule.
+            epoch: u64,
+        }
+        /// Exonum message that appends the hashes of the committed blocks to the Merkle
+        /// mountain range of the block hashes.
+        struct MsgAnchoringBlocksMmr {
+            /// Public key of validator.
+            from: &PublicKey,
+            /// Public key index in anchoring public keys list.
+            validator: ValidatorId,
+            /// Height of the latest committed block at the moment of sending.
+            height: u64,
+        }
     }
 }
 
@@ -65,6 +104,26 @@ encoding_struct! {
     }
 }
 
+encoding_struct! {
+    /// Accepted request for the out-of-schedule anchoring.
+    struct AnchoringRequest {
+        /// Height of the block that should be anchored.
+        height: u64,
+        /// Height of the block that contains the request.
+        requested_at: u64,
+    }
+}
+
+encoding_struct! {
+    /// Block at which the next interval of the time-based anchoring schedule has begun.
+    struct TimeMark {
+        /// Height of the marked block.
+        height: u64,
+        /// Consensus time in seconds at the marked block.
+        time: u64,
+    }
+}
+
 /// Constructs anchoring transaction from the given raw message.
 pub(crate) fn tx_from_raw(raw: RawTransaction) -> Result<Box<Transaction>, EncodingError> {
     Messages::tx_from_raw(raw).map(Into::into)
@@ -45,6 +45,42 @@ pub enum Error {
     /// Received another signature for given tx propose
     #[display(fmt = "Received another signature for given tx propose")]
     SignatureDifferent,
+    /// Received digest that has been already notarized
+    #[display(fmt = "Received digest that has been already notarized")]
+    NotaryDigestExists,
+    /// Received anchoring request from the unauthorized key
+    #[display(fmt = "Received anchoring request from the unauthorized key")]
+    RequestFromUnauthorizedKey,
+    /// Received anchoring request for the height that is not committed
+    #[display(fmt = "Received anchoring request for the height that is not committed")]
+    RequestForUnknownHeight,
+    /// Received anchoring request for the height that is already scheduled for anchoring
+    #[display(fmt = "Received anchoring request for the height that is already scheduled")]
+    RequestForScheduledHeight,
+    /// Received anchoring request before the end of the request interval
+    #[display(fmt = "Received anchoring request before the end of the request interval")]
+    RequestRateLimited,
+    /// Received anchoring request that exceeds the fee budget
+    #[display(fmt = "Received anchoring request that exceeds the fee budget")]
+    RequestBudgetExceeded,
+    /// Received time mark while the time-based schedule is not in use
+    #[display(fmt = "Received time mark while the time-based schedule is not in use")]
+    TimeMarkNotScheduled,
+    /// Received time mark for the interval that has been already marked
+    #[display(fmt = "Received time mark for the interval that has been already marked")]
+    TimeMarkExists,
+    /// Received message from the node that does not hold the anchoring key
+    #[display(fmt = "Received message from the node that does not hold the anchoring key")]
+    MsgFromUnknownSigner,
+    /// Received signature for the input that spends the unknown transaction
+    #[display(fmt = "Received signature for the input that spends the unknown transaction")]
+    SignatureForUnknownInput,
+    /// Received notarization from the unauthorized key
+    #[display(fmt = "Received notarization from the unauthorized key")]
+    NotaryFromUnauthorizedKey,
+    /// Received time mark for the interval that has not begun or has already passed
+    #[display(fmt = "Received time mark for the interval that is not the current one")]
+    TimeMarkWithWrongEpoch,
 }
 
 impl Error {
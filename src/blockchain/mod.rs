@@ -28,5 +28,7 @@ pub mod error;
 mod tests;
 
 pub use self::schema::{AnchoringSchema, KnownSignatureId};
-pub use self::dto::{LectContent, MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+pub use self::dto::{AnchoringRequest, LectContent, MsgAnchoringBlocksMmr, MsgAnchoringNotarize,
+                    MsgAnchoringRequest, MsgAnchoringSignature, MsgAnchoringTimeMark,
+                    MsgAnchoringUpdateLatest, TimeMark};
 pub use self::error::Error;
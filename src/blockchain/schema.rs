@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
+use std::time::UNIX_EPOCH;
 use std::collections::hash_map::{Entry, HashMap};
 
 use byteorder::{BigEndian, ByteOrder};
@@ -21,14 +23,22 @@ use exonum::blockchain::{gen_prefix, Schema, StoredConfiguration};
 use exonum::storage::{Fork, ListIndex, MapIndex, ProofListIndex, Snapshot, StorageKey};
 use exonum::crypto::Hash;
 use exonum::helpers::{Height, ValidatorId};
+use exonum_time::TimeSchema;
 
-use blockchain::consensus_storage::AnchoringConfig;
-use blockchain::dto::{LectContent, MsgAnchoringSignature};
+use blockchain::consensus_storage::{AnchoringConfig, AnchoringSchedule, ConfigError,
+                                    PayloadCommitment};
+use blockchain::dto::{AnchoringRequest, LectContent, MsgAnchoringSignature, TimeMark};
 use details::btc;
 use details::btc::transactions::{AnchoringTx, BitcoinTx};
+use details::mmr::{MerkleMountainRange, MmrOverlay, MmrProof};
 use service::ANCHORING_SERVICE_NAME;
 use super::Error as ValidateError;
 
+/// Maximum number of the block hashes that are appended to the `blocks_mmr` table
+/// by a single transaction. The rest of the hashes are appended by the following
+/// transactions, until then the blocks root is unavailable.
+pub(crate) const BLOCKS_MMR_BATCH: u64 = 1000;
+
 /// Unique identifier of signature for the `AnchoringTx`.
 #[derive(Debug, Clone)]
 pub struct KnownSignatureId {
@@ -126,31 +136,189 @@ where
         MapIndex::new("btc_anchoring.tx_chain", &self.view)
     }
 
+    /// Returns table that keeps the nodes of the Merkle mountain range of the block hashes.
+    ///
+    /// This table is not a part of the service state hash, it is filled by the
+    /// `MsgAnchoringBlocksMmr` messages which the validators send after each committed block.
+    pub fn blocks_mmr(&self) -> ListIndex<&T, Hash> {
+        ListIndex::new("btc_anchoring.blocks_mmr", &self.view)
+    }
+
+    /// Returns the number of the block hashes in the `blocks_mmr` table.
+    pub fn blocks_mmr_leaves(&self) -> u64 {
+        MerkleMountainRange::new(MmrOverlay::new(self.blocks_mmr())).leaves()
+    }
+
+    /// Returns the root of the Merkle mountain range of the block hashes
+    /// up to the given `height` inclusively, or `None` if the `blocks_mmr` table
+    /// does not contain the hash at this height yet.
+    pub fn blocks_root(&self, height: Height) -> Option<Hash> {
+        self.blocks_mmr_view(height).and_then(|mmr| mmr.root(height.0 + 1))
+    }
+
+    /// Returns the proof of the block hash inclusion at the given `height`
+    /// into the blocks root at the `anchored_height`.
+    pub fn block_proof(&self, height: Height, anchored_height: Height) -> Option<MmrProof> {
+        self.blocks_mmr_view(anchored_height)
+            .and_then(|mmr| mmr.proof(height.0, anchored_height.0 + 1))
+    }
+
+    /// Returns table that keeps the nodes of the Merkle mountain range of the notarized digests.
+    pub fn notary_mmr(&self) -> ListIndex<&T, Hash> {
+        ListIndex::new("btc_anchoring.notary_mmr", &self.view)
+    }
+
+    /// Returns table that keeps the block height of every notarized digest
+    /// in the order of notarization.
+    pub fn notary_heights(&self) -> ListIndex<&T, u64> {
+        ListIndex::new("btc_anchoring.notary_heights", &self.view)
+    }
+
+    /// Returns table that maps notarized digests to their indexes in the `notary_mmr`.
+    pub fn notary_indexes(&self) -> MapIndex<&T, Hash, u64> {
+        MapIndex::new("btc_anchoring.notary_indexes", &self.view)
+    }
+
+    /// Returns the number of digests that have been notarized up to the given `height` inclusively.
+    pub fn notary_count(&self, height: Height) -> u64 {
+        let heights = self.notary_heights();
+        count_not_above(heights.len(), height, |index| {
+            Height(heights.get(index).unwrap())
+        })
+    }
+
+    /// Returns the root of the Merkle mountain range of the digests that have been
+    /// notarized up to the given `height` inclusively or zero hash if there are no such digests.
+    /// The range is shared by all anchoring periods, so the root commits to every digest
+    /// notarized since the genesis.
+    pub fn notary_root(&self, height: Height) -> Hash {
+        let mmr = MerkleMountainRange::new(MmrOverlay::new(self.notary_mmr()));
+        mmr.root(self.notary_count(height)).unwrap_or_else(Hash::zero)
+    }
+
+    /// Returns the proof of the `digest` inclusion into the notary root at the `anchored_height`.
+    pub fn notary_proof(&self, digest: &Hash, anchored_height: Height) -> Option<MmrProof> {
+        let index = self.notary_indexes().get(digest)?;
+        let mmr = MerkleMountainRange::new(MmrOverlay::new(self.notary_mmr()));
+        mmr.proof(index, self.notary_count(anchored_height))
+    }
+
+    /// Returns table that keeps the heights flagged for anchoring by other services
+    /// in the increasing order.
+    pub fn flagged_heights(&self) -> ListIndex<&T, u64> {
+        ListIndex::new("btc_anchoring.flagged_heights", &self.view)
+    }
+
+    /// Returns table that keeps the accepted out-of-schedule anchoring requests
+    /// in the increasing order of the requested heights.
+    pub fn anchoring_requests(&self) -> ListIndex<&T, AnchoringRequest> {
+        ListIndex::new("btc_anchoring.requests", &self.view)
+    }
+
+    /// Returns the number of anchoring requests that have been accepted since the given `height`
+    /// inclusively.
+    pub fn anchoring_requests_since(&self, height: Height) -> u64 {
+        let requests = self.anchoring_requests();
+        if height == Height::zero() {
+            return requests.len();
+        }
+        requests.len() - count_not_above(requests.len(), height.previous(), |index| {
+            Height(requests.get(index).unwrap().requested_at())
+        })
+    }
+
+    /// Returns table that keeps the blocks at which the intervals of the time-based
+    /// anchoring schedule have begun.
+    pub fn time_marks(&self) -> ListIndex<&T, TimeMark> {
+        ListIndex::new("btc_anchoring.time_marks", &self.view)
+    }
+
+    /// Returns the consensus time in seconds provided by the `exonum-time` service
+    /// or `None` if it is not available.
+    pub fn consensus_time(&self) -> Option<u64> {
+        let time = TimeSchema::new(&self.view).time().get()?;
+        time.duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs())
+    }
+
+    /// Returns the latest height below or equal to the given `height` which needs to be anchored
+    /// according to the given configuration.
+    ///
+    /// Besides the scheduled heights, which are the multiples of the `frequency` or the latest
+    /// time mark for the time-based schedule, it can be the latest requested height,
+    /// the height at which the latest configuration has taken effect or the latest height
+    /// flagged by other services, if the corresponding options are enabled.
+    pub fn latest_anchoring_height(&self, cfg: &AnchoringConfig, height: Height) -> Height {
+        let mut latest = if let AnchoringSchedule::Time { .. } = cfg.schedule {
+            let marks = self.time_marks();
+            let count = count_not_above(marks.len(), height, |index| {
+                Height(marks.get(index).unwrap().height())
+            });
+            if count > 0 {
+                Height(marks.get(count - 1).unwrap().height())
+            } else {
+                Height::zero()
+            }
+        } else {
+            cfg.latest_anchoring_height(height)
+        };
+        let requests = self.anchoring_requests();
+        let count = count_not_above(requests.len(), height, |index| {
+            Height(requests.get(index).unwrap().height())
+        });
+        if count > 0 {
+            latest = cmp::max(latest, Height(requests.get(count - 1).unwrap().height()));
+        }
+        if cfg.anchor_config_changes {
+            let core_schema = Schema::new(&self.view);
+            let configs = core_schema.configs_actual_from();
+            let count = count_not_above(configs.len(), height, |index| {
+                configs.get(index).unwrap().actual_from()
+            });
+            if count > 0 {
+                latest = cmp::max(latest, configs.get(count - 1).unwrap().actual_from());
+            }
+        }
+        if cfg.anchor_flagged_heights {
+            let heights = self.flagged_heights();
+            let count = count_not_above(heights.len(), height, |index| {
+                Height(heights.get(index).unwrap())
+            });
+            if count > 0 {
+                latest = cmp::max(latest, Height(heights.get(count - 1).unwrap()));
+            }
+        }
+        latest
+    }
+
     /// Returns the actual anchoring configuration.
+    ///
+    /// The configurations that do not pass `AnchoringConfig::validate` are ignored,
+    /// so the latest valid configuration remains actual.
     pub fn actual_anchoring_config(&self) -> AnchoringConfig {
-        let schema = Schema::new(&self.view);
-        let actual = schema.actual_configuration();
-        self.parse_config(&actual)
+        let next_height = Schema::new(&self.view).block_hashes_by_height().len();
+        self.anchoring_config_by_height(Height(next_height))
     }
 
-    /// Returns the nearest following configuration if it exists.
+    /// Returns the nearest following configuration if it exists and is valid.
     pub fn following_anchoring_config(&self) -> Option<AnchoringConfig> {
         let schema = Schema::new(&self.view);
-        if let Some(stored) = schema.following_configuration() {
-            Some(self.parse_config(&stored))
-        } else {
-            None
+        let stored = schema.following_configuration()?;
+        match self.parse_config(&stored) {
+            Ok(cfg) => Some(cfg),
+            Err(e) => {
+                warn!("The following anchoring config is ignored: {}", e);
+                None
+            }
         }
     }
 
-    /// Returns the previous anchoring configuration if it exists.
+    /// Returns the previous valid anchoring configuration if it exists.
     pub fn previous_anchoring_config(&self) -> Option<AnchoringConfig> {
-        let schema = Schema::new(&self.view);
-        if let Some(stored) = schema.previous_configuration() {
-            Some(self.parse_config(&stored))
-        } else {
-            None
-        }
+        let next_height = Schema::new(&self.view).block_hashes_by_height().len();
+        let (index, _) = self.valid_config_by_height(Height(next_height));
+        self.latest_valid_config(index).map(|(_, cfg)| cfg)
     }
 
     /// Returns the anchoring configuration from the genesis block.
@@ -158,12 +326,94 @@ where
         self.anchoring_config_by_height(Height::zero())
     }
 
+    /// Returns the values of the outputs spent by the inputs of the given transaction or
+    /// `None` if some of the spent transactions are unknown, see `spent_txs`. The values
+    /// are required to sign the segregated witness inputs.
+    pub fn spent_values(&self, tx: &AnchoringTx) -> Option<Vec<u64>> {
+        let spent_txs = self.spent_txs(tx)?;
+        tx.0
+            .input
+            .iter()
+            .zip(spent_txs)
+            .map(|(input, spent_tx)| {
+                spent_tx
+                    .output
+                    .get(input.prev_index as usize)
+                    .map(|output| output.value)
+            })
+            .collect()
+    }
+
+    /// Returns the transactions spent by the inputs of the given transaction or `None`
+    /// if some of them are unknown. The spent transactions are looked up among the known
+    /// transactions and the funding transactions of the previous, actual and following
+    /// configurations.
+    pub fn spent_txs(&self, tx: &AnchoringTx) -> Option<Vec<BitcoinTx>> {
+        let funding_txs = self.previous_anchoring_config()
+            .into_iter()
+            .chain(Some(self.actual_anchoring_config()))
+            .chain(self.following_anchoring_config())
+            .filter_map(|cfg| cfg.funding_tx)
+            .collect::<Vec<_>>();
+        let known_txs = self.known_txs();
+        tx.0
+            .input
+            .iter()
+            .map(|input| {
+                let txid = btc::TxId::from(input.prev_hash);
+                known_txs.get(&txid).or_else(|| {
+                    funding_txs
+                        .iter()
+                        .find(|funding_tx| funding_tx.id() == txid)
+                        .map(|funding_tx| BitcoinTx::from(funding_tx.0.clone()))
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the committed configurations that do not pass `AnchoringConfig::validate`
+    /// as the heights from which they should be actual, their hashes and the reasons.
+    pub fn rejected_anchoring_configs(&self) -> Vec<(Height, Hash, ConfigError)> {
+        let schema = Schema::new(&self.view);
+        let configs = schema.configs();
+        schema
+            .configs_actual_from()
+            .iter()
+            .filter_map(|reference| {
+                let stored = configs.get(reference.cfg_hash()).unwrap();
+                self.parse_config(&stored)
+                    .err()
+                    .map(|e| (reference.actual_from(), *reference.cfg_hash(), e))
+            })
+            .collect()
+    }
+
+    /// Returns the hash of the configuration that should become actual exactly at the given
+    /// `height` and the reason why it is rejected, if it does not pass the validation.
+    pub fn rejected_config_at(&self, height: Height) -> Option<(Hash, ConfigError)> {
+        let schema = Schema::new(&self.view);
+        let references = schema.configs_actual_from();
+        let count = count_not_above(references.len(), height, |index| {
+            references.get(index).unwrap().actual_from()
+        });
+        if count == 0 {
+            return None;
+        }
+        let reference = references.get(count - 1).unwrap();
+        if reference.actual_from() != height {
+            return None;
+        }
+        let stored = schema.configs().get(reference.cfg_hash()).unwrap();
+        self.parse_config(&stored)
+            .err()
+            .map(|e| (*reference.cfg_hash(), e))
+    }
+
     /// Returns the configuration that is the actual for the given `height`.
     /// For non-existent heights, it will return the configuration closest to them.
+    /// The invalid configurations are skipped as in `actual_anchoring_config`.
     pub fn anchoring_config_by_height(&self, height: Height) -> AnchoringConfig {
-        let schema = Schema::new(&self.view);
-        let stored = schema.configuration_by_height(height);
-        self.parse_config(&stored)
+        self.valid_config_by_height(height).1
     }
 
     /// Returns `lect` for validator with the given `public_key`.
@@ -232,9 +482,47 @@ where
         lect_hashes
     }
 
-    fn parse_config(&self, cfg: &StoredConfiguration) -> AnchoringConfig {
-        from_value(cfg.services[ANCHORING_SERVICE_NAME].clone())
-            .expect("Anchoring config does not exist")
+    fn blocks_mmr_view(&self, height: Height) -> Option<MerkleMountainRange<MmrOverlay<&T>>> {
+        let mmr = MerkleMountainRange::new(MmrOverlay::new(self.blocks_mmr()));
+        if mmr.leaves() <= height.0 {
+            return None;
+        }
+        Some(mmr)
+    }
+
+    fn parse_config(&self, cfg: &StoredConfiguration) -> Result<AnchoringConfig, ConfigError> {
+        let value = cfg.services
+            .get(ANCHORING_SERVICE_NAME)
+            .ok_or_else(|| ConfigError::Malformed("anchoring config does not exist".to_owned()))?;
+        let cfg: AnchoringConfig =
+            from_value(value.clone()).map_err(|e| ConfigError::Malformed(e.to_string()))?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    // Returns the index in the `configs_actual_from` table and the latest valid configuration
+    // that is actual for the given `height`.
+    fn valid_config_by_height(&self, height: Height) -> (u64, AnchoringConfig) {
+        let schema = Schema::new(&self.view);
+        let references = schema.configs_actual_from();
+        let count = count_not_above(references.len(), height, |index| {
+            references.get(index).unwrap().actual_from()
+        });
+        // The genesis configuration is validated by the service initialization.
+        self.latest_valid_config(cmp::max(count, 1))
+            .expect("Genesis anchoring config is invalid")
+    }
+
+    // Returns the index and the latest valid configuration among the first `count` entries
+    // of the `configs_actual_from` table.
+    fn latest_valid_config(&self, count: u64) -> Option<(u64, AnchoringConfig)> {
+        let schema = Schema::new(&self.view);
+        let references = schema.configs_actual_from();
+        let configs = schema.configs();
+        (0..count).rev().filter_map(|index| {
+            let stored = configs.get(references.get(index).unwrap().cfg_hash()).unwrap();
+            self.parse_config(&stored).ok().map(|cfg| (index, cfg))
+        }).next()
     }
 }
 
@@ -300,6 +588,109 @@ impl<'a> AnchoringSchema<&'a mut Fork> {
         MapIndex::new("btc_anchoring.tx_chain", &mut self.view)
     }
 
+    /// Mutable variant of the [`blocks_mmr`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.blocks_mmr
+    pub fn blocks_mmr_mut(&mut self) -> ListIndex<&mut Fork, Hash> {
+        ListIndex::new("btc_anchoring.blocks_mmr", &mut self.view)
+    }
+
+    /// Appends at most `BLOCKS_MMR_BATCH` missing block hashes to the `blocks_mmr` table
+    /// if the actual configuration anchors the blocks root, so enabling it on a long chain
+    /// does not write all the historical hashes in a single transaction.
+    pub fn update_blocks_mmr(&mut self) {
+        let commitment = self.actual_anchoring_config().payload_commitment;
+        if commitment != PayloadCommitment::BlocksRoot {
+            return;
+        }
+
+        let block_hashes = {
+            let leaves = self.blocks_mmr_leaves();
+            let core_schema = Schema::new(&self.view);
+            let block_hashes = core_schema.block_hashes_by_height();
+            let end = cmp::min(block_hashes.len(), leaves + BLOCKS_MMR_BATCH);
+            (leaves..end)
+                .map(|index| block_hashes.get(index).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let mut mmr = MerkleMountainRange::new(self.blocks_mmr_mut());
+        for block_hash in &block_hashes {
+            mmr.push(block_hash);
+        }
+    }
+
+    /// Mutable variant of the [`notary_mmr`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.notary_mmr
+    pub fn notary_mmr_mut(&mut self) -> ListIndex<&mut Fork, Hash> {
+        ListIndex::new("btc_anchoring.notary_mmr", &mut self.view)
+    }
+
+    /// Mutable variant of the [`notary_heights`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.notary_heights
+    pub fn notary_heights_mut(&mut self) -> ListIndex<&mut Fork, u64> {
+        ListIndex::new("btc_anchoring.notary_heights", &mut self.view)
+    }
+
+    /// Mutable variant of the [`notary_indexes`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.notary_indexes
+    pub fn notary_indexes_mut(&mut self) -> MapIndex<&mut Fork, Hash, u64> {
+        MapIndex::new("btc_anchoring.notary_indexes", &mut self.view)
+    }
+
+    /// Mutable variant of the [`flagged_heights`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.flagged_heights
+    pub fn flagged_heights_mut(&mut self) -> ListIndex<&mut Fork, u64> {
+        ListIndex::new("btc_anchoring.flagged_heights", &mut self.view)
+    }
+
+    /// Flags the height of the block which is being created for anchoring.
+    /// Other services may call it during the transactions execution, the flagged
+    /// heights are anchored only if the `anchor_flagged_heights` option is enabled.
+    pub fn flag_current_height(&mut self) -> Height {
+        let height = Height(Schema::new(&self.view).block_hashes_by_height().len());
+        let mut heights = self.flagged_heights_mut();
+        if heights.last() != Some(height.0) {
+            heights.push(height.0);
+        }
+        height
+    }
+
+    /// Mutable variant of the [`anchoring_requests`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.anchoring_requests
+    pub fn anchoring_requests_mut(&mut self) -> ListIndex<&mut Fork, AnchoringRequest> {
+        ListIndex::new("btc_anchoring.requests", &mut self.view)
+    }
+
+    /// Mutable variant of the [`time_marks`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.time_marks
+    pub fn time_marks_mut(&mut self) -> ListIndex<&mut Fork, TimeMark> {
+        ListIndex::new("btc_anchoring.time_marks", &mut self.view)
+    }
+
+    /// Adds the `digest` notarized in the block at the given `height`.
+    pub fn add_notary_digest(
+        &mut self,
+        digest: &Hash,
+        height: Height,
+    ) -> Result<(), ValidateError> {
+        if self.notary_indexes().contains(digest) {
+            return Err(ValidateError::NotaryDigestExists);
+        }
+
+        let index = self.notary_heights().len();
+        self.notary_heights_mut().push(height.0);
+        self.notary_indexes_mut().put(digest, index);
+        MerkleMountainRange::new(self.notary_mmr_mut()).push(digest);
+        Ok(())
+    }
+
     /// Creates and commits the genesis anchoring configuration from the proposed `cfg`.
     pub fn create_genesis_config(&mut self, cfg: &AnchoringConfig) {
         for validator_key in &cfg.anchoring_keys {
@@ -345,3 +736,21 @@ impl<T> AnchoringSchema<T> {
         self.view
     }
 }
+
+// Returns the number of the leading items that are not above the given `height`,
+// the items of the sorted list are accessed by the `item_height` closure.
+fn count_not_above<F>(len: u64, height: Height, item_height: F) -> u64
+where
+    F: Fn(u64) -> Height,
+{
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if item_height(mid) <= height {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
@@ -12,9 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use exonum::crypto::{hash, PublicKey, Signature};
-use exonum::storage::StorageValue;
-use exonum::helpers::ValidatorId;
+use std::collections::BTreeMap;
+
+use exonum::blockchain::{ConsensusConfig, Schema, StoredConfiguration, ValidatorKeys};
+use exonum::crypto::{gen_keypair, hash, CryptoHash, Hash, PublicKey, Signature};
+use exonum::storage::{Database, ListIndex, MemoryDB, StorageValue};
+use exonum::helpers::{Height, ValidatorId};
 use exonum::encoding::serialize::FromHex;
 
 use bitcoin::blockdata::transaction::SigHashType;
@@ -23,8 +26,14 @@ use serde_json;
 
 use details::btc;
 use details::btc::transactions::{AnchoringTx, BitcoinTx};
-use blockchain::dto::{LectContent, MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use details::mmr::MerkleMountainRange;
+use blockchain::consensus_storage::{AnchoringConfig, AnchoringSchedule, AnchoringSigner,
+                                    ConfigError, PayloadCommitment};
+use blockchain::dto::{AnchoringRequest, LectContent, MsgAnchoringSignature,
+                      MsgAnchoringUpdateLatest, TimeMark};
+use blockchain::schema::{AnchoringSchema, BLOCKS_MMR_BATCH};
 use details::tests::{dummy_anchoring_tx, gen_anchoring_keys, make_signatures};
+use service::ANCHORING_SERVICE_NAME;
 
 #[test]
 fn test_lect_content_encoding_struct() {
@@ -154,3 +163,313 @@ fn test_nonexistent_input_in_msg_signature_tx_body() {
     );
     assert!(!msg.verify_content());
 }
+
+#[test]
+fn test_latest_anchoring_height_with_flagged_heights() {
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    {
+        let mut schema = AnchoringSchema::new(&mut fork);
+        assert_eq!(schema.flag_current_height(), Height(0));
+        assert_eq!(schema.flag_current_height(), Height(0));
+        let mut heights = schema.flagged_heights_mut();
+        heights.push(17);
+        heights.push(25);
+    }
+
+    let schema = AnchoringSchema::new(&fork);
+    assert_eq!(schema.flagged_heights().len(), 3);
+
+    let mut cfg = AnchoringConfig {
+        frequency: 10,
+        ..AnchoringConfig::default()
+    };
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(19)), Height(10));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(26)), Height(20));
+
+    cfg.anchor_flagged_heights = true;
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(9)), Height(0));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(16)), Height(10));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(19)), Height(17));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(24)), Height(20));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(26)), Height(25));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(31)), Height(30));
+}
+
+#[test]
+fn test_latest_anchoring_height_with_requests() {
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    {
+        let mut schema = AnchoringSchema::new(&mut fork);
+        let mut requests = schema.anchoring_requests_mut();
+        requests.push(AnchoringRequest::new(13, 15));
+        requests.push(AnchoringRequest::new(24, 27));
+    }
+
+    let schema = AnchoringSchema::new(&fork);
+    assert_eq!(schema.anchoring_requests_since(Height(0)), 2);
+    assert_eq!(schema.anchoring_requests_since(Height(15)), 2);
+    assert_eq!(schema.anchoring_requests_since(Height(16)), 1);
+    assert_eq!(schema.anchoring_requests_since(Height(28)), 0);
+
+    let cfg = AnchoringConfig {
+        frequency: 10,
+        ..AnchoringConfig::default()
+    };
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(12)), Height(10));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(13)), Height(13));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(19)), Height(13));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(23)), Height(20));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(29)), Height(24));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(30)), Height(30));
+}
+
+#[test]
+fn test_latest_anchoring_height_with_time_marks() {
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    {
+        let mut schema = AnchoringSchema::new(&mut fork);
+        let mut marks = schema.time_marks_mut();
+        marks.push(TimeMark::new(7, 1_200));
+        marks.push(TimeMark::new(31, 1_800));
+    }
+
+    let schema = AnchoringSchema::new(&fork);
+    let mut cfg = AnchoringConfig {
+        frequency: 10,
+        ..AnchoringConfig::default()
+    };
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(32)), Height(30));
+
+    cfg.schedule = AnchoringSchedule::Time { interval: 600 };
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(6)), Height(0));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(7)), Height(7));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(30)), Height(7));
+    assert_eq!(schema.latest_anchoring_height(&cfg, Height(32)), Height(31));
+
+    assert_eq!(cfg.schedule.time_epoch(1_799), Some(2));
+    assert_eq!(cfg.schedule.time_epoch(1_800), Some(3));
+    assert_eq!(AnchoringSchedule::Time { interval: 0 }.time_epoch(1_800), None);
+    assert_eq!(AnchoringSchedule::Blocks.time_epoch(1_800), None);
+
+    // The interval 3 is marked by the validator 3 and then by the next ones each 30 seconds.
+    assert_eq!(cfg.schedule.time_mark_turn(1_800, 4), Some((0, ValidatorId(3))));
+    assert_eq!(cfg.schedule.time_mark_turn(1_829, 4), Some((0, ValidatorId(3))));
+    assert_eq!(cfg.schedule.time_mark_turn(1_830, 4), Some((1, ValidatorId(0))));
+    assert_eq!(cfg.schedule.time_mark_turn(1_955, 4), Some((5, ValidatorId(0))));
+    assert_eq!(cfg.schedule.time_mark_turn(2_400, 4), Some((0, ValidatorId(0))));
+    assert_eq!(cfg.schedule.time_mark_turn(1_800, 0), None);
+    assert_eq!(AnchoringSchedule::Blocks.time_mark_turn(1_800, 4), None);
+}
+
+#[test]
+fn test_anchoring_schedule_json_serde() {
+    let cfg = AnchoringConfig {
+        schedule: AnchoringSchedule::Time { interval: 600 },
+        ..AnchoringConfig::default()
+    };
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert_eq!(json["schedule"], json!({ "time": { "interval": 600 } }));
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2, cfg);
+
+    let json = serde_json::to_value(AnchoringConfig::default()).unwrap();
+    assert!(json.get("schedule").is_none());
+    let cfg: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg.schedule, AnchoringSchedule::Blocks);
+}
+
+#[test]
+fn test_anchoring_config_threshold() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    assert_eq!(cfg.majority_count(), 3);
+    assert_eq!(cfg.validate(), Ok(()));
+
+    cfg.threshold = Some(2);
+    assert_eq!(cfg.majority_count(), 2);
+    assert_eq!(cfg.validate(), Ok(()));
+    assert_eq!(
+        cfg.redeem_script().0,
+        btc::RedeemScript::from_pubkeys(&cfg.anchoring_keys, 2).compressed(Network::Testnet)
+    );
+
+    cfg.threshold = Some(5);
+    assert_eq!(cfg.validate(), Err(ConfigError::IncorrectThreshold(5, 4)));
+    cfg.threshold = Some(0);
+    assert_eq!(cfg.validate(), Err(ConfigError::IncorrectThreshold(0, 4)));
+    cfg.threshold = None;
+    cfg.payload_version = 3;
+    assert_eq!(cfg.validate(), Err(ConfigError::UnsupportedPayloadVersion(3)));
+    cfg.payload_version = 1;
+    cfg.payload_commitment = PayloadCommitment::BlocksRoot;
+    assert_eq!(
+        cfg.validate(),
+        Err(ConfigError::CommitmentRequiresPayloadV2(PayloadCommitment::BlocksRoot))
+    );
+    cfg.payload_version = 2;
+    assert_eq!(cfg.validate(), Ok(()));
+
+    let (pub_keys, _) = gen_anchoring_keys(16);
+    let cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    assert_eq!(cfg.validate(), Err(ConfigError::TooManyKeys(16, 15)));
+
+    let mut cfg = AnchoringConfig::new(Network::Testnet, vec![]);
+    assert_eq!(cfg.validate(), Err(ConfigError::NoAnchoringKeys));
+    cfg.threshold = Some(1);
+    assert_eq!(cfg.validate(), Err(ConfigError::NoAnchoringKeys));
+}
+
+#[test]
+fn test_anchoring_config_signers() {
+    let (pub_keys, _) = gen_anchoring_keys(3);
+    let validator_keys = (0..4)
+        .map(|_| ValidatorKeys {
+            consensus_key: gen_keypair().0,
+            service_key: gen_keypair().0,
+        })
+        .collect::<Vec<_>>();
+
+    // Without the signers list the first validators hold the anchoring keys.
+    let cfg = AnchoringConfig::new(Network::Testnet, pub_keys.clone());
+    let service_key = validator_keys[1].service_key;
+    assert_eq!(cfg.signer_id(&service_key, &validator_keys), Some(ValidatorId(1)));
+    assert_eq!(cfg.signer_id(&validator_keys[3].service_key, &validator_keys), None);
+    assert_eq!(cfg.signer_service_key(ValidatorId(1), &validator_keys), Some(service_key));
+    assert_eq!(cfg.signer_service_key(ValidatorId(3), &validator_keys), None);
+
+    let signer_keys = (0..3).map(|_| gen_keypair().0).collect::<Vec<_>>();
+    let signers = signer_keys
+        .iter()
+        .zip(&pub_keys)
+        .map(|(service_key, anchoring_key)| AnchoringSigner {
+            service_key: *service_key,
+            anchoring_key: *anchoring_key,
+        });
+    let mut cfg = AnchoringConfig::new_with_signers(Network::Testnet, signers);
+    assert_eq!(cfg.anchoring_keys, pub_keys);
+    assert_eq!(cfg.validate(), Ok(()));
+    assert_eq!(cfg.signer_id(&signer_keys[2], &validator_keys), Some(ValidatorId(2)));
+    assert_eq!(cfg.signer_id(&service_key, &validator_keys), None);
+    assert_eq!(
+        cfg.signer_service_key(ValidatorId(0), &validator_keys),
+        Some(signer_keys[0])
+    );
+
+    cfg.anchoring_keys.swap(0, 1);
+    assert_eq!(cfg.validate(), Err(ConfigError::SignersMismatch));
+}
+
+#[test]
+fn test_invalid_anchoring_config_ignored() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    let invalid_cfg = AnchoringConfig {
+        threshold: Some(5),
+        ..cfg.clone()
+    };
+
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    let mut invalid_cfg_hash = Hash::zero();
+    {
+        let mut schema = Schema::new(&mut fork);
+        let mut previous_cfg_hash = Hash::zero();
+        for &(actual_from, anchoring_cfg) in &[(0, &cfg), (10, &invalid_cfg)] {
+            let mut stored = StoredConfiguration {
+                previous_cfg_hash,
+                actual_from: Height(actual_from),
+                validator_keys: Vec::new(),
+                consensus: ConsensusConfig::default(),
+                services: BTreeMap::new(),
+            };
+            stored.services.insert(
+                ANCHORING_SERVICE_NAME.to_owned(),
+                serde_json::to_value(anchoring_cfg).unwrap(),
+            );
+            previous_cfg_hash = stored.hash();
+            invalid_cfg_hash = stored.hash();
+            schema.commit_configuration(stored);
+        }
+    }
+
+    let schema = AnchoringSchema::new(&fork);
+    assert_eq!(schema.following_anchoring_config(), None);
+    assert_eq!(schema.actual_anchoring_config(), cfg);
+    assert_eq!(schema.anchoring_config_by_height(Height(20)), cfg);
+    assert_eq!(schema.previous_anchoring_config(), None);
+
+    let rejection = ConfigError::IncorrectThreshold(5, 4);
+    assert_eq!(
+        schema.rejected_anchoring_configs(),
+        vec![(Height(10), invalid_cfg_hash, rejection.clone())]
+    );
+    assert_eq!(
+        schema.rejected_config_at(Height(10)),
+        Some((invalid_cfg_hash, rejection))
+    );
+    assert_eq!(schema.rejected_config_at(Height(0)), None);
+    assert_eq!(schema.rejected_config_at(Height(11)), None);
+}
+
+#[test]
+fn test_blocks_mmr_backfill_is_bounded() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let cfg = AnchoringConfig {
+        payload_version: 2,
+        payload_commitment: PayloadCommitment::BlocksRoot,
+        ..AnchoringConfig::new(Network::Testnet, pub_keys)
+    };
+    let blocks_count = BLOCKS_MMR_BATCH * 2 + 5;
+    let block_hashes = (0..blocks_count)
+        .map(|height| hash(height.to_string().as_bytes()))
+        .collect::<Vec<_>>();
+
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    {
+        let mut stored = StoredConfiguration {
+            previous_cfg_hash: Hash::zero(),
+            actual_from: Height(0),
+            validator_keys: Vec::new(),
+            consensus: ConsensusConfig::default(),
+            services: BTreeMap::new(),
+        };
+        stored.services.insert(
+            ANCHORING_SERVICE_NAME.to_owned(),
+            serde_json::to_value(&cfg).unwrap(),
+        );
+        Schema::new(&mut fork).commit_configuration(stored);
+        // The blocks are committed before the blocks root is anchored.
+        let mut hashes = ListIndex::new("core.block_hashes_by_height", &mut fork);
+        for block_hash in &block_hashes {
+            hashes.push(*block_hash);
+        }
+    }
+
+    let mut expected = MerkleMountainRange::new(Vec::new());
+    for block_hash in &block_hashes {
+        expected.push(block_hash);
+    }
+    let anchored_height = Height(blocks_count - 1);
+    assert_eq!(AnchoringSchema::new(&fork).blocks_root(anchored_height), None);
+    for batch in 1..4 {
+        let mut schema = AnchoringSchema::new(&mut fork);
+        schema.update_blocks_mmr();
+        let leaves = schema.blocks_mmr_leaves();
+        assert_eq!(leaves, ::std::cmp::min(BLOCKS_MMR_BATCH * batch, blocks_count));
+        // The blocks root is available only after all the hashes have been appended.
+        let expected_root = if leaves == blocks_count {
+            expected.root(blocks_count)
+        } else {
+            None
+        };
+        assert_eq!(schema.blocks_root(anchored_height), expected_root);
+        assert_eq!(
+            schema.blocks_root(Height(leaves - 1)),
+            expected.root(leaves)
+        );
+    }
+}
@@ -20,10 +20,13 @@ use exonum::messages::Message;
 use exonum::storage::{Fork, Snapshot};
 use exonum::helpers::Height;
 
-use blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use blockchain::dto::{AnchoringRequest, MsgAnchoringBlocksMmr, MsgAnchoringNotarize,
+                      MsgAnchoringRequest, MsgAnchoringSignature, MsgAnchoringTimeMark,
+                      MsgAnchoringUpdateLatest, TimeMark};
 use blockchain::schema::AnchoringSchema;
 use blockchain::consensus_storage::AnchoringConfig;
 use details::btc;
+use details::btc::payload::{NetworkId, Payload};
 use details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx, TxKind};
 use super::Error as ValidateError;
 
@@ -57,6 +60,11 @@ impl MsgAnchoringSignature {
                 return false;
             }
         }
+        // Check that the witness is empty
+        if !tx.witness.is_empty() {
+            warn!("Received msg with non empty witness, content={:#?}", self);
+            return false;
+        }
         true
     }
 
@@ -67,13 +75,15 @@ impl MsgAnchoringSignature {
         let tx = self.tx();
         let id = self.validator().0 as usize;
         let actual_cfg = core_schema.actual_configuration();
+        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
         // Verify from field
-        if actual_cfg.validator_keys.get(id).map(|k| k.service_key) != Some(*self.from()) {
-            return Err(ValidateError::MsgFromNonValidator);
+        let signer_key =
+            anchoring_cfg.signer_service_key(self.validator(), &actual_cfg.validator_keys);
+        if signer_key != Some(*self.from()) {
+            return Err(ValidateError::MsgFromUnknownSigner);
         }
 
         // Verify signature
-        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
         if let Some(pub_key) = anchoring_cfg.anchoring_keys.get(id) {
             let (redeem_script, addr) = anchoring_cfg.redeem_script();
             let tx_addr = tx.output_address(anchoring_cfg.network);
@@ -86,13 +96,28 @@ impl MsgAnchoringSignature {
             if tx_addr != addr {
                 return Err(ValidateError::MsgWithIncorrectAddress);
             }
-            verify_anchoring_tx_payload(&tx, &core_schema)?;
-            if !tx.verify_input(&redeem_script, self.input(), pub_key, self.signature()) {
+            verify_anchoring_tx_payload(&tx, view)?;
+            let is_correct = if redeem_script.is_witness_script() {
+                let value = anchoring_schema
+                    .spent_values(&tx)
+                    .and_then(|values| values.get(self.input() as usize).cloned())
+                    .ok_or(ValidateError::SignatureForUnknownInput)?;
+                tx.verify_witness_input(
+                    &redeem_script,
+                    self.input(),
+                    value,
+                    pub_key,
+                    self.signature(),
+                )
+            } else {
+                tx.verify_input(&redeem_script, self.input(), pub_key, self.signature())
+            };
+            if !is_correct {
                 return Err(ValidateError::SignatureIncorrect);
             }
             Ok(())
         } else {
-            return Err(ValidateError::MsgFromNonValidator);
+            Err(ValidateError::MsgFromUnknownSigner)
         }
     }
 }
@@ -104,8 +129,7 @@ impl Transaction for MsgAnchoringSignature {
 
     fn execute(&self, fork: &mut Fork) -> ExecutionResult {
         self.validate(fork)?;
-        let mut anchoring_schema = AnchoringSchema::new(fork);
-        anchoring_schema
+        AnchoringSchema::new(fork)
             .add_known_signature(self.clone())
             .map_err(Into::into)
     }
@@ -120,16 +144,18 @@ impl MsgAnchoringUpdateLatest {
         let id = self.validator().0 as usize;
         // Verify lect with actual cfg
         let actual_cfg = core_schema.actual_configuration();
+        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
 
-        if actual_cfg.validator_keys.get(id).map(|k| k.service_key) != Some(*self.from()) {
-            return Err(ValidateError::MsgFromNonValidator);
+        let signer_key =
+            anchoring_cfg.signer_service_key(self.validator(), &actual_cfg.validator_keys);
+        if signer_key != Some(*self.from()) {
+            return Err(ValidateError::MsgFromUnknownSigner);
         }
 
-        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
         let key = &anchoring_cfg.anchoring_keys[id];
         match TxKind::from(tx.clone()) {
             TxKind::Anchoring(tx) => {
-                verify_anchoring_tx_payload(&tx, &core_schema)?;
+                verify_anchoring_tx_payload(&tx, view)?;
                 verify_anchoring_tx_prev_hash(&tx, &anchoring_schema)?;
             }
             TxKind::FundingTx(tx) => {
@@ -159,6 +185,172 @@ impl Transaction for MsgAnchoringUpdateLatest {
     }
 }
 
+impl Transaction for MsgAnchoringNotarize {
+    fn verify(&self) -> bool {
+        self.verify_signature(self.from())
+    }
+
+    fn execute(&self, fork: &mut Fork) -> ExecutionResult {
+        self.validate(fork)?;
+        // The block that contains this transaction is not committed yet.
+        let height = Height(Schema::new(&fork).block_hashes_by_height().len());
+        AnchoringSchema::new(fork)
+            .add_notary_digest(self.digest(), height)
+            .map_err(Into::into)
+    }
+}
+
+impl MsgAnchoringNotarize {
+    pub fn validate(&self, view: &Fork) -> Result<(), ValidateError> {
+        let core_schema = Schema::new(&view);
+        let anchoring_schema = AnchoringSchema::new(&view);
+
+        // Verify from field
+        let is_validator = core_schema
+            .actual_configuration()
+            .validator_keys
+            .iter()
+            .any(|keys| keys.service_key == *self.from());
+        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
+        if !is_validator && !anchoring_cfg.notary_submitters.contains(self.from()) {
+            return Err(ValidateError::NotaryFromUnauthorizedKey);
+        }
+
+        if anchoring_schema.notary_indexes().contains(self.digest()) {
+            return Err(ValidateError::NotaryDigestExists);
+        }
+        Ok(())
+    }
+}
+
+impl MsgAnchoringRequest {
+    pub fn validate(&self, view: &Fork) -> Result<(), ValidateError> {
+        let core_schema = Schema::new(&view);
+        let anchoring_schema = AnchoringSchema::new(&view);
+
+        let actual_cfg = core_schema.actual_configuration();
+        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
+        // Verify from field
+        let is_validator = actual_cfg
+            .validator_keys
+            .iter()
+            .any(|keys| keys.service_key == *self.from());
+        if !is_validator && !anchoring_cfg.anchoring_requesters.contains(self.from()) {
+            return Err(ValidateError::RequestFromUnauthorizedKey);
+        }
+
+        // The block that contains this transaction is not committed yet.
+        let current_height = Height(core_schema.block_hashes_by_height().len());
+        let height = Height(self.height());
+        if height >= current_height {
+            return Err(ValidateError::RequestForUnknownHeight);
+        }
+        if height <= anchoring_schema.latest_anchoring_height(&anchoring_cfg, current_height) {
+            return Err(ValidateError::RequestForScheduledHeight);
+        }
+
+        // Verify rate limit and fee budget
+        if let Some(last) = anchoring_schema.anchoring_requests().last() {
+            if last.requested_at() + anchoring_cfg.request_interval > current_height.0 {
+                return Err(ValidateError::RequestRateLimited);
+            }
+        }
+        let spent = anchoring_schema.anchoring_requests_since(actual_cfg.actual_from)
+            * anchoring_cfg.fee;
+        if spent + anchoring_cfg.fee > anchoring_cfg.request_fee_budget {
+            return Err(ValidateError::RequestBudgetExceeded);
+        }
+        Ok(())
+    }
+}
+
+impl Transaction for MsgAnchoringRequest {
+    fn verify(&self) -> bool {
+        self.verify_signature(self.from())
+    }
+
+    fn execute(&self, fork: &mut Fork) -> ExecutionResult {
+        self.validate(fork)?;
+        let requested_at = Schema::new(&fork).block_hashes_by_height().len();
+        AnchoringSchema::new(fork)
+            .anchoring_requests_mut()
+            .push(AnchoringRequest::new(self.height(), requested_at));
+        Ok(())
+    }
+}
+
+impl MsgAnchoringTimeMark {
+    pub fn validate(&self, view: &Fork) -> Result<u64, ValidateError> {
+        let core_schema = Schema::new(&view);
+        let anchoring_schema = AnchoringSchema::new(&view);
+
+        let id = self.validator().0 as usize;
+        let actual_cfg = core_schema.actual_configuration();
+        // Verify from field
+        if actual_cfg.validator_keys.get(id).map(|k| k.service_key) != Some(*self.from()) {
+            return Err(ValidateError::MsgFromNonValidator);
+        }
+
+        // The mark uses the consensus time instead of the observed one.
+        let schedule = anchoring_schema.actual_anchoring_config().schedule;
+        let time = anchoring_schema
+            .consensus_time()
+            .ok_or(ValidateError::TimeMarkNotScheduled)?;
+        let epoch = schedule
+            .time_epoch(time)
+            .ok_or(ValidateError::TimeMarkNotScheduled)?;
+        if epoch != self.epoch() {
+            return Err(ValidateError::TimeMarkWithWrongEpoch);
+        }
+        if let Some(last) = anchoring_schema.time_marks().last() {
+            if schedule.time_epoch(last.time()) >= Some(epoch) {
+                return Err(ValidateError::TimeMarkExists);
+            }
+        }
+        Ok(time)
+    }
+}
+
+impl Transaction for MsgAnchoringTimeMark {
+    fn verify(&self) -> bool {
+        self.verify_signature(self.from())
+    }
+
+    fn execute(&self, fork: &mut Fork) -> ExecutionResult {
+        let time = self.validate(fork)?;
+        // The block that contains this transaction is not committed yet.
+        let height = Schema::new(&fork).block_hashes_by_height().len();
+        AnchoringSchema::new(fork)
+            .time_marks_mut()
+            .push(TimeMark::new(height, time));
+        Ok(())
+    }
+}
+
+impl MsgAnchoringBlocksMmr {
+    pub fn validate(&self, view: &Fork) -> Result<(), ValidateError> {
+        let id = self.validator().0 as usize;
+        let actual_cfg = Schema::new(&view).actual_configuration();
+        // Verify from field
+        if actual_cfg.validator_keys.get(id).map(|k| k.service_key) != Some(*self.from()) {
+            return Err(ValidateError::MsgFromNonValidator);
+        }
+        Ok(())
+    }
+}
+
+impl Transaction for MsgAnchoringBlocksMmr {
+    fn verify(&self) -> bool {
+        self.verify_signature(self.from())
+    }
+
+    fn execute(&self, fork: &mut Fork) -> ExecutionResult {
+        self.validate(fork)?;
+        AnchoringSchema::new(fork).update_blocks_mmr();
+        Ok(())
+    }
+}
+
 fn verify_anchoring_tx_prev_hash<T>(
     tx: &AnchoringTx,
     anchoring_schema: &AnchoringSchema<T>,
@@ -217,18 +409,63 @@ where
     }
 }
 
-fn verify_anchoring_tx_payload<T>(tx: &AnchoringTx, schema: &Schema<T>) -> Result<(), ValidateError>
-where
-    T: AsRef<Snapshot>,
-{
-    let payload = tx.payload();
-    if schema.block_hashes_by_height().get(payload.block_height.0) == Some(payload.block_hash) {
+fn verify_anchoring_tx_payload(tx: &AnchoringTx, view: &Fork) -> Result<(), ValidateError> {
+    if payload_is_correct(&tx.payload(), view) {
         Ok(())
     } else {
         Err(ValidateError::MsgWithIncorrectPayload)
     }
 }
 
+/// Checks that the payload has the prefix of the previous, actual or following
+/// configuration and refers to the existing block. The state hash, the network
+/// identifier, the blocks root and the notary root are checked only if the payload
+/// contains them.
+pub(crate) fn payload_is_correct<T>(payload: &Payload, view: T) -> bool
+where
+    T: AsRef<Snapshot>,
+{
+    let anchoring_schema = AnchoringSchema::new(&view);
+    let prefix_is_known = anchoring_schema
+        .previous_anchoring_config()
+        .into_iter()
+        .chain(Some(anchoring_schema.actual_anchoring_config()))
+        .chain(anchoring_schema.following_anchoring_config())
+        .any(|cfg| cfg.payload_prefix == payload.prefix);
+    if !prefix_is_known {
+        return false;
+    }
+
+    let schema = Schema::new(&view);
+    let block_hash = payload.block_hash;
+    if schema.block_hashes_by_height().get(payload.block_height.0) != Some(block_hash) {
+        return false;
+    }
+    if let Some(ref state_hash) = payload.state_hash {
+        match schema.blocks().get(&block_hash) {
+            Some(ref block) if block.state_hash() == state_hash => {}
+            _ => return false,
+        }
+    }
+    if let Some(network_id) = payload.network_id {
+        let genesis_hash = schema.block_hash_by_height(Height::zero());
+        if genesis_hash.map(|hash| NetworkId::from_genesis_hash(&hash)) != Some(network_id) {
+            return false;
+        }
+    }
+    if let Some(blocks_root) = payload.blocks_root {
+        if anchoring_schema.blocks_root(payload.block_height) != Some(blocks_root) {
+            return false;
+        }
+    }
+    if let Some(notary_root) = payload.notary_root {
+        if anchoring_schema.notary_root(payload.block_height) != notary_root {
+            return false;
+        }
+    }
+    true
+}
+
 fn verify_funding_tx(tx: &FundingTx, anchoring_cfg: &AnchoringConfig) -> Result<(), ValidateError> {
     if tx == anchoring_cfg.funding_tx() {
         Ok(())
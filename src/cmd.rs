@@ -17,12 +17,20 @@
 //! we can use in `anchoring` bootstrapping process.
 //!
 use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use bitcoin::network::constants::Network;
 use bitcoin::util::base58::ToBase58;
+use clap::{App, Arg, ArgMatches, SubCommand};
 use failure;
-use toml::Value;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use toml::{self, Value};
 
 use exonum::helpers::fabric::{keys, Argument, CommandExtension, CommandName, Context,
                               ServiceFactory};
@@ -33,7 +41,13 @@ use exonum::encoding::serialize::FromHex;
 use service::AnchoringService;
 use super::{gen_btc_keypair, AnchoringConfig, AnchoringNodeConfig, AnchoringRpcConfig};
 use details::btc::{self, PrivateKey, PublicKey};
-use details::rpc::{BitcoinRelay, RpcClient};
+use details::btc::chain::ChainParams;
+use details::btc::payload::{is_valid_prefix, MAX_PAYLOAD_PREFIX_LEN};
+use details::btc::transactions::BitcoinTx;
+use details::keystore::{read_passphrase, read_secret, EncryptedKey, KdfParams,
+                        KEYSTORE_PASSPHRASE_ENV};
+use details::sweep::{SweepProposal, SweepSignature};
+use details::rpc::UtxoDiscovery;
 use bitcoin::util::base58::FromBase58;
 use observer::AnchoringObserverConfig;
 
@@ -75,6 +89,31 @@ impl CommandExtension for GenerateNodeConfig {
                 "anchoring-password",
                 false,
             ),
+            Argument::new_named(
+                "ANCHORING_RPC_CREDENTIALS_FILE",
+                false,
+                "Path to the file with bitcoind credentials in the `user:password` format.",
+                None,
+                "anchoring-credentials-file",
+                false,
+            ),
+            Argument::new_named(
+                "ANCHORING_RPC_COOKIE_FILE",
+                false,
+                "Path to the bitcoind `.cookie` file.",
+                None,
+                "anchoring-cookie-file",
+                false,
+            ),
+            Argument::new_named(
+                "ANCHORING_RPC_UTXO_DISCOVERY",
+                false,
+                "Method to discover the anchoring address outputs \
+                 (wallet, descriptors or scantxoutset).",
+                None,
+                "anchoring-utxo-discovery",
+                false,
+            ),
             Argument::new_named(
                 "ANCHORING_OBSERVER_CHECK_INTERVAL",
                 false,
@@ -84,6 +123,15 @@ impl CommandExtension for GenerateNodeConfig {
                 "anchoring-observer-check-interval",
                 false,
             ),
+            Argument::new_named(
+                "ANCHORING_KEYSTORE",
+                false,
+                "Encrypt anchoring private key by the passphrase from the prompt \
+                 (`prompt`) or from the given file descriptor",
+                None,
+                "anchoring-keystore",
+                false,
+            ),
         ]
     }
 
@@ -93,6 +141,19 @@ impl CommandExtension for GenerateNodeConfig {
             .expect("Expected ANCHORING_RPC_HOST");
         let user = context.arg("ANCHORING_RPC_USER").ok();
         let passwd = context.arg("ANCHORING_RPC_PASSWD").ok();
+        let credentials_file = context
+            .arg::<String>("ANCHORING_RPC_CREDENTIALS_FILE")
+            .ok()
+            .map(PathBuf::from);
+        let cookie_file = context
+            .arg::<String>("ANCHORING_RPC_COOKIE_FILE")
+            .ok()
+            .map(PathBuf::from);
+        let utxo_discovery = context
+            .arg::<String>("ANCHORING_RPC_UTXO_DISCOVERY")
+            .ok()
+            .map(|method| UtxoDiscovery::from_str(&method).expect("Wrong utxo discovery method"))
+            .unwrap_or_default();
         let observer_check_interval = context.arg("ANCHORING_OBSERVER_CHECK_INTERVAL").ok();
 
         let config = context.get(keys::COMMON_CONFIG).unwrap();
@@ -110,7 +171,19 @@ impl CommandExtension for GenerateNodeConfig {
             _ => panic!("Wrong network type"),
         };
 
+        let keystore = context.arg::<String>("ANCHORING_KEYSTORE").ok();
         let (p, s) = gen_btc_keypair(network);
+        // The anchoring address is not known until all public keys are collected,
+        // so the encrypted key is bound to the public key instead.
+        let (sec_key_name, sec_key) = match keystore {
+            Some(keystore) => {
+                let passphrase = read_new_passphrase(parse_passphrase_fd(&keystore)?)?;
+                let key =
+                    EncryptedKey::encrypt(&p.to_string(), &s, &passphrase, KdfParams::default())?;
+                ("anchoring_encrypted_sec_key", Value::try_from(key)?)
+            }
+            None => ("anchoring_sec_key", Value::try_from(s.to_base58check())?),
+        };
         let mut services_public_configs = context
             .get(keys::SERVICES_PUBLIC_CONFIGS)
             .unwrap_or_default();
@@ -127,6 +200,10 @@ impl CommandExtension for GenerateNodeConfig {
             host,
             username: user,
             password: passwd,
+            credentials_file,
+            cookie_file,
+            utxo_discovery,
+            ..Default::default()
         };
         let observer_config = {
             let mut observer_config = AnchoringObserverConfig::default();
@@ -142,10 +219,7 @@ impl CommandExtension for GenerateNodeConfig {
             .unwrap_or_default();
         services_secret_configs.extend(
             vec![
-                (
-                    "anchoring_sec_key".to_owned(),
-                    Value::try_from(s.to_base58check()).unwrap(),
-                ),
+                (sec_key_name.to_owned(), sec_key),
                 (
                     "anchoring_pub_key".to_owned(),
                     Value::try_from(p.to_string()).unwrap(),
@@ -204,6 +278,14 @@ impl CommandExtension for GenerateCommonConfig {
                 "anchoring-network",
                 false,
             ),
+            Argument::new_named(
+                "ANCHORING_PAYLOAD_PREFIX",
+                false,
+                "Prefix of the anchoring transactions payload, `EXONUM` by default.",
+                None,
+                "anchoring-payload-prefix",
+                false,
+            ),
         ]
     }
 
@@ -219,6 +301,15 @@ impl CommandExtension for GenerateCommonConfig {
         let network = context
             .arg::<String>("ANCHORING_NETWORK")
             .expect("No network type found.");
+        let payload_prefix = context.arg::<String>("ANCHORING_PAYLOAD_PREFIX").ok();
+        if let Some(ref prefix) = payload_prefix {
+            if !is_valid_prefix(prefix.as_bytes()) {
+                return Err(failure::err_msg(format!(
+                    "Payload prefix must consist of 1 to {} ASCII alphanumeric characters",
+                    MAX_PAYLOAD_PREFIX_LEN
+                )));
+            }
+        }
 
         let mut values: BTreeMap<String, Value> = context.get(keys::SERVICES_CONFIG).expect(
             "Expected services_config \
@@ -242,6 +333,12 @@ impl CommandExtension for GenerateCommonConfig {
                 ),
             ].into_iter(),
         );
+        if let Some(prefix) = payload_prefix {
+            values.insert(
+                "anchoring_payload_prefix".to_owned(),
+                Value::try_from(prefix).unwrap(),
+            );
+        }
         context.set(keys::SERVICES_CONFIG, values);
         Ok(context)
     }
@@ -268,6 +365,15 @@ impl CommandExtension for Finalize {
                 "anchoring-create-funding-tx",
                 false,
             ),
+            Argument::new_named(
+                "ANCHORING_KEYSTORE",
+                false,
+                "Encrypt anchoring private key by the passphrase from the prompt \
+                 (`prompt`) or from the given file descriptor",
+                None,
+                "anchoring-keystore",
+                false,
+            ),
         ]
     }
 
@@ -279,12 +385,34 @@ impl CommandExtension for Finalize {
 
         let funding_txid = context.arg::<String>("ANCHORING_FUNDING_TXID").ok();
         let create_funding_tx_with_amount = context.arg::<u64>("ANCHORING_CREATE_FUNDING_TX").ok();
+        let keystore = context.arg::<String>("ANCHORING_KEYSTORE").ok();
         // Local config section
-        let sec_key: String = services_secret_configs
-            .get("anchoring_sec_key")
-            .expect("Anchoring secret key not found")
-            .clone()
-            .try_into()?;
+        let (priv_key, passphrase) = match services_secret_configs
+            .get("anchoring_encrypted_sec_key")
+        {
+            // The encrypted key is encrypted again by the same passphrase for the address.
+            Some(key) => {
+                let key: EncryptedKey = key.clone().try_into()?;
+                let fd = match keystore {
+                    Some(keystore) => parse_passphrase_fd(&keystore)?,
+                    None => None,
+                };
+                let passphrase = read_passphrase(fd, "Keystore passphrase: ")?;
+                (key.decrypt(&passphrase)?, Some(passphrase))
+            }
+            None => {
+                let sec_key: String = services_secret_configs
+                    .get("anchoring_sec_key")
+                    .expect("Anchoring secret key not found")
+                    .clone()
+                    .try_into()?;
+                let passphrase = match keystore {
+                    Some(keystore) => Some(read_new_passphrase(parse_passphrase_fd(&keystore)?)?),
+                    None => None,
+                };
+                (PrivateKey::from_base58check(&sec_key)?, passphrase)
+            }
+        };
         let pub_key: String = services_secret_configs
             .get("anchoring_pub_key")
             .expect("Anchoring public key not found")
@@ -325,6 +453,13 @@ impl CommandExtension for Finalize {
             .expect("Anchoring fee not found")
             .clone()
             .try_into()?;
+        let payload_prefix: Option<String> = match common_config
+            .services_config
+            .get("anchoring_payload_prefix")
+        {
+            Some(prefix) => Some(prefix.clone().try_into()?),
+            None => None,
+        };
 
         let network = match network.as_str() {
             "testnet" => Network::Testnet,
@@ -332,7 +467,6 @@ impl CommandExtension for Finalize {
             _ => panic!("Wrong network type"),
         };
 
-        let priv_key: PrivateKey = PrivateKey::from_base58check(&sec_key).unwrap();
         //TODO: validate config keys
         let _pub_key = PublicKey::from_hex(&pub_key).unwrap();
         let pub_keys: Vec<_> = public_config_list
@@ -347,7 +481,7 @@ impl CommandExtension for Finalize {
                 PublicKey::from_hex(&key).unwrap()
             })
             .collect();
-        let client = RpcClient::from(rpc.clone());
+        let client = rpc.clone().into_relay(ChainParams::bitcoin(network));
         let mut anchoring_config = AnchoringNodeConfig::new(Some(rpc));
         anchoring_config.observer = observer;
 
@@ -374,10 +508,17 @@ impl CommandExtension for Finalize {
         anchoring_config
             .private_keys
             .insert(address.to_base58check(), priv_key.clone());
+        if let Some(passphrase) = passphrase {
+            anchoring_config.encrypt_keys(&passphrase, KdfParams::default())?;
+        }
 
         genesis_cfg.fee = fee;
         genesis_cfg.frequency = frequency;
         genesis_cfg.utxo_confirmations = utxo_confirmations;
+        if let Some(prefix) = payload_prefix {
+            genesis_cfg.payload_prefix = prefix;
+        }
+        genesis_cfg.validate()?;
 
         node_config.services_configs.insert(
             "anchoring_service".to_owned(),
@@ -391,6 +532,334 @@ impl CommandExtension for Finalize {
     }
 }
 
+/// Command that sweeps the anchoring funds to an arbitrary address, which allows
+/// to recover the funds when the network is decommissioned.
+///
+/// The sweep is made in three steps:
+///
+/// - `create` builds the unsigned transaction that spends the anchoring output of the lect
+///   and saves it into the proposal file.
+/// - `sign` signs the proposal by the private key of the key holder, so each holder
+///   is able to sign it offline and send back only the signature file.
+/// - `finalize` assembles the signed transaction from the signature files and prints
+///   its raw hex, which can be sent by `bitcoin-cli sendrawtransaction`.
+#[derive(Debug)]
+pub struct SweepCommand;
+
+impl SweepCommand {
+    /// Returns the `clap` application of the command.
+    pub fn app<'a, 'b>() -> App<'a, 'b> {
+        App::new("anchoring-sweep")
+            .about("Sweeps the anchoring funds to the given address")
+            .subcommand(
+                SubCommand::with_name("create")
+                    .about("Creates the unsigned sweep transaction")
+                    .arg(
+                        Arg::with_name("CONFIG")
+                            .long("config")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Path to the actual anchoring configuration in json"),
+                    )
+                    .arg(
+                        Arg::with_name("LECT")
+                            .long("lect")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Raw hex of the latest anchoring transaction"),
+                    )
+                    .arg(
+                        Arg::with_name("TO")
+                            .long("to")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Address that receives the funds"),
+                    )
+                    .arg(
+                        Arg::with_name("FEE")
+                            .long("fee")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Fee of the sweep transaction in satoshis"),
+                    )
+                    .arg(
+                        Arg::with_name("RECOVERY")
+                            .long("recovery")
+                            .help("Spend the time-locked recovery path"),
+                    )
+                    .arg(
+                        Arg::with_name("OUTPUT")
+                            .long("output")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Path to the proposal file"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("sign")
+                    .about("Signs the sweep transaction by the private key")
+                    .arg(
+                        Arg::with_name("PROPOSAL")
+                            .long("proposal")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Path to the proposal file"),
+                    )
+                    .arg(
+                        Arg::with_name("PRIVATE_KEY_FILE")
+                            .long("private-key-file")
+                            .takes_value(true)
+                            .conflicts_with("PRIVATE_KEY_FD")
+                            .help("Path to the file with the anchoring private key in WIF"),
+                    )
+                    .arg(
+                        Arg::with_name("PRIVATE_KEY_FD")
+                            .long("private-key-fd")
+                            .takes_value(true)
+                            .help(
+                                "File descriptor from which the anchoring private key \
+                                 in WIF is read, e.g. 0 for stdin",
+                            ),
+                    )
+                    .arg(
+                        Arg::with_name("OUTPUT")
+                            .long("output")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Path to the signature file"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("finalize")
+                    .about("Prints the signed sweep transaction")
+                    .arg(
+                        Arg::with_name("PROPOSAL")
+                            .long("proposal")
+                            .takes_value(true)
+                            .required(true)
+                            .help("Path to the proposal file"),
+                    )
+                    .arg(
+                        Arg::with_name("SIGNATURES")
+                            .multiple(true)
+                            .required(true)
+                            .help("Paths to the signature files"),
+                    ),
+            )
+    }
+
+    /// Executes the command with the given arguments.
+    pub fn execute(matches: &ArgMatches) -> Result<(), failure::Error> {
+        match matches.subcommand() {
+            ("create", Some(matches)) => {
+                let cfg: AnchoringConfig = read_json(matches.value_of("CONFIG").unwrap())?;
+                let lect = BitcoinTx::from_hex(matches.value_of("LECT").unwrap())?;
+                let to = btc::Address::from_base58check(matches.value_of("TO").unwrap())
+                    .map_err(|e| failure::err_msg(format!("Wrong address: {:?}", e)))?;
+                let fee = matches.value_of("FEE").unwrap().parse::<u64>()?;
+                let recovery = matches.is_present("RECOVERY");
+
+                let proposal = SweepProposal::new(&cfg, &lect, &to, fee, recovery)?;
+                write_json(matches.value_of("OUTPUT").unwrap(), &proposal)?;
+                println!("Created sweep transaction with txid {}", proposal.tx.txid());
+            }
+            ("sign", Some(matches)) => {
+                let proposal: SweepProposal = read_json(matches.value_of("PROPOSAL").unwrap())?;
+                let priv_key = read_private_key(matches)?;
+
+                let signature = proposal.sign(&priv_key)?;
+                write_json(matches.value_of("OUTPUT").unwrap(), &signature)?;
+            }
+            ("finalize", Some(matches)) => {
+                let proposal: SweepProposal = read_json(matches.value_of("PROPOSAL").unwrap())?;
+                let signatures = matches
+                    .values_of("SIGNATURES")
+                    .unwrap()
+                    .map(read_json::<SweepSignature>)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let tx = proposal.finalize(&signatures)?;
+                println!("{}", tx.to_hex());
+            }
+            _ => return Err(failure::err_msg(matches.usage().to_owned())),
+        }
+        Ok(())
+    }
+}
+
+// Reads the private key of the sweep signer from the file, the file descriptor or the prompt,
+// so the key does not appear in the command line arguments.
+fn read_private_key(matches: &ArgMatches) -> Result<PrivateKey, failure::Error> {
+    let wif = if let Some(path) = matches.value_of("PRIVATE_KEY_FILE") {
+        let mut wif = String::new();
+        File::open(path)?.read_to_string(&mut wif)?;
+        wif
+    } else {
+        let fd = match matches.value_of("PRIVATE_KEY_FD") {
+            Some(fd) => Some(fd.parse::<i32>()?),
+            None => None,
+        };
+        read_secret(fd, "Anchoring private key in WIF: ")?
+    };
+    PrivateKey::from_base58check(wif.trim())
+        .map_err(|e| failure::err_msg(format!("Wrong private key: {:?}", e)))
+}
+
+/// Command that encrypts the anchoring private keys of the existing node configuration
+/// or decrypts them back.
+///
+/// - `encrypt` moves the `private_keys` into the encrypted `keystore`. If the keystore
+///   already contains the keys, they are unlocked first, so the new keys, e.g. for the
+///   following anchoring address, are encrypted by the same passphrase.
+/// - `decrypt` moves the keys from the `keystore` back into the `private_keys`.
+///
+/// The passphrase is read from the `ANCHORING_KEYSTORE_PASSPHRASE` environment variable,
+/// the `--passphrase-fd` file descriptor or the prompt.
+#[derive(Debug)]
+pub struct KeystoreCommand;
+
+impl KeystoreCommand {
+    /// Returns the `clap` application of the command.
+    pub fn app<'a, 'b>() -> App<'a, 'b> {
+        let config = Arg::with_name("CONFIG")
+            .long("config")
+            .takes_value(true)
+            .required(true)
+            .help("Path to the node configuration in toml");
+        let passphrase_fd = Arg::with_name("PASSPHRASE_FD")
+            .long("passphrase-fd")
+            .takes_value(true)
+            .help("File descriptor from which the passphrase is read");
+        App::new("anchoring-keystore")
+            .about("Encrypts or decrypts the anchoring private keys of the node")
+            .subcommand(
+                SubCommand::with_name("encrypt")
+                    .about("Encrypts the private keys by the passphrase")
+                    .arg(config.clone())
+                    .arg(passphrase_fd.clone())
+                    .arg(
+                        Arg::with_name("KDF_LOG_N")
+                            .long("kdf-log-n")
+                            .takes_value(true)
+                            .help("Logarithm of the scrypt cost, 15 by default"),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("decrypt")
+                    .about("Decrypts the private keys back into the configuration")
+                    .arg(config)
+                    .arg(passphrase_fd),
+            )
+    }
+
+    /// Executes the command with the given arguments.
+    pub fn execute(matches: &ArgMatches) -> Result<(), failure::Error> {
+        let (name, matches) = match matches.subcommand() {
+            (name, Some(matches)) => (name, matches),
+            _ => return Err(failure::err_msg(matches.usage().to_owned())),
+        };
+        let path = matches.value_of("CONFIG").unwrap();
+        let fd = match matches.value_of("PASSPHRASE_FD") {
+            Some(fd) => Some(fd.parse::<i32>()?),
+            None => None,
+        };
+        let mut config = read_toml(path)?;
+        let mut service: AnchoringServiceConfig = config
+            .get("services_configs")
+            .and_then(|configs| configs.get("anchoring_service"))
+            .cloned()
+            .ok_or_else(|| failure::err_msg("Anchoring service configuration not found"))?
+            .try_into()?;
+
+        match name {
+            "encrypt" => {
+                let mut kdf = KdfParams::default();
+                if let Some(log_n) = matches.value_of("KDF_LOG_N") {
+                    kdf.log_n = log_n.parse()?;
+                }
+                let passphrase = if service.node.keystore.keys.is_empty() {
+                    read_new_passphrase(fd)?
+                } else {
+                    let passphrase = read_passphrase(fd, "Keystore passphrase: ")?;
+                    service.node.decrypt_keys(&passphrase)?;
+                    passphrase
+                };
+                service.node.encrypt_keys(&passphrase, kdf)?;
+            }
+            "decrypt" => {
+                let passphrase = read_passphrase(fd, "Keystore passphrase: ")?;
+                service.node.decrypt_keys(&passphrase)?;
+            }
+            _ => return Err(failure::err_msg(matches.usage().to_owned())),
+        }
+
+        config
+            .as_table_mut()
+            .and_then(|config| config.get_mut("services_configs"))
+            .and_then(Value::as_table_mut)
+            .unwrap()
+            .insert("anchoring_service".to_owned(), Value::try_from(service)?);
+        write_toml(path, &config)
+    }
+}
+
+// Parses the passphrase source of the `--anchoring-keystore` argument.
+fn parse_passphrase_fd(source: &str) -> Result<Option<i32>, failure::Error> {
+    if source == "prompt" {
+        Ok(None)
+    } else {
+        Ok(Some(source.parse::<i32>()?))
+    }
+}
+
+// Reads the passphrase for the new keystore, the prompted passphrase is asked twice.
+fn read_new_passphrase(fd: Option<i32>) -> Result<String, failure::Error> {
+    let passphrase = read_passphrase(fd, "New keystore passphrase: ")?;
+    if fd.is_none() && env::var(KEYSTORE_PASSPHRASE_ENV).is_err() {
+        let repeated = read_passphrase(None, "Repeat the passphrase: ")?;
+        if repeated != passphrase {
+            return Err(failure::err_msg("Passphrases do not match"));
+        }
+    }
+    if passphrase.is_empty() {
+        return Err(failure::err_msg("Keystore passphrase is empty"));
+    }
+    Ok(passphrase)
+}
+
+fn read_toml(path: &str) -> Result<Value, failure::Error> {
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    Ok(toml::from_str(&content)?)
+}
+
+// Replaces the file atomically, so the keys are not lost if the tool is killed while writing.
+// The temporary file gets the permissions of the replaced one.
+fn write_toml(path: &str, value: &Value) -> Result<(), failure::Error> {
+    let content = toml::to_string(value)?;
+    let tmp_path = Path::new(path).with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn read_json<T: DeserializeOwned>(path: &str) -> Result<T, failure::Error> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+fn write_json<T: Serialize>(path: &str, value: &T) -> Result<(), failure::Error> {
+    let mut file = File::create(path)?;
+    serde_json::to_writer_pretty(&mut file, value)?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
 /// An anchoring service creator for the `NodeBuilder`.
 #[derive(Debug)]
 pub struct AnchoringServiceFactory;
@@ -412,9 +881,8 @@ impl ServiceFactory for AnchoringServiceFactory {
                 .clone()
                 .try_into()
                 .unwrap();
-        Box::new(AnchoringService::new(
-            anchoring_config.genesis,
-            anchoring_config.node,
-        ))
+        let mut node = anchoring_config.node;
+        node.unlock_keys().expect("Unable to unlock the anchoring keystore");
+        Box::new(AnchoringService::new(anchoring_config.genesis, node))
     }
 }
@@ -13,11 +13,26 @@
 // limitations under the License.
 
 use bitcoin::network::constants::Network;
+use bitcoin::util::address::Type;
+use bitcoin::util::base58::ToBase58;
 
+use super::chain::ChainParams;
 use super::types::{Address, RawAddress, RawScript};
 
 impl Address {
     pub fn from_script(script: &RawScript, network: Network) -> Address {
         RawAddress::from_script(network, script).into()
     }
+
+    /// Returns the base58check representation of the address with the version byte
+    /// of the given chain.
+    pub fn to_chain_string(&self, chain: &ChainParams) -> String {
+        let version = match self.0.ty {
+            Type::PubkeyHash => chain.p2pkh_prefix,
+            Type::ScriptHash => chain.p2sh_prefix,
+        };
+        let mut data = vec![version];
+        data.extend_from_slice(&self.0.hash[..]);
+        data.to_base58check()
+    }
 }
@@ -0,0 +1,110 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parameters of the bitcoin-like chains that can be used for anchoring.
+
+use bitcoin::network::constants::Network;
+
+/// Parameters of the bitcoin-like chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainParams {
+    /// Name of the chain in the anchoring configuration.
+    pub name: &'static str,
+    /// Bitcoin network with the same keys and transactions encoding.
+    pub network: Network,
+    /// Version byte of the pay-to-pubkey-hash addresses.
+    pub p2pkh_prefix: u8,
+    /// Version byte of the pay-to-script-hash addresses.
+    pub p2sh_prefix: u8,
+    /// The minimum value of the relayed output in satoshis.
+    pub dust_limit: u64,
+    /// The minimum relay fee in satoshis per 1000 bytes of the transaction.
+    pub min_relay_fee: u64,
+    /// Target interval between blocks in seconds. The confirmations of the chains
+    /// with the different intervals provide different security, so the
+    /// `utxo_confirmations` should be chosen accordingly.
+    pub block_interval: u64,
+}
+
+/// Bitcoin mainnet.
+pub const BITCOIN: ChainParams = ChainParams {
+    name: "bitcoin",
+    network: Network::Bitcoin,
+    p2pkh_prefix: 0x00,
+    p2sh_prefix: 0x05,
+    dust_limit: 546,
+    min_relay_fee: 1000,
+    block_interval: 600,
+};
+
+/// Bitcoin testnet.
+pub const BITCOIN_TESTNET: ChainParams = ChainParams {
+    name: "testnet",
+    network: Network::Testnet,
+    p2pkh_prefix: 0x6f,
+    p2sh_prefix: 0xc4,
+    dust_limit: 546,
+    min_relay_fee: 1000,
+    block_interval: 600,
+};
+
+/// Litecoin mainnet.
+pub const LITECOIN: ChainParams = ChainParams {
+    name: "litecoin",
+    network: Network::Bitcoin,
+    p2pkh_prefix: 0x30,
+    p2sh_prefix: 0x32,
+    dust_limit: 54_600,
+    min_relay_fee: 100_000,
+    block_interval: 150,
+};
+
+/// Litecoin testnet.
+pub const LITECOIN_TESTNET: ChainParams = ChainParams {
+    name: "litecoin_testnet",
+    network: Network::Testnet,
+    p2pkh_prefix: 0x6f,
+    p2sh_prefix: 0x3a,
+    dust_limit: 54_600,
+    min_relay_fee: 100_000,
+    block_interval: 150,
+};
+
+/// All known chains.
+pub const KNOWN_CHAINS: &[ChainParams] = &[BITCOIN, BITCOIN_TESTNET, LITECOIN, LITECOIN_TESTNET];
+
+impl ChainParams {
+    /// Returns the parameters of the chain with the given `name`.
+    pub fn by_name(name: &str) -> Option<ChainParams> {
+        KNOWN_CHAINS.iter().find(|chain| chain.name == name).cloned()
+    }
+
+    /// Returns the parameters of the bitcoin chain for the given `network`.
+    pub fn bitcoin(network: Network) -> ChainParams {
+        match network {
+            Network::Bitcoin => BITCOIN,
+            Network::Testnet => BITCOIN_TESTNET,
+        }
+    }
+
+    /// Returns `true` if the output with the given `value` is too small to be relayed.
+    pub fn is_dust(&self, value: u64) -> bool {
+        value < self.dust_limit
+    }
+
+    /// Returns the minimum fee that allows to relay the transaction of the given `size` in bytes.
+    pub fn min_fee(&self, size: usize) -> u64 {
+        (size as u64 * self.min_relay_fee + 999) / 1000
+    }
+}
@@ -19,6 +19,7 @@ mod redeem_script;
 mod address;
 mod private_key;
 mod public_key;
+pub mod chain;
 pub mod payload;
 pub mod transactions;
 
@@ -12,29 +12,51 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::fmt;
+
 use byteorder::{ByteOrder, LittleEndian};
 
 use bitcoin::blockdata::script::{Builder, Instruction, Script};
 use bitcoin::blockdata::opcodes::All;
 
 use exonum::crypto::Hash;
+use exonum::encoding::serialize::{FromHex, FromHexError, ToHex};
 use exonum::helpers::Height;
 
 use details::btc;
 
-const PAYLOAD_PREFIX: &[u8] = b"EXONUM";
-const PAYLOAD_HEADER_LEN: usize = 8;
 const PAYLOAD_V1: u8 = 1;
 const PAYLOAD_V1_KIND_REGULAR: u8 = 0;
 const PAYLOAD_V1_KIND_RECOVER: u8 = 1;
+const PAYLOAD_V2: u8 = 2;
+const PAYLOAD_V2_KIND_REGULAR: u8 = 0;
+const PAYLOAD_V2_KIND_RECOVER: u8 = 1;
+const PAYLOAD_V2_KIND_BLOCKS_ROOT: u8 = 2;
+const PAYLOAD_V2_KIND_NOTARY_ROOT: u8 = 3;
+const PAYLOAD_V1_REGULAR_DATA_LEN: usize = 40;
+const PAYLOAD_V1_RECOVER_DATA_LEN: usize = 72;
+const PAYLOAD_V2_DATA_LEN: usize = 72;
+
+/// Length of the Exonum network identifier in bytes.
+pub const NETWORK_ID_LEN: usize = 4;
+/// Default prefix of the anchoring transactions payload.
+pub const DEFAULT_PAYLOAD_PREFIX: &str = "EXONUM";
+/// Maximum length of the payload prefix, the v.2 payload with such prefix occupies
+/// all 80 bytes of the `OP_RETURN` output.
+pub const MAX_PAYLOAD_PREFIX_LEN: usize = 6;
 
 /// Anchoring transaction payload.
 ///
+/// Each payload starts with the ASCII-encoded prefix of 1 to 6 alphanumeric characters,
+/// `EXONUM` by default. The prefix length is determined by the total payload length,
+/// since the data length is fixed for each version and kind.
+/// The positions below are given for the 6 characters prefix.
+///
 /// Data layout in `OP_RETURN` script for `Payload` v.1:
 ///
 /// | Position in bytes 	| Description                   	                |
 /// |-------------------	|----------------------------------------------     |
-/// | 0..6               	| ASCII-encoded prefix `EXONUM` 	                |
+/// | 0..6               	| ASCII-encoded prefix, e.g. `EXONUM`               |
 /// | 6                 	| Version byte, currently is 1             	        |
 /// | 7                     | Payload kind: (0 is regular, 1 is recover)        |
 /// | 8..16                 | Block height                                      |
@@ -42,6 +64,29 @@ const PAYLOAD_V1_KIND_RECOVER: u8 = 1;
 /// | 48..80 (Optionally)   | Txid of previous tx chain (only for recover kind) |
 ///
 /// In this way the length of `regular` payload is 48, and for `recover` is 80.
+///
+/// Data layout in `OP_RETURN` script for `Payload` v.2:
+///
+/// | Position in bytes     | Description                                       |
+/// |-----------------------|---------------------------------------------------|
+/// | 0..6                  | ASCII-encoded prefix, e.g. `EXONUM`               |
+/// | 6                     | Version byte, equals to 2                         |
+/// | 7                     | Payload kind                                      |
+/// | 8..12                 | Block height                                      |
+/// | 12..16                | Exonum network identifier                         |
+/// | 16..48                | Block hash                                        |
+/// | 48..80                | Kind specific data                                |
+///
+/// The kind specific data of the v.2 payload is:
+///
+/// - `0` (regular) - state hash of the anchored block;
+/// - `1` (recover) - txid of previous tx chain;
+/// - `2` (blocks root) - root of the Merkle mountain range of all block hashes
+///   up to the anchored height inclusively;
+/// - `3` (notary root) - root of the Merkle mountain range of all notarized digests
+///   up to the anchored height inclusively.
+///
+/// In this way the length of all kinds of the v.2 payload is 80 bytes.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Payload {
     /// Anchored block height
@@ -50,49 +95,138 @@ pub struct Payload {
     pub block_hash: Hash,
     /// `Txid` of previous transactions chain if it has been lost.
     pub prev_tx_chain: Option<btc::TxId>,
+    /// State hash of the anchored block (only for v.2 regular payloads).
+    #[serde(default)]
+    pub state_hash: Option<Hash>,
+    /// Identifier of the anchored Exonum network (only for v.2 payloads).
+    #[serde(default)]
+    pub network_id: Option<NetworkId>,
+    /// Root of the Merkle mountain range of the block hashes (only for v.2 blocks root payloads).
+    #[serde(default)]
+    pub blocks_root: Option<Hash>,
+    /// Root of the Merkle mountain range of the notarized digests (only for v.2 notary root
+    /// payloads).
+    #[serde(default)]
+    pub notary_root: Option<Hash>,
+    /// Prefix of the payload, which identifies the anchoring deployment.
+    #[serde(default = "default_payload_prefix")]
+    pub prefix: String,
 }
 
+/// Reason why the script does not contain a correct anchoring payload.
+#[derive(Debug, Display, Fail, Clone, PartialEq)]
+pub enum PayloadError {
+    /// The script is not an `OP_RETURN` script that starts with the payload prefix,
+    /// so the transaction is not an anchoring one.
+    #[display(fmt = "Script does not contain the anchoring payload")]
+    NotPayload,
+    /// The payload has the version which is unknown to this service.
+    #[display(fmt = "Unknown payload version {}", _0)]
+    UnknownVersion(u8),
+    /// The payload of the known version is malformed.
+    #[display(fmt = "Malformed payload v.{}: {}", _0, _1)]
+    Malformed(u8, String),
+    /// The block height does not fit into the payload of the given version.
+    #[display(fmt = "Block height {} does not fit into the payload v.{}", _0, _1)]
+    HeightOverflow(u64, u8),
+    /// The payload prefix is not 1 to 6 ASCII alphanumeric characters.
+    #[display(fmt = "Invalid payload prefix: {}", _0)]
+    InvalidPrefix(String),
+}
+
+/// Short identifier of the Exonum network, which is the prefix of its genesis block hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetworkId(pub [u8; NETWORK_ID_LEN]);
+
 #[derive(Debug)]
 enum PayloadV1 {
     Regular(Height, Hash),
     Recover(Height, Hash, btc::TxId),
 }
 
-#[derive(Debug, Default)]
-pub struct PayloadV1Builder {
+#[derive(Debug)]
+enum PayloadV2 {
+    Regular(Height, NetworkId, Hash, Hash),
+    Recover(Height, NetworkId, Hash, btc::TxId),
+    BlocksRoot(Height, NetworkId, Hash, Hash),
+    NotaryRoot(Height, NetworkId, Hash, Hash),
+}
+
+#[derive(Debug)]
+pub struct PayloadBuilder {
+    prefix: String,
+    version: u8,
     block_hash: Option<Hash>,
     block_height: Option<Height>,
     prev_tx_chain: Option<btc::TxId>,
+    state_hash: Option<Hash>,
+    network_id: Option<NetworkId>,
+    blocks_root: Option<Hash>,
+    notary_root: Option<Hash>,
+}
+
+implement_serde_hex! {NetworkId}
+
+impl NetworkId {
+    /// Creates network identifier from the genesis block hash.
+    pub fn from_genesis_hash(hash: &Hash) -> NetworkId {
+        let mut id = [0; NETWORK_ID_LEN];
+        id.copy_from_slice(&hash.as_ref()[0..NETWORK_ID_LEN]);
+        NetworkId(id)
+    }
+
+    fn from_slice(s: &[u8]) -> Option<NetworkId> {
+        if s.len() == NETWORK_ID_LEN {
+            let mut id = [0; NETWORK_ID_LEN];
+            id.copy_from_slice(s);
+            Some(NetworkId(id))
+        } else {
+            None
+        }
+    }
+}
+
+impl FromHex for NetworkId {
+    type Error = FromHexError;
+
+    fn from_hex<T: AsRef<[u8]>>(v: T) -> Result<Self, Self::Error> {
+        let bytes = Vec::<u8>::from_hex(v)?;
+        NetworkId::from_slice(&bytes).ok_or(FromHexError::InvalidStringLength)
+    }
 }
 
-pub type PayloadBuilder = PayloadV1Builder;
+impl ToHex for NetworkId {
+    fn write_hex<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        self.0.as_ref().write_hex(w)
+    }
+
+    fn write_hex_upper<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        self.0.as_ref().write_hex_upper(w)
+    }
+}
 
 #[cfg_attr(feature = "cargo-clippy", allow(len_without_is_empty))]
 impl PayloadV1 {
-    fn read(bytes: &[u8]) -> Option<PayloadV1> {
-        let kind = bytes[0];
+    fn read(bytes: &[u8]) -> Result<PayloadV1, PayloadError> {
+        let kind = *bytes.first().ok_or_else(|| malformed(PAYLOAD_V1, "missing kind"))?;
         let data = &bytes[1..];
         match kind {
             PAYLOAD_V1_KIND_REGULAR => {
-                if data.len() != 40 {
-                    return None;
-                }
+                check_data_len(PAYLOAD_V1, kind, data, PAYLOAD_V1_REGULAR_DATA_LEN)?;
 
                 let block_height = LittleEndian::read_u64(&data[0..8]);
                 let block_hash = Hash::from_slice(&data[8..40]).unwrap();
-                Some(PayloadV1::Regular(Height(block_height), block_hash))
+                Ok(PayloadV1::Regular(Height(block_height), block_hash))
             }
             PAYLOAD_V1_KIND_RECOVER => {
-                if data.len() != 72 {
-                    return None;
-                }
+                check_data_len(PAYLOAD_V1, kind, data, PAYLOAD_V1_RECOVER_DATA_LEN)?;
 
                 let block_height = LittleEndian::read_u64(&data[0..8]);
                 let block_hash = Hash::from_slice(&data[8..40]).unwrap();
                 let txid = btc::TxId::from_slice(&data[40..72]).unwrap();
-                Some(PayloadV1::Recover(Height(block_height), block_hash, txid))
+                Ok(PayloadV1::Recover(Height(block_height), block_hash, txid))
             }
-            _ => None,
+            kind => Err(malformed(PAYLOAD_V1, &format!("unknown kind {}", kind))),
         }
     }
 
@@ -118,8 +252,8 @@ impl PayloadV1 {
 
     fn len(&self) -> usize {
         match *self {
-            PayloadV1::Regular(..) => 40,
-            PayloadV1::Recover(..) => 72,
+            PayloadV1::Regular(..) => PAYLOAD_V1_REGULAR_DATA_LEN,
+            PayloadV1::Recover(..) => PAYLOAD_V1_RECOVER_DATA_LEN,
         }
     }
 
@@ -130,88 +264,276 @@ impl PayloadV1 {
         }
     }
 
-    fn into_script(self) -> Script {
-        let len = self.len() + PAYLOAD_HEADER_LEN;
+    fn into_script(self, prefix: &[u8]) -> Script {
+        let len = self.len() + prefix.len() + 2;
         let mut buf = vec![0; len];
         // Serialize header
-        buf[0..6].copy_from_slice(PAYLOAD_PREFIX);
-        buf[6] = PAYLOAD_V1;
-        self.write(&mut buf[7..]);
-        // Build script
-        Builder::new()
-            .push_opcode(All::OP_RETURN)
-            .push_slice(buf.as_ref())
-            .into_script()
+        buf[0..prefix.len()].copy_from_slice(prefix);
+        buf[prefix.len()] = PAYLOAD_V1;
+        self.write(&mut buf[prefix.len() + 1..]);
+        payload_script(&buf)
+    }
+}
+
+impl PayloadV2 {
+    fn read(bytes: &[u8]) -> Result<PayloadV2, PayloadError> {
+        let kind = *bytes.first().ok_or_else(|| malformed(PAYLOAD_V2, "missing kind"))?;
+        let data = &bytes[1..];
+        check_data_len(PAYLOAD_V2, kind, data, PAYLOAD_V2_DATA_LEN)?;
+        let block_height = Height(u64::from(LittleEndian::read_u32(&data[0..4])));
+        let network_id = NetworkId::from_slice(&data[4..8]).unwrap();
+        let block_hash = Hash::from_slice(&data[8..40]).unwrap();
+        match kind {
+            PAYLOAD_V2_KIND_REGULAR => {
+                let state_hash = Hash::from_slice(&data[40..72]).unwrap();
+                Ok(PayloadV2::Regular(
+                    block_height,
+                    network_id,
+                    block_hash,
+                    state_hash,
+                ))
+            }
+            PAYLOAD_V2_KIND_RECOVER => {
+                let txid = btc::TxId::from_slice(&data[40..72]).unwrap();
+                Ok(PayloadV2::Recover(block_height, network_id, block_hash, txid))
+            }
+            PAYLOAD_V2_KIND_BLOCKS_ROOT => {
+                let blocks_root = Hash::from_slice(&data[40..72]).unwrap();
+                Ok(PayloadV2::BlocksRoot(
+                    block_height,
+                    network_id,
+                    block_hash,
+                    blocks_root,
+                ))
+            }
+            PAYLOAD_V2_KIND_NOTARY_ROOT => {
+                let notary_root = Hash::from_slice(&data[40..72]).unwrap();
+                Ok(PayloadV2::NotaryRoot(
+                    block_height,
+                    network_id,
+                    block_hash,
+                    notary_root,
+                ))
+            }
+            kind => Err(malformed(PAYLOAD_V2, &format!("unknown kind {}", kind))),
+        }
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        let (kind, height, network_id, block_hash, data) = match *self {
+            PayloadV2::Regular(height, network_id, block_hash, ref state_hash) => (
+                PAYLOAD_V2_KIND_REGULAR,
+                height,
+                network_id,
+                block_hash,
+                state_hash.as_ref(),
+            ),
+            PayloadV2::Recover(height, network_id, block_hash, ref txid) => (
+                PAYLOAD_V2_KIND_RECOVER,
+                height,
+                network_id,
+                block_hash,
+                txid.as_bytes(),
+            ),
+            PayloadV2::BlocksRoot(height, network_id, block_hash, ref blocks_root) => (
+                PAYLOAD_V2_KIND_BLOCKS_ROOT,
+                height,
+                network_id,
+                block_hash,
+                blocks_root.as_ref(),
+            ),
+            PayloadV2::NotaryRoot(height, network_id, block_hash, ref notary_root) => (
+                PAYLOAD_V2_KIND_NOTARY_ROOT,
+                height,
+                network_id,
+                block_hash,
+                notary_root.as_ref(),
+            ),
+        };
+        debug_assert!(height.0 <= u64::from(u32::max_value()));
+
+        buf[0] = kind;
+        let buf = &mut buf[1..];
+        debug_assert_eq!(buf.len(), PAYLOAD_V2_DATA_LEN);
+        LittleEndian::write_u32(&mut buf[0..4], height.0 as u32);
+        buf[4..8].copy_from_slice(&network_id.0);
+        buf[8..40].copy_from_slice(block_hash.as_ref());
+        buf[40..72].copy_from_slice(data);
+    }
+
+    fn into_script(self, prefix: &[u8]) -> Script {
+        let mut buf = vec![0; PAYLOAD_V2_DATA_LEN + prefix.len() + 2];
+        // Serialize header
+        buf[0..prefix.len()].copy_from_slice(prefix);
+        buf[prefix.len()] = PAYLOAD_V2;
+        self.write(&mut buf[prefix.len() + 1..]);
+        payload_script(&buf)
+    }
+}
+
+impl Default for PayloadBuilder {
+    fn default() -> PayloadBuilder {
+        PayloadBuilder::new()
     }
 }
 
-impl PayloadV1Builder {
-    pub fn new() -> PayloadV1Builder {
-        PayloadV1Builder {
+impl PayloadBuilder {
+    pub fn new() -> PayloadBuilder {
+        PayloadBuilder {
+            prefix: DEFAULT_PAYLOAD_PREFIX.to_owned(),
+            version: PAYLOAD_V1,
             block_hash: None,
             block_height: None,
             prev_tx_chain: None,
+            state_hash: None,
+            network_id: None,
+            blocks_root: None,
+            notary_root: None,
         }
     }
 
-    pub fn block_height(mut self, height: Height) -> PayloadV1Builder {
+    /// Sets the payload prefix, `EXONUM` by default.
+    pub fn prefix(mut self, prefix: &str) -> PayloadBuilder {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// Sets the payload version, the supported versions are 1 and 2.
+    pub fn version(mut self, version: u8) -> PayloadBuilder {
+        self.version = version;
+        self
+    }
+
+    pub fn block_height(mut self, height: Height) -> PayloadBuilder {
         self.block_height = Some(height);
         self
     }
 
-    pub fn block_hash(mut self, hash: Hash) -> PayloadV1Builder {
+    pub fn block_hash(mut self, hash: Hash) -> PayloadBuilder {
         self.block_hash = Some(hash);
         self
     }
 
-    pub fn prev_tx_chain(mut self, txid: Option<btc::TxId>) -> PayloadV1Builder {
+    pub fn prev_tx_chain(mut self, txid: Option<btc::TxId>) -> PayloadBuilder {
         self.prev_tx_chain = txid;
         self
     }
 
-    pub fn into_script(self) -> Script {
+    /// Sets the state hash of the anchored block, it is used only by the v.2 payloads.
+    pub fn state_hash(mut self, hash: Hash) -> PayloadBuilder {
+        self.state_hash = Some(hash);
+        self
+    }
+
+    /// Sets the Exonum network identifier, it is used only by the v.2 payloads.
+    pub fn network_id(mut self, id: NetworkId) -> PayloadBuilder {
+        self.network_id = Some(id);
+        self
+    }
+
+    /// Sets the root of the block hashes Merkle mountain range,
+    /// it is used only by the v.2 payloads instead of the state hash.
+    pub fn blocks_root(mut self, root: Hash) -> PayloadBuilder {
+        self.blocks_root = Some(root);
+        self
+    }
+
+    /// Sets the root of the notarized digests Merkle mountain range,
+    /// it is used only by the v.2 payloads instead of the state hash.
+    pub fn notary_root(mut self, root: Hash) -> PayloadBuilder {
+        self.notary_root = Some(root);
+        self
+    }
+
+    /// Serializes the payload into the `OP_RETURN` script.
+    pub fn into_script(self) -> Result<Script, PayloadError> {
         let block_height = self.block_height.expect("Block height is not set");
         let block_hash = self.block_hash.expect("Block hash is not set");
+        let prefix = self.prefix.as_bytes();
+        if !is_valid_prefix(prefix) {
+            return Err(PayloadError::InvalidPrefix(self.prefix.clone()));
+        }
 
-        let payload = match self.prev_tx_chain {
-            Some(txid) => PayloadV1::Recover(block_height, block_hash, txid),
-            None => PayloadV1::Regular(block_height, block_hash),
+        let script = match self.version {
+            PAYLOAD_V1 => {
+                let payload = match self.prev_tx_chain {
+                    Some(txid) => PayloadV1::Recover(block_height, block_hash, txid),
+                    None => PayloadV1::Regular(block_height, block_hash),
+                };
+                payload.into_script(prefix)
+            }
+            PAYLOAD_V2 => {
+                if block_height.0 > u64::from(u32::max_value()) {
+                    return Err(PayloadError::HeightOverflow(block_height.0, PAYLOAD_V2));
+                }
+                let network_id = self.network_id.expect("Network id is not set");
+                let payload = match (self.prev_tx_chain, self.blocks_root, self.notary_root) {
+                    (Some(txid), _, _) => {
+                        PayloadV2::Recover(block_height, network_id, block_hash, txid)
+                    }
+                    (None, Some(root), _) => {
+                        PayloadV2::BlocksRoot(block_height, network_id, block_hash, root)
+                    }
+                    (None, None, Some(root)) => {
+                        PayloadV2::NotaryRoot(block_height, network_id, block_hash, root)
+                    }
+                    (None, None, None) => {
+                        let state_hash = self.state_hash.expect("State hash is not set");
+                        PayloadV2::Regular(block_height, network_id, block_hash, state_hash)
+                    }
+                };
+                payload.into_script(prefix)
+            }
+            version => return Err(PayloadError::UnknownVersion(version)),
         };
-        payload.into_script()
+        Ok(script)
     }
 }
 
 impl Payload {
     /// Tries to extract payload from given `Script`
     pub fn from_script(script: &Script) -> Option<Payload> {
+        Payload::parse(script).ok()
+    }
+
+    /// Extracts payload from given `Script` or explains why it is not possible.
+    ///
+    /// The payload prefix is the longest sequence of the leading alphanumeric characters,
+    /// so the byte that follows it is treated as the payload version.
+    pub fn parse(script: &Script) -> Result<Payload, PayloadError> {
         let mut instructions = script.into_iter();
-        instructions
-            .next()
-            .and_then(|instr| {
-                if instr == Instruction::Op(All::OP_RETURN) {
-                    instructions.next()
-                } else {
-                    None
-                }
-            })
-            .and_then(|instr| {
-                if let Instruction::PushBytes(bytes) = instr {
-                    if bytes.len() < PAYLOAD_HEADER_LEN {
-                        return None;
-                    }
-                    if &bytes[0..6] != PAYLOAD_PREFIX {
-                        return None;
-                    }
-                    // Parse metadata
-                    let version = bytes[6];
-                    match version {
-                        PAYLOAD_V1 => PayloadV1::read(&bytes[7..]).map(Payload::from),
-                        _ => None,
-                    }
-                } else {
-                    None
-                }
-            })
+        if instructions.next() != Some(Instruction::Op(All::OP_RETURN)) {
+            return Err(PayloadError::NotPayload);
+        }
+        let bytes = match instructions.next() {
+            Some(Instruction::PushBytes(bytes)) => bytes,
+            _ => return Err(PayloadError::NotPayload),
+        };
+
+        let prefix_len = bytes
+            .iter()
+            .take_while(|byte| byte.is_ascii_alphanumeric())
+            .count();
+        if prefix_len == bytes.len() || !is_valid_prefix(&bytes[..prefix_len]) {
+            return Err(PayloadError::NotPayload);
+        }
+        let (prefix, bytes) = bytes.split_at(prefix_len);
+        // Parse metadata
+        let version = bytes[0];
+        // The version is a binary byte, so a printable character after the prefix
+        // means that the output carries an arbitrary text.
+        if version == b' ' || version.is_ascii_graphic() {
+            return Err(PayloadError::NotPayload);
+        }
+        let payload = match version {
+            PAYLOAD_V1 => Payload::from(PayloadV1::read(&bytes[1..])?),
+            PAYLOAD_V2 => Payload::from(PayloadV2::read(&bytes[1..])?),
+            version => return Err(PayloadError::UnknownVersion(version)),
+        };
+        Ok(Payload {
+            prefix: String::from_utf8(prefix.to_vec()).unwrap(),
+            ..payload
+        })
     }
 }
 
@@ -222,16 +544,113 @@ impl From<PayloadV1> for Payload {
                 block_height: height,
                 block_hash: hash,
                 prev_tx_chain: None,
+                state_hash: None,
+                network_id: None,
+                blocks_root: None,
+                notary_root: None,
+                prefix: default_payload_prefix(),
             },
             PayloadV1::Recover(height, hash, txid) => Payload {
                 block_height: height,
                 block_hash: hash,
                 prev_tx_chain: Some(txid),
+                state_hash: None,
+                network_id: None,
+                blocks_root: None,
+                notary_root: None,
+                prefix: default_payload_prefix(),
             },
         }
     }
 }
 
+impl From<PayloadV2> for Payload {
+    fn from(v2: PayloadV2) -> Payload {
+        match v2 {
+            PayloadV2::Regular(height, network_id, hash, state_hash) => Payload {
+                block_height: height,
+                block_hash: hash,
+                prev_tx_chain: None,
+                state_hash: Some(state_hash),
+                network_id: Some(network_id),
+                blocks_root: None,
+                notary_root: None,
+                prefix: default_payload_prefix(),
+            },
+            PayloadV2::Recover(height, network_id, hash, txid) => Payload {
+                block_height: height,
+                block_hash: hash,
+                prev_tx_chain: Some(txid),
+                state_hash: None,
+                network_id: Some(network_id),
+                blocks_root: None,
+                notary_root: None,
+                prefix: default_payload_prefix(),
+            },
+            PayloadV2::BlocksRoot(height, network_id, hash, blocks_root) => Payload {
+                block_height: height,
+                block_hash: hash,
+                prev_tx_chain: None,
+                state_hash: None,
+                network_id: Some(network_id),
+                blocks_root: Some(blocks_root),
+                notary_root: None,
+                prefix: default_payload_prefix(),
+            },
+            PayloadV2::NotaryRoot(height, network_id, hash, notary_root) => Payload {
+                block_height: height,
+                block_hash: hash,
+                prev_tx_chain: None,
+                state_hash: None,
+                network_id: Some(network_id),
+                blocks_root: None,
+                notary_root: Some(notary_root),
+                prefix: default_payload_prefix(),
+            },
+        }
+    }
+}
+
+/// Checks that the payload prefix consists of 1 to 6 ASCII alphanumeric characters.
+pub fn is_valid_prefix(prefix: &[u8]) -> bool {
+    !prefix.is_empty() && prefix.len() <= MAX_PAYLOAD_PREFIX_LEN
+        && prefix.iter().all(u8::is_ascii_alphanumeric)
+}
+
+/// Checks that the payload of the given version can be created by this service.
+pub fn is_supported_version(version: u8) -> bool {
+    version == PAYLOAD_V1 || version == PAYLOAD_V2
+}
+
+pub(crate) fn default_payload_prefix() -> String {
+    DEFAULT_PAYLOAD_PREFIX.to_owned()
+}
+
+fn malformed(version: u8, reason: &str) -> PayloadError {
+    PayloadError::Malformed(version, reason.to_owned())
+}
+
+fn check_data_len(version: u8, kind: u8, data: &[u8], len: usize) -> Result<(), PayloadError> {
+    if data.len() == len {
+        Ok(())
+    } else {
+        let reason = format!(
+            "data length of kind {} is {} bytes instead of {}",
+            kind,
+            data.len(),
+            len
+        );
+        Err(PayloadError::Malformed(version, reason))
+    }
+}
+
+fn payload_script(data: &[u8]) -> Script {
+    Builder::new()
+        .push_opcode(All::OP_RETURN)
+        .push_slice(data)
+        .into_script()
+}
+
 #[cfg(test)]
 mod tests {
     use bitcoin::blockdata::script::Script;
@@ -242,7 +661,8 @@ mod tests {
     use details::btc;
     use details::btc::HexValueEx;
 
-    use super::{Payload, PayloadBuilder};
+    use super::{is_valid_prefix, payload_script, NetworkId, Payload, PayloadBuilder,
+                PayloadError};
 
     #[test]
     fn test_payload_regular_serialize() {
@@ -250,7 +670,8 @@ mod tests {
         let payload_script = PayloadBuilder::new()
             .block_hash(block_hash)
             .block_height(Height(1234))
-            .into_script();
+            .into_script()
+            .unwrap();
 
         assert_eq!(
             payload_script.to_hex(),
@@ -271,6 +692,7 @@ mod tests {
         assert_eq!(payload.block_hash, block_hash);
         assert_eq!(payload.block_height, Height(1234));
         assert_eq!(payload.prev_tx_chain, None);
+        assert_eq!(payload.prefix, "EXONUM");
     }
 
     #[test]
@@ -281,7 +703,8 @@ mod tests {
             .block_hash(block_hash)
             .block_height(Height(1234))
             .prev_tx_chain(Some(prev_txid))
-            .into_script();
+            .into_script()
+            .unwrap();
 
         assert_eq!(
             payload_script.to_hex(),
@@ -308,6 +731,231 @@ mod tests {
         assert_eq!(payload.prev_tx_chain, Some(prev_txid));
     }
 
+    #[test]
+    fn test_payload_v2_regular_serialize() {
+        let block_hash = hash(&[]);
+        let payload_script = PayloadBuilder::new()
+            .version(2)
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .state_hash(hash(&[1]))
+            .network_id(NetworkId::from_genesis_hash(&block_hash))
+            .into_script()
+            .unwrap();
+
+        assert_eq!(
+            payload_script.to_hex(),
+            "6a4c5045584f4e554d0200d2040000e3b0c442e3b0c44298fc1c149afbf4c8996fb92427ae41e46\
+             49b934ca495991b7852b8554bf5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce23c7\
+             785459a"
+        );
+    }
+
+    #[test]
+    fn test_payload_v2_regular_deserialize() {
+        let payload_script = Script::from_hex(
+            "6a4c5045584f4e554d0200d2040000e3b0c442e3b0c44298fc1c\
+             149afbf4c8996fb92427ae41e4649b934ca495991b7852b8554b\
+             f5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce2\
+             3c7785459a",
+        ).unwrap();
+
+        let block_hash = hash(&[]);
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload.block_hash, block_hash);
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.prev_tx_chain, None);
+        assert_eq!(payload.state_hash, Some(hash(&[1])));
+        assert_eq!(
+            payload.network_id,
+            Some(NetworkId::from_genesis_hash(&block_hash))
+        );
+        assert_eq!(payload.network_id.unwrap().to_string(), "e3b0c442");
+    }
+
+    #[test]
+    fn test_payload_v2_recover_roundtrip() {
+        let block_hash = hash(&[]);
+        let prev_txid = btc::TxId::from_slice(block_hash.as_ref()).unwrap();
+        let network_id = NetworkId::from_genesis_hash(&block_hash);
+        let payload_script = PayloadBuilder::new()
+            .version(2)
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .prev_tx_chain(Some(prev_txid))
+            .network_id(network_id)
+            .into_script()
+            .unwrap();
+
+        assert_eq!(
+            payload_script.to_hex(),
+            "6a4c5045584f4e554d0201d2040000e3b0c442e3b0c44298fc1c149afbf4c8996fb92427ae41e46\
+             49b934ca495991b7852b855e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7\
+             852b855"
+        );
+
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload.block_hash, block_hash);
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.prev_tx_chain, Some(prev_txid));
+        assert_eq!(payload.state_hash, None);
+        assert_eq!(payload.network_id, Some(network_id));
+    }
+
+    #[test]
+    fn test_payload_v2_blocks_root_roundtrip() {
+        let block_hash = hash(&[]);
+        let network_id = NetworkId::from_genesis_hash(&block_hash);
+        let payload_script = PayloadBuilder::new()
+            .version(2)
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .state_hash(hash(&[1]))
+            .blocks_root(hash(&[2]))
+            .network_id(network_id)
+            .into_script()
+            .unwrap();
+
+        assert_eq!(payload_script.len(), 83);
+        assert_eq!(&payload_script[2..11], b"PEXONUM\x02\x02");
+
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload.block_hash, block_hash);
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.prev_tx_chain, None);
+        assert_eq!(payload.state_hash, None);
+        assert_eq!(payload.network_id, Some(network_id));
+        assert_eq!(payload.blocks_root, Some(hash(&[2])));
+    }
+
+    #[test]
+    fn test_payload_v2_notary_root_roundtrip() {
+        let block_hash = hash(&[]);
+        let network_id = NetworkId::from_genesis_hash(&block_hash);
+        let payload_script = PayloadBuilder::new()
+            .version(2)
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .notary_root(hash(&[3]))
+            .network_id(network_id)
+            .into_script()
+            .unwrap();
+
+        assert_eq!(&payload_script[2..11], b"PEXONUM\x02\x03");
+
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.state_hash, None);
+        assert_eq!(payload.blocks_root, None);
+        assert_eq!(payload.notary_root, Some(hash(&[3])));
+    }
+
+    #[test]
+    fn test_payload_v1_has_no_v2_fields() {
+        let payload_script = PayloadBuilder::new()
+            .block_hash(hash(&[]))
+            .block_height(Height(1234))
+            .state_hash(hash(&[1]))
+            .into_script()
+            .unwrap();
+
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload.state_hash, None);
+        assert_eq!(payload.network_id, None);
+    }
+
+    #[test]
+    fn test_payload_v2_height_overflow() {
+        let block_hash = hash(&[]);
+        let height = u64::from(u32::max_value()) + 1;
+        let result = PayloadBuilder::new()
+            .version(2)
+            .block_hash(block_hash)
+            .block_height(Height(height))
+            .state_hash(block_hash)
+            .network_id(NetworkId::from_genesis_hash(&block_hash))
+            .into_script();
+        assert_eq!(result, Err(PayloadError::HeightOverflow(height, 2)));
+    }
+
+    #[test]
+    fn test_payload_unknown_version() {
+        let result = PayloadBuilder::new()
+            .version(3)
+            .block_hash(hash(&[]))
+            .block_height(Height(1234))
+            .into_script();
+        assert_eq!(result, Err(PayloadError::UnknownVersion(3)));
+    }
+
+    #[test]
+    fn test_payload_custom_prefix_roundtrip() {
+        let block_hash = hash(&[]);
+        let prev_txid = btc::TxId::from_slice(block_hash.as_ref()).unwrap();
+        let network_id = NetworkId::from_genesis_hash(&block_hash);
+        let scripts = vec![
+            PayloadBuilder::new()
+                .prefix("A")
+                .block_hash(block_hash)
+                .block_height(Height(1234))
+                .into_script(),
+            PayloadBuilder::new()
+                .prefix("ABC")
+                .block_hash(block_hash)
+                .block_height(Height(1234))
+                .prev_tx_chain(Some(prev_txid))
+                .into_script(),
+            PayloadBuilder::new()
+                .prefix("PROD42")
+                .version(2)
+                .block_hash(block_hash)
+                .block_height(Height(1234))
+                .state_hash(hash(&[1]))
+                .network_id(network_id)
+                .into_script(),
+            PayloadBuilder::new()
+                .prefix("x1")
+                .version(2)
+                .block_hash(block_hash)
+                .block_height(Height(1234))
+                .prev_tx_chain(Some(prev_txid))
+                .network_id(network_id)
+                .into_script(),
+        ].into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let prefixes = ["A", "ABC", "PROD42", "x1"];
+
+        for (script, prefix) in scripts.iter().zip(prefixes.iter()) {
+            let payload = Payload::from_script(script).unwrap();
+            assert_eq!(payload.prefix, *prefix);
+            assert_eq!(payload.block_hash, block_hash);
+            assert_eq!(payload.block_height, Height(1234));
+        }
+        assert_eq!(&scripts[0][2..5], b"A\x01\x00");
+        assert_eq!(&scripts[1][3..8], b"ABC\x01\x01");
+    }
+
+    #[test]
+    fn test_payload_prefix_validation() {
+        assert!(is_valid_prefix(b"EXONUM"));
+        assert!(is_valid_prefix(b"a"));
+        assert!(!is_valid_prefix(b""));
+        assert!(!is_valid_prefix(b"EXONUM1"));
+        assert!(!is_valid_prefix(b"EX-NUM"));
+        assert!(!is_valid_prefix(b"EX\x01"));
+    }
+
+    #[test]
+    fn test_payload_invalid_prefix() {
+        let result = PayloadBuilder::new()
+            .prefix("TOOLONG")
+            .block_hash(hash(&[]))
+            .block_height(Height(1234))
+            .into_script();
+        assert_eq!(result, Err(PayloadError::InvalidPrefix("TOOLONG".to_owned())));
+    }
+
     #[test]
     fn test_payload_incorrect_deserialize() {
         // Payload from old anchoring transaction
@@ -325,4 +973,38 @@ mod tests {
             Script::from_hex("a91472b7506704dc074fa46359251052e781d96f939a87").unwrap();
         assert_eq!(Payload::from_script(&script_pubkey), None);
     }
+
+    #[test]
+    fn test_payload_parse_errors() {
+        let script_pubkey =
+            Script::from_hex("a91472b7506704dc074fa46359251052e781d96f939a87").unwrap();
+        assert_eq!(Payload::parse(&script_pubkey), Err(PayloadError::NotPayload));
+        let text = payload_script(b"Hello world");
+        assert_eq!(Payload::parse(&text), Err(PayloadError::NotPayload));
+
+        let unknown_version = payload_script(&[b"EXONUM".as_ref(), &[3, 0], &[0; 72]].concat());
+        assert_eq!(
+            Payload::parse(&unknown_version),
+            Err(PayloadError::UnknownVersion(3))
+        );
+
+        let short_v1 = payload_script(&[b"EXONUM".as_ref(), &[1, 0], &[0; 39]].concat());
+        assert_eq!(
+            Payload::parse(&short_v1),
+            Err(PayloadError::Malformed(
+                1,
+                "data length of kind 0 is 39 bytes instead of 40".to_owned()
+            ))
+        );
+        let unknown_kind = payload_script(&[b"EX".as_ref(), &[2, 7], &[0; 72]].concat());
+        assert_eq!(
+            Payload::parse(&unknown_kind),
+            Err(PayloadError::Malformed(2, "unknown kind 7".to_owned()))
+        );
+        let empty_v2 = payload_script(b"EX\x02");
+        assert_eq!(
+            Payload::parse(&empty_v2),
+            Err(PayloadError::Malformed(2, "missing kind".to_owned()))
+        );
+    }
 }
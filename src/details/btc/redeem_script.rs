@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use bitcoin::blockdata::script::{Builder, Script};
-use bitcoin::blockdata::opcodes::All;
+use bitcoin::blockdata::opcodes::{self, All};
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::util::base58::FromBase58;
 use bitcoin::util::address::Address as RawAddress;
@@ -21,6 +21,8 @@ use bitcoin::network::constants::Network;
 use secp256k1::key::PublicKey as RawPublicKey;
 use secp256k1::Secp256k1;
 
+use exonum::crypto::hash;
+
 use super::{Address, PublicKey, RedeemScript};
 
 // TODO implement errors
@@ -30,18 +32,100 @@ impl RedeemScript {
     where
         I: IntoIterator<Item = &'a PublicKey>,
     {
-        let mut builder = Builder::new().push_int(i64::from(majority_count));
-        let mut total_count = 0;
+        let script = push_multisig(Builder::new(), pubkeys, majority_count).into_script();
+        RedeemScript(script)
+    }
+
+    /// Creates the redeem script that may be spent by the `majority_count` signatures of
+    /// the `pubkeys` at any time or by the `recovery_count` signatures of the `recovery_pubkeys`
+    /// after `timelock` blocks since the confirmation of the spent output. The timelock
+    /// is enforced by `OP_CHECKSEQUENCEVERIFY`, so the recovery transaction must have
+    /// the version 2 and the input sequence equal to the `timelock`.
+    ///
+    /// The scriptSig of the main path ends with `OP_TRUE` and the scriptSig of the recovery
+    /// path ends with `OP_FALSE` before the redeem script.
+    pub fn from_pubkeys_with_recovery<'a, 'b, I, J>(
+        pubkeys: I,
+        majority_count: u8,
+        recovery_pubkeys: J,
+        recovery_count: u8,
+        timelock: u16,
+    ) -> RedeemScript
+    where
+        I: IntoIterator<Item = &'a PublicKey>,
+        J: IntoIterator<Item = &'b PublicKey>,
+    {
+        let builder = Builder::new().push_opcode(All::OP_IF);
+        let builder = push_multisig(builder, pubkeys, majority_count)
+            .push_opcode(All::OP_ELSE)
+            .push_int(i64::from(timelock))
+            .push_opcode(opcodes::OP_CSV)
+            .push_opcode(All::OP_DROP);
+        let script = push_multisig(builder, recovery_pubkeys, recovery_count)
+            .push_opcode(All::OP_ENDIF)
+            .into_script();
+        RedeemScript(script)
+    }
+
+    /// Returns `true` if the script has the time-locked recovery path.
+    pub fn has_recovery_path(&self) -> bool {
+        self.0.clone().into_vec().first() == Some(&(All::OP_IF as u8))
+    }
 
-        for pubkey in pubkeys {
+    /// Creates the redeem script with the `pubkeys` sorted lexicographically by their
+    /// compressed serialization as specified by BIP67, so that the script does not
+    /// depend on the order of the keys.
+    pub fn from_pubkeys_sorted<'a, I>(pubkeys: I, majority_count: u8) -> RedeemScript
+    where
+        I: IntoIterator<Item = &'a PublicKey>,
+    {
+        let mut pubkeys = pubkeys.into_iter().collect::<Vec<_>>();
+        pubkeys.sort_by(|a, b| a.serialize()[..].cmp(&b.serialize()[..]));
+        RedeemScript::from_pubkeys(pubkeys, majority_count)
+    }
+
+    /// Creates the witness script that requires `threshold` signatures of the `pubkeys` by
+    /// the chained `OP_CHECKSIG` operations. Unlike `OP_CHECKMULTISIG` it is not limited
+    /// to 15 keys, the witness stack must contain the signatures in the reverse order of
+    /// the keys with the empty items for the missing signatures.
+    pub fn chained_checksig_from_pubkeys<'a, I>(pubkeys: I, threshold: u8) -> RedeemScript
+    where
+        I: IntoIterator<Item = &'a PublicKey>,
+    {
+        let mut builder = Builder::new();
+        for (idx, pubkey) in pubkeys.into_iter().enumerate() {
             let bytes = pubkey.serialize();
-            builder = builder.push_slice(bytes.as_ref());
-            total_count += 1;
+            if idx > 0 {
+                builder = builder.push_opcode(All::OP_SWAP);
+            }
+            builder = builder
+                .push_slice(bytes.as_ref())
+                .push_opcode(All::OP_CHECKSIG);
+            if idx > 0 {
+                builder = builder.push_opcode(All::OP_ADD);
+            }
         }
 
         let script = builder
-            .push_int(total_count)
-            .push_opcode(All::OP_CHECKMULTISIG)
+            .push_int(i64::from(threshold))
+            .push_opcode(All::OP_NUMEQUAL)
+            .into_script();
+        RedeemScript(script)
+    }
+
+    /// Returns `true` if this is the witness script of the chained `OP_CHECKSIG` operations,
+    /// which must be spent by the P2SH-wrapped P2WSH output.
+    pub fn is_witness_script(&self) -> bool {
+        self.0.clone().into_vec().last() == Some(&(All::OP_NUMEQUAL as u8))
+    }
+
+    /// Returns the P2WSH witness program of this script, which is the redeem script
+    /// of the P2SH-wrapped P2WSH address.
+    pub fn witness_program(&self) -> RedeemScript {
+        let script_hash = hash(&self.0.clone().into_vec());
+        let script = Builder::new()
+            .push_opcode(All::OP_PUSHBYTES_0)
+            .push_slice(script_hash.as_ref())
             .into_script();
         RedeemScript(script)
     }
@@ -76,12 +160,14 @@ impl RedeemScript {
         for instruction in &self.0 {
             match instruction {
                 Instruction::PushBytes(bytes) => {
-                    if bytes.len() == 33 {
-                        builder = builder.push_slice(bytes);
-                    } else {
+                    // Only the uncompressed public keys are converted, the other pushes
+                    // may be the numbers like the timelock of the recovery path.
+                    if bytes.len() == 65 {
                         let pubkey = RawPublicKey::from_slice(&context, bytes).unwrap();
                         let addr = RawAddress::from_key(network, &pubkey, true);
                         builder = builder.push_slice(addr.hash[..].as_ref());
+                    } else {
+                        builder = builder.push_slice(bytes);
                     }
                 }
                 Instruction::Op(opcode) => builder = builder.push_opcode(opcode),
@@ -96,3 +182,21 @@ impl RedeemScript {
         addr.script_pubkey()
     }
 }
+
+fn push_multisig<'a, I>(builder: Builder, pubkeys: I, majority_count: u8) -> Builder
+where
+    I: IntoIterator<Item = &'a PublicKey>,
+{
+    let mut builder = builder.push_int(i64::from(majority_count));
+    let mut total_count = 0;
+
+    for pubkey in pubkeys {
+        let bytes = pubkey.serialize();
+        builder = builder.push_slice(bytes.as_ref());
+        total_count += 1;
+    }
+
+    builder
+        .push_int(total_count)
+        .push_opcode(All::OP_CHECKMULTISIG)
+}
@@ -16,9 +16,10 @@ use std::fmt;
 use std::collections::HashMap;
 use std::ops::Deref;
 
+use byteorder::{LittleEndian, WriteBytesExt};
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::blockdata::opcodes::All;
-use bitcoin::util::hash::Hash160;
+use bitcoin::util::hash::{Hash160, Sha256dHash};
 use bitcoin::network::serialize::{deserialize, serialize, serialize_hex, BitcoinHash};
 use bitcoin::blockdata::transaction::{TxIn, TxOut};
 use bitcoin::blockdata::script::{Builder, Script};
@@ -39,7 +40,8 @@ use details::rpc::{Error as RpcError, RpcClient};
 use details::btc;
 use details::btc::{HexValueEx, RedeemScript, TxId};
 use details::error::Error as InternalError;
-use details::btc::payload::{Payload, PayloadBuilder};
+use details::btc::payload::{NetworkId, Payload, PayloadBuilder, PayloadError,
+                            DEFAULT_PAYLOAD_PREFIX};
 
 pub type RawBitcoinTx = ::bitcoin::blockdata::transaction::Transaction;
 
@@ -77,6 +79,13 @@ pub struct TransactionBuilder {
     fee: Option<u64>,
     payload: Option<(Height, Hash)>,
     prev_tx_chain: Option<TxId>,
+    payload_prefix: String,
+    payload_version: u8,
+    state_hash: Option<Hash>,
+    network_id: Option<NetworkId>,
+    blocks_root: Option<Hash>,
+    notary_root: Option<Hash>,
+    dust_limit: u64,
 }
 
 impl HexValueEx for RawBitcoinTx {
@@ -194,6 +203,40 @@ impl AnchoringTx {
         verify_tx_input(self, input as usize, redeem_script, pub_key, signature)
     }
 
+    /// Signs the segregated witness `input` that spends the output with the given `value`.
+    pub fn sign_witness_input(
+        &self,
+        witness_script: &btc::RedeemScript,
+        input: u32,
+        value: u64,
+        priv_key: &Privkey,
+    ) -> btc::Signature {
+        let mut sign_data = sign_witness_tx_input(
+            self,
+            input as usize,
+            witness_script,
+            value,
+            priv_key.secret_key(),
+        );
+        sign_data.push(SigHashType::All.as_u32() as u8);
+        sign_data
+    }
+
+    /// Verifies the signature of the segregated witness `input` that spends the output
+    /// with the given `value`.
+    pub fn verify_witness_input(
+        &self,
+        witness_script: &RedeemScript,
+        input: u32,
+        value: u64,
+        pub_key: &PublicKey,
+        signature: &[u8],
+    ) -> bool {
+        // Cuts off btc related sighash type byte
+        let signature = &signature[0..signature.len() - 1];
+        verify_witness_tx_input(self, input as usize, witness_script, value, pub_key, signature)
+    }
+
     pub fn finalize(
         self,
         redeem_script: &btc::RedeemScript,
@@ -281,6 +324,13 @@ impl TransactionBuilder {
             payload: None,
             fee: None,
             prev_tx_chain: None,
+            payload_prefix: DEFAULT_PAYLOAD_PREFIX.to_owned(),
+            payload_version: 1,
+            state_hash: None,
+            network_id: None,
+            blocks_root: None,
+            notary_root: None,
+            dust_limit: 0,
         }
     }
 
@@ -309,6 +359,41 @@ impl TransactionBuilder {
         self
     }
 
+    pub fn payload_prefix(mut self, prefix: &str) -> TransactionBuilder {
+        self.payload_prefix = prefix.to_owned();
+        self
+    }
+
+    pub fn payload_version(mut self, version: u8) -> TransactionBuilder {
+        self.payload_version = version;
+        self
+    }
+
+    pub fn state_hash(mut self, hash: Hash) -> TransactionBuilder {
+        self.state_hash = Some(hash);
+        self
+    }
+
+    pub fn network_id(mut self, id: NetworkId) -> TransactionBuilder {
+        self.network_id = Some(id);
+        self
+    }
+
+    pub fn blocks_root(mut self, root: Hash) -> TransactionBuilder {
+        self.blocks_root = Some(root);
+        self
+    }
+
+    pub fn notary_root(mut self, root: Hash) -> TransactionBuilder {
+        self.notary_root = Some(root);
+        self
+    }
+
+    pub fn dust_limit(mut self, limit: u64) -> TransactionBuilder {
+        self.dust_limit = limit;
+        self
+    }
+
     pub fn into_transaction(mut self) -> Result<AnchoringTx, InternalError> {
         let available_funds: u64 = self.inputs
             .iter()
@@ -318,48 +403,55 @@ impl TransactionBuilder {
         let addr = self.output.take().expect("Output address is not set");
         let fee = self.fee.expect("Fee is not set");
         let (height, block_hash) = self.payload.take().expect("Payload is not set");
-        if available_funds < fee {
+        if available_funds < fee + self.dust_limit {
             return Err(InternalError::InsufficientFunds);
         }
         let total_funds = available_funds - fee;
 
-        let tx = create_anchoring_transaction(
-            &addr,
-            height,
-            block_hash,
-            self.inputs.iter(),
-            total_funds,
-            self.prev_tx_chain,
-        );
+        let mut payload = PayloadBuilder::new()
+            .prefix(&self.payload_prefix)
+            .version(self.payload_version)
+            .block_hash(block_hash)
+            .block_height(height)
+            .prev_tx_chain(self.prev_tx_chain);
+        if let Some(state_hash) = self.state_hash {
+            payload = payload.state_hash(state_hash);
+        }
+        if let Some(network_id) = self.network_id {
+            payload = payload.network_id(network_id);
+        }
+        if let Some(blocks_root) = self.blocks_root {
+            payload = payload.blocks_root(blocks_root);
+        }
+        if let Some(notary_root) = self.notary_root {
+            payload = payload.notary_root(notary_root);
+        }
+
+        let metadata_script = payload.into_script().map_err(InternalError::Payload)?;
+        let tx =
+            create_anchoring_transaction(&addr, self.inputs.iter(), total_funds, metadata_script);
         Ok(tx)
     }
 }
 
 fn create_anchoring_transaction<'a, I>(
     addr: &btc::Address,
-    block_height: Height,
-    block_hash: Hash,
     inputs: I,
     out_funds: u64,
-    prev_chain_txid: Option<TxId>,
+    metadata_script: Script,
 ) -> AnchoringTx
 where
     I: Iterator<Item = &'a (RawBitcoinTx, u32)>,
 {
     let inputs = inputs
         .map(|&(ref unspent_tx, utxo_vout)| TxIn {
-            prev_hash: unspent_tx.bitcoin_hash(),
+            prev_hash: unspent_tx.txid(),
             prev_index: utxo_vout,
             script_sig: Script::new(),
             sequence: 0xFFFF_FFFF,
         })
         .collect::<Vec<_>>();
 
-    let metadata_script = PayloadBuilder::new()
-        .block_hash(block_hash)
-        .block_height(block_height)
-        .prev_tx_chain(prev_chain_txid)
-        .into_script();
     let outputs = vec![
         TxOut {
             value: out_funds,
@@ -388,12 +480,7 @@ pub fn sign_tx_input(
     sec_key: &SecretKey,
 ) -> Vec<u8> {
     let sighash = tx.signature_hash(input, subscript, SigHashType::All.as_u32());
-    // Make signature
-    let context = Secp256k1::new();
-    let msg = Message::from_slice(&sighash[..]).unwrap();
-    let sign = context.sign(&msg, sec_key).unwrap();
-    // Serialize signature
-    sign.serialize_der(&context)
+    sign_sighash(&sighash, sec_key)
 }
 
 pub fn verify_tx_input(
@@ -404,6 +491,42 @@ pub fn verify_tx_input(
     signature: &[u8],
 ) -> bool {
     let sighash = tx.signature_hash(input, subscript, SigHashType::All.as_u32());
+    verify_sighash(&sighash, pub_key, signature)
+}
+
+pub fn sign_witness_tx_input(
+    tx: &RawBitcoinTx,
+    input: usize,
+    witness_script: &Script,
+    value: u64,
+    sec_key: &SecretKey,
+) -> Vec<u8> {
+    let sighash = witness_signature_hash(tx, input, witness_script, value);
+    sign_sighash(&sighash, sec_key)
+}
+
+pub fn verify_witness_tx_input(
+    tx: &RawBitcoinTx,
+    input: usize,
+    witness_script: &Script,
+    value: u64,
+    pub_key: &PublicKey,
+    signature: &[u8],
+) -> bool {
+    let sighash = witness_signature_hash(tx, input, witness_script, value);
+    verify_sighash(&sighash, pub_key, signature)
+}
+
+fn sign_sighash(sighash: &Sha256dHash, sec_key: &SecretKey) -> Vec<u8> {
+    // Make signature
+    let context = Secp256k1::new();
+    let msg = Message::from_slice(&sighash[..]).unwrap();
+    let sign = context.sign(&msg, sec_key).unwrap();
+    // Serialize signature
+    sign.serialize_der(&context)
+}
+
+fn verify_sighash(sighash: &Sha256dHash, pub_key: &PublicKey, signature: &[u8]) -> bool {
     let msg = Message::from_slice(&sighash[..]).unwrap();
 
     let context = Secp256k1::new();
@@ -414,11 +537,53 @@ pub fn verify_tx_input(
     }
 }
 
+/// Computes the signature hash of the segregated witness input as specified by BIP143.
+pub fn witness_signature_hash(
+    tx: &RawBitcoinTx,
+    input: usize,
+    script_code: &Script,
+    value: u64,
+) -> Sha256dHash {
+    let mut prevouts = Vec::new();
+    let mut sequences = Vec::new();
+    for txin in &tx.input {
+        prevouts.extend_from_slice(&txin.prev_hash[..]);
+        prevouts.write_u32::<LittleEndian>(txin.prev_index).unwrap();
+        sequences.write_u32::<LittleEndian>(txin.sequence).unwrap();
+    }
+    let mut outputs = Vec::new();
+    for txout in &tx.output {
+        outputs.extend(serialize(txout).unwrap());
+    }
+
+    let txin = &tx.input[input];
+    let mut data = Vec::new();
+    data.write_u32::<LittleEndian>(tx.version).unwrap();
+    data.extend_from_slice(&Sha256dHash::from_data(&prevouts)[..]);
+    data.extend_from_slice(&Sha256dHash::from_data(&sequences)[..]);
+    data.extend_from_slice(&txin.prev_hash[..]);
+    data.write_u32::<LittleEndian>(txin.prev_index).unwrap();
+    data.extend(serialize(script_code).unwrap());
+    data.write_u64::<LittleEndian>(value).unwrap();
+    data.write_u32::<LittleEndian>(txin.sequence).unwrap();
+    data.extend_from_slice(&Sha256dHash::from_data(&outputs)[..]);
+    data.write_u32::<LittleEndian>(tx.lock_time).unwrap();
+    data.write_u32::<LittleEndian>(SigHashType::All.as_u32()).unwrap();
+    Sha256dHash::from_data(&data)
+}
+
+// The `signatures` of each input must be ordered by the positions of the keys
+// in the `redeem_script`. The signatures for the witness script must contain
+// the empty items for the missing signatures.
 fn finalize_anchoring_transaction(
     mut anchoring_tx: AnchoringTx,
     redeem_script: &btc::RedeemScript,
     signatures: HashMap<u32, Vec<btc::Signature>>,
 ) -> AnchoringTx {
+    if redeem_script.is_witness_script() {
+        return finalize_witness_anchoring_transaction(anchoring_tx, redeem_script, signatures);
+    }
+
     let redeem_script_bytes = redeem_script.0.clone().into_vec();
     // build scriptSig
     for (out, signatures) in signatures {
@@ -428,6 +593,10 @@ fn finalize_anchoring_transaction(
             for sign in &signatures {
                 builder = builder.push_slice(sign.as_ref());
             }
+            // Select the main path of the script with the recovery path.
+            if redeem_script.has_recovery_path() {
+                builder = builder.push_int(1);
+            }
             builder
                 .push_slice(redeem_script_bytes.as_ref())
                 .into_script()
@@ -436,8 +605,37 @@ fn finalize_anchoring_transaction(
     anchoring_tx
 }
 
-fn find_payload(tx: &RawBitcoinTx) -> Option<Payload> {
+fn finalize_witness_anchoring_transaction(
+    mut anchoring_tx: AnchoringTx,
+    witness_script: &btc::RedeemScript,
+    signatures: HashMap<u32, Vec<btc::Signature>>,
+) -> AnchoringTx {
+    let witness_script_bytes = witness_script.0.clone().into_vec();
+    let witness_program_bytes = witness_script.witness_program().0.into_vec();
+    let inputs_count = anchoring_tx.0.input.len();
+    anchoring_tx.0.witness.resize(inputs_count, vec![]);
+    for (out, signatures) in signatures {
+        // The script of the P2SH-wrapped P2WSH output is the witness program.
+        anchoring_tx.0.input[out as usize].script_sig = Builder::new()
+            .push_slice(witness_program_bytes.as_ref())
+            .into_script();
+        // The first key is checked against the top item of the stack.
+        let mut witness = signatures.into_iter().rev().collect::<Vec<_>>();
+        witness.push(witness_script_bytes.clone());
+        anchoring_tx.0.witness[out as usize] = witness;
+    }
+    anchoring_tx
+}
+
+/// Extracts the payload from the data output of the transaction or explains
+/// why the transaction is not an anchoring one.
+pub fn parse_payload(tx: &RawBitcoinTx) -> Result<Payload, PayloadError> {
     tx.output
         .get(ANCHORING_TX_DATA_OUTPUT as usize)
-        .and_then(|output| Payload::from_script(&output.script_pubkey))
+        .ok_or(PayloadError::NotPayload)
+        .and_then(|output| Payload::parse(&output.script_pubkey))
+}
+
+fn find_payload(tx: &RawBitcoinTx) -> Option<Payload> {
+    parse_payload(tx).ok()
 }
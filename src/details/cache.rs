@@ -0,0 +1,285 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Caching decorator for the `BitcoinRelay`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use details::rpc::{AnchoringRpcConfig, BitcoinRelay, Result, TxInfo};
+use observer::Milliseconds;
+
+/// Relay cache configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RelayCacheConfig {
+    /// Enables the cache.
+    pub enabled: bool,
+    /// Maximum number of the cached transactions.
+    pub capacity: usize,
+    /// Time during which the cached confirmations count is considered as actual.
+    pub confirmations_ttl: Milliseconds,
+}
+
+impl Default for RelayCacheConfig {
+    fn default() -> RelayCacheConfig {
+        RelayCacheConfig {
+            enabled: false,
+            capacity: 1024,
+            confirmations_ttl: 5_000,
+        }
+    }
+}
+
+/// Cache hits and misses statistics.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Number of requests that have been served from the cache.
+    pub hits: usize,
+    /// Number of requests that have been forwarded to the inner relay.
+    pub misses: usize,
+}
+
+/// Simple least recently used cache.
+///
+/// Each entry remembers the tick of its latest use, and the `order` maps the ticks back
+/// to the keys, so the least recently used entry is the first one in the `order`.
+#[derive(Debug)]
+struct LruCache<K: Eq + Hash, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+    fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+        self.tick += 1;
+        let key = self.order.remove(&entry.1).expect("Cache order is inconsistent");
+        self.order.insert(self.tick, key);
+        entry.1 = self.tick;
+        Some(entry.0.clone())
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((_, tick)) = self.entries.remove(&key) {
+            self.order.remove(&tick);
+        } else if self.entries.len() >= self.capacity {
+            let oldest = self.order.keys().next().cloned();
+            if let Some(oldest) = oldest {
+                let key = self.order.remove(&oldest).unwrap();
+                self.entries.remove(&key);
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some((_, tick)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+}
+
+/// `BitcoinRelay` decorator that caches transaction lookups.
+///
+/// Bodies of the confirmed transactions never change, so they are cached until eviction.
+/// Confirmations counts are cached only for the `confirmations_ttl`.
+#[derive(Debug)]
+pub struct CachedRelay {
+    inner: Box<BitcoinRelay>,
+    config: RelayCacheConfig,
+    confirmations_ttl: Duration,
+    bodies: Mutex<LruCache<btc::TxId, BitcoinTx>>,
+    confirmations: Mutex<LruCache<btc::TxId, (Option<u64>, Instant)>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl CachedRelay {
+    /// Wraps the given relay.
+    pub fn new(inner: Box<BitcoinRelay>, config: &RelayCacheConfig) -> CachedRelay {
+        CachedRelay {
+            inner,
+            config: config.clone(),
+            confirmations_ttl: Duration::from_millis(config.confirmations_ttl),
+            bodies: Mutex::new(LruCache::new(config.capacity)),
+            confirmations: Mutex::new(LruCache::new(config.capacity)),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns cache hits and misses statistics.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn cached_body(&self, txid: &btc::TxId) -> Option<BitcoinTx> {
+        self.bodies.lock().unwrap().get(txid)
+    }
+
+    fn cached_confirmations(&self, txid: &btc::TxId) -> Option<Option<u64>> {
+        let mut confirmations = self.confirmations.lock().unwrap();
+        match confirmations.get(txid) {
+            Some((value, time)) if time.elapsed() < self.confirmations_ttl => Some(value),
+            Some(_) => {
+                confirmations.remove(txid);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn remember(&self, txid: btc::TxId, info: &TxInfo) {
+        if info.confirmations.unwrap_or(0) > 0 {
+            self.bodies.lock().unwrap().insert(txid, info.body.clone());
+        }
+        self.confirmations
+            .lock()
+            .unwrap()
+            .insert(txid, (info.confirmations, Instant::now()));
+    }
+
+    fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn fetch_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        self.miss();
+        let info = self.inner.get_transaction_info(txid)?;
+        if let Some(ref info) = info {
+            self.remember(txid, info);
+        }
+        Ok(info)
+    }
+}
+
+impl BitcoinRelay for CachedRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        if let Some(body) = self.cached_body(&txid) {
+            self.hit();
+            return Ok(Some(body));
+        }
+        Ok(self.fetch_transaction_info(txid)?.map(|info| info.body))
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        if let (Some(body), Some(confirmations)) =
+            (self.cached_body(&txid), self.cached_confirmations(&txid))
+        {
+            self.hit();
+            return Ok(Some(TxInfo {
+                body,
+                confirmations,
+            }));
+        }
+        self.fetch_transaction_info(txid)
+    }
+
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        self.inner.watch_address(addr, rescan)
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.confirmations.lock().unwrap().remove(&tx.id());
+        self.inner.send_transaction(tx)
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        self.inner.send_to_address(addr, satoshis)
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let txs = self.inner.unspent_transactions(addr)?;
+        for info in &txs {
+            self.remember(info.body.id(), info);
+        }
+        Ok(txs)
+    }
+
+    fn get_transaction_confirmations(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        if let Some(confirmations) = self.cached_confirmations(&txid) {
+            self.hit();
+            return Ok(confirmations);
+        }
+        let info = self.fetch_transaction_info(txid)?;
+        Ok(info.and_then(|info| info.confirmations))
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            cache: self.config.clone(),
+            ..self.inner.config()
+        }
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LruCache;
+
+    #[test]
+    fn test_lru_cache_eviction() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        // The first entry becomes the most recently used one.
+        assert_eq!(cache.get(&1), Some("a"));
+        cache.insert(3, "c");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&3), Some("c"));
+
+        // Replacing the value does not evict the other entries.
+        cache.insert(3, "d");
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&3), Some("d"));
+        cache.remove(&1);
+        cache.insert(4, "e");
+        assert_eq!(cache.get(&3), Some("d"));
+        assert_eq!(cache.get(&4), Some("e"));
+        assert_eq!(cache.entries.len(), cache.order.len());
+    }
+}
@@ -14,7 +14,9 @@
 
 use std::io;
 
+use details::btc::payload::PayloadError;
 use details::rpc::Error as RpcError;
+use details::signer::SignerError;
 
 /// Service error.
 #[derive(Debug, Display, Fail)]
@@ -28,4 +30,10 @@ pub enum Error {
     /// An input output error.
     #[display(fmt = "{}", _0)]
     Io(io::Error),
+    /// Signer error.
+    #[display(fmt = "{}", _0)]
+    Signer(SignerError),
+    /// The anchoring transaction payload can not be created.
+    #[display(fmt = "{}", _0)]
+    Payload(PayloadError),
 }
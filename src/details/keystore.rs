@@ -0,0 +1,277 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encrypted storage of the anchoring private keys.
+//!
+//! Each key is encrypted by the IETF `ChaCha20-Poly1305` cipher with the key derived from
+//! the passphrase by `scrypt`. The anchoring address is authenticated along with the key,
+//! so the encrypted keys can not be swapped between the addresses.
+
+use std::env;
+use std::io;
+
+use bitcoin::util::base58::{FromBase58, ToBase58};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::{self, Rng};
+use rpassword;
+use scrypt::{scrypt, Params as ScryptParams};
+use serde::{Deserialize, Deserializer, Serializer};
+
+use exonum::encoding::serialize::{encode_hex, FromHex};
+
+use details::btc;
+
+/// Name of the environment variable with the keystore passphrase.
+pub const KEYSTORE_PASSPHRASE_ENV: &str = "ANCHORING_KEYSTORE_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Parameters of the `scrypt` key derivation.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct KdfParams {
+    /// Logarithm of the CPU and memory cost.
+    pub log_n: u8,
+    /// Block size.
+    pub r: u32,
+    /// Parallelization.
+    pub p: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> KdfParams {
+        KdfParams {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<Vec<u8>, KeystoreError> {
+        // Bounds the memory that the config is able to request.
+        if self.log_n == 0 || self.log_n > 20 || self.r == 0 || self.r > 32 || self.p == 0
+            || self.p > 16
+        {
+            return Err(KeystoreError::IncorrectParams(format!("{:?}", self)));
+        }
+        let params = ScryptParams::new(self.log_n, self.r, self.p, KEY_LEN)
+            .map_err(|_| KeystoreError::IncorrectParams(format!("{:?}", self)))?;
+        let mut key = vec![0; KEY_LEN];
+        scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+            .map_err(|_| KeystoreError::IncorrectParams(format!("{:?}", self)))?;
+        Ok(key)
+    }
+}
+
+/// Anchoring private key encrypted by the passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EncryptedKey {
+    /// Anchoring address of the key.
+    pub address: String,
+    /// Salt of the key derivation.
+    #[serde(serialize_with = "bytes_to_hex", deserialize_with = "bytes_from_hex")]
+    pub salt: Vec<u8>,
+    /// Nonce of the cipher.
+    #[serde(serialize_with = "bytes_to_hex", deserialize_with = "bytes_from_hex")]
+    pub nonce: Vec<u8>,
+    /// Encrypted private key in WIF followed by the authentication tag.
+    #[serde(serialize_with = "bytes_to_hex", deserialize_with = "bytes_from_hex")]
+    pub ciphertext: Vec<u8>,
+    /// Parameters of the key derivation.
+    pub kdf: KdfParams,
+}
+
+/// Encrypted private keys of the node with the source of their passphrase.
+///
+/// The passphrase is resolved in the following order: the `ANCHORING_KEYSTORE_PASSPHRASE`
+/// environment variable, the `passphrase_fd` file descriptor and finally the prompt
+/// on the terminal.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeystoreConfig {
+    /// File descriptor from which the passphrase is read, e.g. a pipe of the supervisor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_fd: Option<i32>,
+    /// Encrypted private keys for each anchoring address.
+    #[serde(default)]
+    pub keys: Vec<EncryptedKey>,
+}
+
+impl Default for KeystoreConfig {
+    fn default() -> KeystoreConfig {
+        KeystoreConfig {
+            passphrase_fd: None,
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl KeystoreConfig {
+    /// Decrypts all keys by the passphrase. Fails if any key can not be decrypted.
+    pub fn decrypt(
+        &self,
+        passphrase: &str,
+    ) -> Result<Vec<(String, btc::PrivateKey)>, KeystoreError> {
+        self.keys
+            .iter()
+            .map(|key| key.decrypt(passphrase).map(|priv_key| (key.address.clone(), priv_key)))
+            .collect()
+    }
+
+    /// Decrypts all keys by the passphrase from the configured source if there are any.
+    pub fn unlock(&self) -> Result<Vec<(String, btc::PrivateKey)>, KeystoreError> {
+        if self.keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let passphrase = read_passphrase(self.passphrase_fd, "Anchoring keystore passphrase: ")?;
+        self.decrypt(&passphrase)
+    }
+}
+
+/// Reason why the private keys can not be encrypted or decrypted.
+#[derive(Debug, Display, Fail, Clone, PartialEq)]
+pub enum KeystoreError {
+    /// The passphrase can not be read from the configured source.
+    #[display(fmt = "Unable to read the keystore passphrase: {}", _0)]
+    Passphrase(String),
+    /// The passphrase is wrong or the encrypted key is corrupted.
+    #[display(fmt = "Unable to decrypt the key of the address {}", _0)]
+    IncorrectPassphrase(String),
+    /// The key derivation or the cipher parameters are incorrect.
+    #[display(fmt = "Incorrect keystore parameters: {}", _0)]
+    IncorrectParams(String),
+}
+
+impl EncryptedKey {
+    /// Encrypts the private key of the anchoring address by the passphrase.
+    pub fn encrypt(
+        address: &str,
+        priv_key: &btc::PrivateKey,
+        passphrase: &str,
+        kdf: KdfParams,
+    ) -> Result<EncryptedKey, KeystoreError> {
+        let mut rng = rand::thread_rng();
+        let mut salt = vec![0; SALT_LEN];
+        let mut nonce = vec![0; NONCE_LEN];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let key = kdf.derive_key(passphrase, &salt)?;
+        let plaintext = priv_key.to_base58check().into_bytes();
+        let payload = Payload {
+            msg: &plaintext,
+            aad: address.as_bytes(),
+        };
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| KeystoreError::IncorrectParams("Unable to encrypt the key".to_owned()))?;
+
+        Ok(EncryptedKey {
+            address: address.to_owned(),
+            salt,
+            nonce,
+            ciphertext,
+            kdf,
+        })
+    }
+
+    /// Decrypts the private key by the passphrase.
+    pub fn decrypt(&self, passphrase: &str) -> Result<btc::PrivateKey, KeystoreError> {
+        if self.nonce.len() != NONCE_LEN || self.ciphertext.len() < TAG_LEN {
+            return Err(KeystoreError::IncorrectParams(format!(
+                "Malformed key of the address {}",
+                self.address
+            )));
+        }
+        let key = self.kdf.derive_key(passphrase, &self.salt)?;
+        let payload = Payload {
+            msg: &self.ciphertext,
+            aad: self.address.as_bytes(),
+        };
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(Nonce::from_slice(&self.nonce), payload)
+            .map_err(|_| KeystoreError::IncorrectPassphrase(self.address.clone()))?;
+        String::from_utf8(plaintext)
+            .ok()
+            .and_then(|wif| btc::PrivateKey::from_base58check(&wif).ok())
+            .ok_or_else(|| KeystoreError::IncorrectPassphrase(self.address.clone()))
+    }
+}
+
+/// Reads the passphrase from the `ANCHORING_KEYSTORE_PASSPHRASE` environment variable,
+/// the given file descriptor or the prompt on the terminal.
+pub fn read_passphrase(fd: Option<i32>, prompt: &str) -> Result<String, KeystoreError> {
+    if let Ok(passphrase) = env::var(KEYSTORE_PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    read_secret(fd, prompt).map_err(|e| KeystoreError::Passphrase(e.to_string()))
+}
+
+/// Reads the secret line from the given file descriptor or the prompt on the terminal
+/// without echo.
+pub fn read_secret(fd: Option<i32>, prompt: &str) -> io::Result<String> {
+    if let Some(fd) = fd {
+        read_fd(fd)
+    } else {
+        rpassword::prompt_password_stderr(prompt)
+    }
+}
+
+// Reads the first line from the file descriptor, the descriptor is closed afterwards.
+// The standard streams are refused, so they are never closed by mistake.
+#[cfg(unix)]
+fn read_fd(fd: i32) -> io::Result<String> {
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::io::FromRawFd;
+
+    if fd < 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("File descriptor {} can not be used for the secrets", fd),
+        ));
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    let mut passphrase = String::new();
+    BufReader::new(file).read_line(&mut passphrase)?;
+    Ok(passphrase.trim_right_matches(|c| c == '\r' || c == '\n').to_owned())
+}
+
+#[cfg(not(unix))]
+fn read_fd(_: i32) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Passphrase file descriptors are supported only on unix",
+    ))
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(ptr_arg))]
+fn bytes_to_hex<S>(bytes: &Vec<u8>, ser: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    ser.serialize_str(&encode_hex(bytes))
+}
+
+fn bytes_from_hex<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    Vec::<u8>::from_hex(s).map_err(|_| ::serde::de::Error::custom("Wrong hex"))
+}
@@ -0,0 +1,307 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merkle mountain range of the block hashes.
+//!
+//! Nodes are stored in the post-order, so the range of the first `n` leaves is
+//! a prefix of the range of any greater number of leaves. It allows to compute
+//! roots and proofs for any anchored height from the single storage.
+
+use exonum::crypto::{hash, Hash};
+use exonum::storage::{Fork, ListIndex, Snapshot};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const PEAKS_PREFIX: u8 = 2;
+const MAX_HEIGHT: u32 = 63;
+
+/// Storage of the Merkle mountain range nodes.
+pub trait MmrStorage {
+    /// Returns the number of the stored nodes.
+    fn size(&self) -> u64;
+    /// Returns the node at the given position.
+    fn node(&self, pos: u64) -> Hash;
+    /// Appends a new node.
+    fn push_node(&mut self, node: Hash);
+}
+
+impl MmrStorage for Vec<Hash> {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn node(&self, pos: u64) -> Hash {
+        self[pos as usize]
+    }
+
+    fn push_node(&mut self, node: Hash) {
+        self.push(node)
+    }
+}
+
+impl<'a> MmrStorage for ListIndex<&'a mut Fork, Hash> {
+    fn size(&self) -> u64 {
+        self.len()
+    }
+
+    fn node(&self, pos: u64) -> Hash {
+        self.get(pos).expect("Merkle mountain range node is absent")
+    }
+
+    fn push_node(&mut self, node: Hash) {
+        self.push(node)
+    }
+}
+
+/// Read-only nodes table with the in-memory tail for the nodes which have not been stored yet.
+pub(crate) struct MmrOverlay<T> {
+    stored: ListIndex<T, Hash>,
+    stored_size: u64,
+    tail: Vec<Hash>,
+}
+
+impl<T: AsRef<Snapshot>> MmrOverlay<T> {
+    pub fn new(stored: ListIndex<T, Hash>) -> MmrOverlay<T> {
+        let stored_size = stored.len();
+        MmrOverlay {
+            stored,
+            stored_size,
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl<T: AsRef<Snapshot>> MmrStorage for MmrOverlay<T> {
+    fn size(&self) -> u64 {
+        self.stored_size + self.tail.len() as u64
+    }
+
+    fn node(&self, pos: u64) -> Hash {
+        if pos < self.stored_size {
+            self.stored
+                .get(pos)
+                .expect("Merkle mountain range node is absent")
+        } else {
+            self.tail[(pos - self.stored_size) as usize]
+        }
+    }
+
+    fn push_node(&mut self, node: Hash) {
+        self.tail.push(node)
+    }
+}
+
+/// Merkle mountain range over the given nodes storage.
+#[derive(Debug)]
+pub struct MerkleMountainRange<S> {
+    storage: S,
+}
+
+/// Proof of the leaf inclusion into the Merkle mountain range.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MmrProof {
+    /// Index of the proven leaf.
+    pub leaf_index: u64,
+    /// Total number of the leaves in the range.
+    pub leaves: u64,
+    /// Hashes of the siblings on the path from the leaf to its peak.
+    pub path: Vec<Hash>,
+    /// Hashes of the all peaks from left to right.
+    pub peaks: Vec<Hash>,
+}
+
+impl<S: MmrStorage> MerkleMountainRange<S> {
+    /// Creates the range over the given nodes storage.
+    pub fn new(storage: S) -> MerkleMountainRange<S> {
+        MerkleMountainRange { storage }
+    }
+
+    /// Returns the number of the leaves in the range.
+    pub fn leaves(&self) -> u64 {
+        let mut size = self.storage.size();
+        let mut leaves = 0;
+        for height in (0..MAX_HEIGHT).rev() {
+            let tree_size = (1 << (height + 1)) - 1;
+            if size >= tree_size {
+                size -= tree_size;
+                leaves += 1 << height;
+            }
+        }
+        leaves
+    }
+
+    /// Appends a new leaf with the given data.
+    pub fn push(&mut self, data: &Hash) {
+        let index = self.leaves();
+        self.storage.push_node(leaf_hash(data));
+        // Each trailing one bit of the leaf index means that there is a complete pair to merge.
+        for height in 0..(!index).trailing_zeros() {
+            // The right child is the latest pushed node.
+            let right = self.storage.size() - 1;
+            let left = right - ((1 << (height + 1)) - 1);
+            let parent = node_hash(&self.storage.node(left), &self.storage.node(right));
+            self.storage.push_node(parent);
+        }
+    }
+
+    /// Returns the root of the range that contains the first `leaves` leaves.
+    pub fn root(&self, leaves: u64) -> Option<Hash> {
+        if leaves == 0 || leaves > self.leaves() {
+            return None;
+        }
+        let peaks = peaks(leaves)
+            .into_iter()
+            .map(|peak| self.storage.node(peak.pos))
+            .collect::<Vec<_>>();
+        Some(bag_peaks(&peaks))
+    }
+
+    /// Returns the proof of the leaf with the given index inclusion into the range
+    /// that contains the first `leaves` leaves.
+    pub fn proof(&self, leaf_index: u64, leaves: u64) -> Option<MmrProof> {
+        if leaf_index >= leaves || leaves > self.leaves() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let peaks = peaks(leaves);
+        for peak in &peaks {
+            if !peak.contains(leaf_index) {
+                continue;
+            }
+            let offset = leaf_index - peak.first_leaf;
+            let mut pos = peak.pos;
+            for height in (1..peak.height + 1).rev() {
+                let left = pos - (1 << height);
+                let right = pos - 1;
+                if offset & (1 << (height - 1)) == 0 {
+                    path.push(self.storage.node(right));
+                    pos = left;
+                } else {
+                    path.push(self.storage.node(left));
+                    pos = right;
+                }
+            }
+            path.reverse();
+        }
+
+        Some(MmrProof {
+            leaf_index,
+            leaves,
+            path,
+            peaks: peaks
+                .into_iter()
+                .map(|peak| self.storage.node(peak.pos))
+                .collect(),
+        })
+    }
+}
+
+impl MmrProof {
+    /// Computes the range root for the given leaf data.
+    /// Returns `None` if the proof is malformed or does not match the leaf.
+    pub fn root(&self, data: &Hash) -> Option<Hash> {
+        if self.leaf_index >= self.leaves {
+            return None;
+        }
+        let peaks = peaks(self.leaves);
+        if peaks.len() != self.peaks.len() {
+            return None;
+        }
+
+        let index = peaks.iter().position(|peak| peak.contains(self.leaf_index))?;
+        let peak = &peaks[index];
+        if self.path.len() != peak.height as usize {
+            return None;
+        }
+
+        let offset = self.leaf_index - peak.first_leaf;
+        let mut node = leaf_hash(data);
+        for (level, sibling) in self.path.iter().enumerate() {
+            node = if offset & (1 << level) == 0 {
+                node_hash(&node, sibling)
+            } else {
+                node_hash(sibling, &node)
+            };
+        }
+        if node != self.peaks[index] {
+            return None;
+        }
+        Some(bag_peaks(&self.peaks))
+    }
+
+    /// Checks that the leaf with the given data is included into the range with the given root.
+    pub fn verify(&self, data: &Hash, root: &Hash) -> bool {
+        self.root(data).as_ref() == Some(root)
+    }
+}
+
+#[derive(Debug)]
+struct Peak {
+    pos: u64,
+    height: u32,
+    first_leaf: u64,
+}
+
+impl Peak {
+    fn contains(&self, leaf_index: u64) -> bool {
+        leaf_index >= self.first_leaf && leaf_index - self.first_leaf < 1 << self.height
+    }
+}
+
+fn peaks(leaves: u64) -> Vec<Peak> {
+    let mut peaks = Vec::new();
+    let mut size = 0;
+    let mut first_leaf = 0;
+    for height in (0..MAX_HEIGHT).rev() {
+        if leaves & (1 << height) != 0 {
+            size += (1 << (height + 1)) - 1;
+            peaks.push(Peak {
+                pos: size - 1,
+                height,
+                first_leaf,
+            });
+            first_leaf += 1 << height;
+        }
+    }
+    peaks
+}
+
+fn leaf_hash(data: &Hash) -> Hash {
+    let mut buf = Vec::with_capacity(33);
+    buf.push(LEAF_PREFIX);
+    buf.extend_from_slice(data.as_ref());
+    hash(&buf)
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    prefixed_pair_hash(NODE_PREFIX, left, right)
+}
+
+fn bag_peaks(peaks: &[Hash]) -> Hash {
+    let mut peaks = peaks.iter().rev();
+    let mut root = *peaks.next().expect("Merkle mountain range is empty");
+    for peak in peaks {
+        root = prefixed_pair_hash(PEAKS_PREFIX, peak, &root);
+    }
+    root
+}
+
+fn prefixed_pair_hash(prefix: u8, left: &Hash, right: &Hash) -> Hash {
+    let mut buf = Vec::with_capacity(65);
+    buf.push(prefix);
+    buf.extend_from_slice(left.as_ref());
+    buf.extend_from_slice(right.as_ref());
+    hash(&buf)
+}
@@ -17,7 +17,16 @@ mod macros;
 
 pub mod btc;
 pub mod rpc;
+pub mod notifier;
+pub mod cache;
+pub mod spv;
+pub mod mmr;
+pub mod ots;
+pub mod psbt;
+pub mod signer;
+pub mod keystore;
 pub mod error;
+pub mod sweep;
 
 #[cfg(test)]
 pub mod tests;
@@ -0,0 +1,162 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Notifications about the new bitcoin blocks.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use details::rpc::{AnchoringRpcConfig, Result, RpcConnection};
+use observer::Milliseconds;
+
+/// Notifier configuration.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChainNotifierConfig {
+    /// If this option enabled the handler and the observer are woken up by
+    /// the new bitcoin blocks.
+    pub enabled: bool,
+    /// Timeout of the `waitfornewblock` long-poll request.
+    pub timeout: Milliseconds,
+}
+
+impl Default for ChainNotifierConfig {
+    fn default() -> ChainNotifierConfig {
+        ChainNotifierConfig {
+            enabled: false,
+            timeout: 30_000,
+        }
+    }
+}
+
+/// An event in the bitcoin blockchain.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChainEvent {
+    /// Hash of the new best block.
+    pub hash: String,
+    /// Height of the new best block.
+    pub height: u64,
+}
+
+/// Source of the bitcoin blockchain events.
+pub trait ChainNotifier: 'static + ::std::fmt::Debug + Send {
+    /// Waits for the next event no longer than the given `timeout`.
+    /// Returns `None` if there is no events during this time.
+    fn wait_event(&mut self, timeout: Duration) -> Result<Option<ChainEvent>>;
+}
+
+/// Notifier based on the `waitfornewblock` long-poll rpc call.
+#[derive(Debug)]
+pub struct LongPollNotifier {
+    client: RpcConnection,
+    best_block: Option<String>,
+}
+
+impl LongPollNotifier {
+    /// Creates a notifier for the given rpc configuration.
+    pub fn new(rpc: AnchoringRpcConfig) -> LongPollNotifier {
+        LongPollNotifier {
+            client: RpcConnection::new(rpc),
+            best_block: None,
+        }
+    }
+}
+
+impl ChainNotifier for LongPollNotifier {
+    fn wait_event(&mut self, timeout: Duration) -> Result<Option<ChainEvent>> {
+        let timeout = timeout.as_secs() * 1_000 + u64::from(timeout.subsec_nanos() / 1_000_000);
+        let event: ChainEvent = self.client
+            .call("waitfornewblock", vec![Value::from(timeout)])?;
+        // `waitfornewblock` returns the current tip on timeout.
+        if self.best_block.as_ref() == Some(&event.hash) {
+            return Ok(None);
+        }
+        self.best_block = Some(event.hash.clone());
+        Ok(Some(event))
+    }
+}
+
+/// Notifier that receives events from the channel, it is useful for tests.
+#[derive(Debug)]
+pub struct ChannelNotifier {
+    receiver: Receiver<ChainEvent>,
+}
+
+impl ChannelNotifier {
+    /// Creates a notifier and the sender of its events.
+    pub fn channel() -> (Sender<ChainEvent>, ChannelNotifier) {
+        let (sender, receiver) = mpsc::channel();
+        (sender, ChannelNotifier { receiver })
+    }
+}
+
+impl ChainNotifier for ChannelNotifier {
+    fn wait_event(&mut self, timeout: Duration) -> Result<Option<ChainEvent>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                // There will be no more events, so behave like the ordinary polling.
+                thread::sleep(timeout);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Flag that signals that the bitcoin blockchain has been changed since the last check.
+#[derive(Debug, Clone, Default)]
+pub struct ChainUpdates(Arc<AtomicBool>);
+
+impl ChainUpdates {
+    /// Marks that there are new events in the bitcoin blockchain.
+    pub fn notify(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if there were events since the last call and resets the flag.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+
+    /// Spawns a thread that listens to the `notifier` and marks updates.
+    ///
+    /// The thread stops after all the other copies of the flag have been dropped,
+    /// since there is nobody to receive the updates anymore.
+    pub fn listen(
+        &self,
+        mut notifier: Box<ChainNotifier>,
+        timeout: Milliseconds,
+    ) -> thread::JoinHandle<()> {
+        let updates = self.clone();
+        let timeout = Duration::from_millis(timeout);
+        thread::spawn(move || while Arc::strong_count(&updates.0) > 1 {
+            match notifier.wait_event(timeout) {
+                Ok(Some(event)) => {
+                    trace!("Received a new bitcoin block, event={:?}", event);
+                    updates.notify();
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("An error during waiting for a new block occurred, msg={:?}", e);
+                    thread::sleep(timeout);
+                }
+            }
+        })
+    }
+}
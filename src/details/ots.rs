@@ -0,0 +1,111 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of the anchoring proofs into the [OpenTimestamps][1] format.
+//!
+//! The exported timestamp proves that the anchored Exonum block hash existed
+//! by the time of the bitcoin block that contains the anchoring transaction.
+//! The block hash is used as the `sha256` digest of the timestamped file, the chain of
+//! operations turns it into the anchoring transaction id and then into the bitcoin
+//! block merkle root, which is checked by the bitcoin block header attestation.
+//!
+//! [1]: https://opentimestamps.org
+
+use bitcoin::network::serialize::serialize;
+
+use details::btc::payload::Payload;
+use details::btc::transactions::AnchoringTx;
+use details::spv::{MerkleStep, TxInclusion};
+
+/// Magic bytes of the detached timestamp file.
+const HEADER_MAGIC: &[u8] = b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94";
+/// Major version of the timestamp file format.
+const MAJOR_VERSION: u64 = 1;
+/// Tag of the attestation.
+const ATTESTATION_TAG: u8 = 0x00;
+/// Tag of the bitcoin block header attestation.
+const BITCOIN_ATTESTATION_TAG: &[u8] = b"\x05\x88\x96\x0d\x73\xd7\x19\x01";
+/// Tag of the `sha256` operation.
+const OP_SHA256: u8 = 0x08;
+/// Tag of the `append` operation.
+const OP_APPEND: u8 = 0xf0;
+/// Tag of the `prepend` operation.
+const OP_PREPEND: u8 = 0xf1;
+
+/// Creates the detached `.ots` timestamp of the block hash from the anchoring
+/// transaction `payload`.
+///
+/// Returns `None` if the transaction does not contain the given payload.
+pub fn timestamp(tx: &AnchoringTx, payload: &Payload, inclusion: &TxInclusion) -> Option<Vec<u8>> {
+    // The transaction id commits to the transaction without the witness data.
+    let mut raw_tx = tx.0.clone();
+    raw_tx.witness.clear();
+    let tx_bytes = serialize(&raw_tx).ok()?;
+    let digest = payload.block_hash.as_ref();
+    let pos = tx_bytes
+        .windows(digest.len())
+        .position(|window| window == digest)?;
+
+    let mut buf = HEADER_MAGIC.to_vec();
+    write_varuint(&mut buf, MAJOR_VERSION);
+    buf.push(OP_SHA256);
+    buf.extend_from_slice(digest);
+
+    // Block hash -> anchoring transaction id.
+    write_binary_op(&mut buf, OP_PREPEND, &tx_bytes[..pos]);
+    write_binary_op(&mut buf, OP_APPEND, &tx_bytes[pos + digest.len()..]);
+    buf.push(OP_SHA256);
+    buf.push(OP_SHA256);
+    // Transaction id -> block merkle root.
+    for step in &inclusion.branch {
+        match *step {
+            MerkleStep::Left(ref sibling) => write_binary_op(&mut buf, OP_PREPEND, sibling),
+            MerkleStep::Right(ref sibling) => write_binary_op(&mut buf, OP_APPEND, sibling),
+        }
+        buf.push(OP_SHA256);
+        buf.push(OP_SHA256);
+    }
+
+    let mut attestation = Vec::new();
+    write_varuint(&mut attestation, inclusion.block_height);
+    buf.push(ATTESTATION_TAG);
+    buf.extend_from_slice(BITCOIN_ATTESTATION_TAG);
+    write_varbytes(&mut buf, &attestation);
+    Some(buf)
+}
+
+fn write_binary_op(buf: &mut Vec<u8>, tag: u8, arg: &[u8]) {
+    // Empty arguments are not allowed by the format.
+    if !arg.is_empty() {
+        buf.push(tag);
+        write_varbytes(buf, arg);
+    }
+}
+
+fn write_varbytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_varuint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_varuint(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
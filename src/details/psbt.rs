@@ -0,0 +1,363 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Partially signed bitcoin transactions as specified by BIP174.
+//!
+//! Only the fields that are required to sign the anchoring transaction inputs are
+//! interpreted, the other fields are kept as is, so they survive the round trip.
+
+use base64;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::TxOut;
+use bitcoin::network::serialize::{deserialize, serialize};
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey as RawPublicKey;
+
+use exonum::encoding::serialize::encode_hex;
+
+use details::btc;
+use details::btc::transactions::RawBitcoinTx;
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+
+const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+
+/// Raw key-value pair of the PSBT map.
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// Reason why the PSBT can not be decoded.
+#[derive(Debug, Display, Fail, Clone, PartialEq)]
+pub enum PsbtError {
+    /// The data is not a correct base64 string.
+    #[display(fmt = "Incorrect base64 encoding of PSBT")]
+    IncorrectBase64,
+    /// The data does not start with the PSBT magic bytes.
+    #[display(fmt = "Incorrect PSBT magic bytes")]
+    IncorrectMagic,
+    /// The data ends in the middle of a key-value map.
+    #[display(fmt = "Unexpected end of PSBT data")]
+    UnexpectedEnd,
+    /// The global map does not contain the unsigned transaction.
+    #[display(fmt = "PSBT does not contain the unsigned transaction")]
+    MissingUnsignedTx,
+    /// The map contains the same key twice.
+    #[display(fmt = "Duplicate PSBT key {}", _0)]
+    DuplicateKey(String),
+    /// The value can not be decoded for the given key.
+    #[display(fmt = "Incorrect PSBT value for the key {}", _0)]
+    IncorrectValue(String),
+}
+
+/// Partially signed bitcoin transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Psbt {
+    /// The transaction with the empty scriptSigs and witnesses.
+    pub unsigned_tx: RawBitcoinTx,
+    /// The signing data of each input of the transaction.
+    pub inputs: Vec<PsbtInput>,
+    /// The data of each output of the transaction.
+    pub outputs: Vec<PsbtOutput>,
+    /// The unknown global fields.
+    pub unknown: Vec<KeyValue>,
+}
+
+/// Signing data of the transaction input.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PsbtInput {
+    /// The transaction that contains the spent output.
+    pub non_witness_utxo: Option<RawBitcoinTx>,
+    /// The spent output of the segregated witness input.
+    pub witness_utxo: Option<TxOut>,
+    /// The signatures with the sighash type byte by the public keys.
+    pub partial_sigs: Vec<(btc::PublicKey, btc::Signature)>,
+    /// The sighash type of the signatures.
+    pub sighash_type: Option<u32>,
+    /// The redeem script of the spent P2SH output.
+    pub redeem_script: Option<btc::RedeemScript>,
+    /// The witness script of the spent P2WSH output.
+    pub witness_script: Option<btc::RedeemScript>,
+    /// The unknown fields.
+    pub unknown: Vec<KeyValue>,
+}
+
+/// Data of the transaction output.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PsbtOutput {
+    /// The redeem script of the P2SH output.
+    pub redeem_script: Option<btc::RedeemScript>,
+    /// The witness script of the P2WSH output.
+    pub witness_script: Option<btc::RedeemScript>,
+    /// The unknown fields.
+    pub unknown: Vec<KeyValue>,
+}
+
+impl Psbt {
+    /// Creates PSBT without the signing data for the given transaction.
+    /// The scriptSigs and witnesses of the transaction are removed.
+    pub fn from_unsigned_tx(mut tx: RawBitcoinTx) -> Psbt {
+        for input in &mut tx.input {
+            input.script_sig = Script::new();
+        }
+        tx.witness = vec![];
+        Psbt {
+            inputs: vec![PsbtInput::default(); tx.input.len()],
+            outputs: vec![PsbtOutput::default(); tx.output.len()],
+            unsigned_tx: tx,
+            unknown: vec![],
+        }
+    }
+
+    /// Returns the signature of the given `input` by the `pub_key` if there is one.
+    pub fn partial_sig(&self, input: usize, pub_key: &btc::PublicKey) -> Option<&btc::Signature> {
+        self.inputs
+            .get(input)?
+            .partial_sigs
+            .iter()
+            .find(|&&(ref key, _)| key == pub_key)
+            .map(|&(_, ref signature)| signature)
+    }
+
+    /// Serializes PSBT into the binary format.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = PSBT_MAGIC.to_vec();
+
+        write_pair(
+            &mut out,
+            &[PSBT_GLOBAL_UNSIGNED_TX],
+            &serialize(&self.unsigned_tx).unwrap(),
+        );
+        write_unknown(&mut out, &self.unknown);
+
+        for input in &self.inputs {
+            if let Some(ref tx) = input.non_witness_utxo {
+                write_pair(&mut out, &[PSBT_IN_NON_WITNESS_UTXO], &serialize(tx).unwrap());
+            }
+            if let Some(ref output) = input.witness_utxo {
+                write_pair(&mut out, &[PSBT_IN_WITNESS_UTXO], &serialize(output).unwrap());
+            }
+            for &(ref pub_key, ref signature) in &input.partial_sigs {
+                let mut key = vec![PSBT_IN_PARTIAL_SIG];
+                key.extend_from_slice(&pub_key.serialize());
+                write_pair(&mut out, &key, signature);
+            }
+            if let Some(sighash_type) = input.sighash_type {
+                let mut value = Vec::new();
+                value.write_u32::<LittleEndian>(sighash_type).unwrap();
+                write_pair(&mut out, &[PSBT_IN_SIGHASH_TYPE], &value);
+            }
+            if let Some(ref script) = input.redeem_script {
+                write_pair(&mut out, &[PSBT_IN_REDEEM_SCRIPT], &script.0.clone().into_vec());
+            }
+            if let Some(ref script) = input.witness_script {
+                write_pair(&mut out, &[PSBT_IN_WITNESS_SCRIPT], &script.0.clone().into_vec());
+            }
+            write_unknown(&mut out, &input.unknown);
+        }
+
+        for output in &self.outputs {
+            if let Some(ref script) = output.redeem_script {
+                write_pair(&mut out, &[PSBT_OUT_REDEEM_SCRIPT], &script.0.clone().into_vec());
+            }
+            if let Some(ref script) = output.witness_script {
+                write_pair(&mut out, &[PSBT_OUT_WITNESS_SCRIPT], &script.0.clone().into_vec());
+            }
+            write_unknown(&mut out, &output.unknown);
+        }
+        out
+    }
+
+    /// Deserializes PSBT from the binary format.
+    pub fn deserialize(data: &[u8]) -> Result<Psbt, PsbtError> {
+        if !data.starts_with(PSBT_MAGIC) {
+            return Err(PsbtError::IncorrectMagic);
+        }
+        let mut reader = Reader {
+            data,
+            pos: PSBT_MAGIC.len(),
+        };
+
+        let mut unsigned_tx = None;
+        let mut unknown = Vec::new();
+        for (key, value) in reader.read_map()? {
+            if key == [PSBT_GLOBAL_UNSIGNED_TX] {
+                let tx = deserialize::<RawBitcoinTx>(&value).map_err(|_| incorrect_value(&key))?;
+                unsigned_tx = Some(tx);
+            } else {
+                unknown.push((key, value));
+            }
+        }
+        let unsigned_tx = unsigned_tx.ok_or(PsbtError::MissingUnsignedTx)?;
+
+        let mut inputs = Vec::new();
+        for _ in 0..unsigned_tx.input.len() {
+            let mut input = PsbtInput::default();
+            for (key, value) in reader.read_map()? {
+                match (key[0], key.len()) {
+                    (PSBT_IN_NON_WITNESS_UTXO, 1) => {
+                        let tx = deserialize(&value).map_err(|_| incorrect_value(&key))?;
+                        input.non_witness_utxo = Some(tx);
+                    }
+                    (PSBT_IN_WITNESS_UTXO, 1) => {
+                        let output = deserialize(&value).map_err(|_| incorrect_value(&key))?;
+                        input.witness_utxo = Some(output);
+                    }
+                    (PSBT_IN_PARTIAL_SIG, _) => {
+                        let context = Secp256k1::without_caps();
+                        let pub_key = RawPublicKey::from_slice(&context, &key[1..])
+                            .map_err(|_| incorrect_value(&key))?;
+                        input.partial_sigs.push((pub_key.into(), value));
+                    }
+                    (PSBT_IN_SIGHASH_TYPE, 1) => {
+                        if value.len() != 4 {
+                            return Err(incorrect_value(&key));
+                        }
+                        input.sighash_type = Some(LittleEndian::read_u32(&value));
+                    }
+                    (PSBT_IN_REDEEM_SCRIPT, 1) => {
+                        input.redeem_script = Some(btc::RedeemScript(Script::from(value)));
+                    }
+                    (PSBT_IN_WITNESS_SCRIPT, 1) => {
+                        input.witness_script = Some(btc::RedeemScript(Script::from(value)));
+                    }
+                    _ => input.unknown.push((key, value)),
+                }
+            }
+            inputs.push(input);
+        }
+
+        let mut outputs = Vec::new();
+        for _ in 0..unsigned_tx.output.len() {
+            let mut output = PsbtOutput::default();
+            for (key, value) in reader.read_map()? {
+                match (key[0], key.len()) {
+                    (PSBT_OUT_REDEEM_SCRIPT, 1) => {
+                        output.redeem_script = Some(btc::RedeemScript(Script::from(value)));
+                    }
+                    (PSBT_OUT_WITNESS_SCRIPT, 1) => {
+                        output.witness_script = Some(btc::RedeemScript(Script::from(value)));
+                    }
+                    _ => output.unknown.push((key, value)),
+                }
+            }
+            outputs.push(output);
+        }
+
+        Ok(Psbt {
+            unsigned_tx,
+            inputs,
+            outputs,
+            unknown,
+        })
+    }
+
+    /// Returns the base64 representation of PSBT, which is accepted by the wallets.
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.serialize())
+    }
+
+    /// Decodes PSBT from the base64 representation.
+    pub fn from_base64(s: &str) -> Result<Psbt, PsbtError> {
+        let data = base64::decode(s.trim()).map_err(|_| PsbtError::IncorrectBase64)?;
+        Psbt::deserialize(&data)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], PsbtError> {
+        if self.data.len() - self.pos < len {
+            return Err(PsbtError::UnexpectedEnd);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_compact_size(&mut self) -> Result<usize, PsbtError> {
+        let value = match self.read_bytes(1)?[0] {
+            0xfd => u64::from(LittleEndian::read_u16(self.read_bytes(2)?)),
+            0xfe => u64::from(LittleEndian::read_u32(self.read_bytes(4)?)),
+            0xff => LittleEndian::read_u64(self.read_bytes(8)?),
+            prefix => u64::from(prefix),
+        };
+        Ok(value as usize)
+    }
+
+    // Reads the key-value pairs up to the separator.
+    fn read_map(&mut self) -> Result<Vec<KeyValue>, PsbtError> {
+        let mut map: Vec<KeyValue> = Vec::new();
+        loop {
+            let key_len = self.read_compact_size()?;
+            if key_len == 0 {
+                return Ok(map);
+            }
+            let key = self.read_bytes(key_len)?.to_vec();
+            let value_len = self.read_compact_size()?;
+            let value = self.read_bytes(value_len)?.to_vec();
+            if map.iter().any(|&(ref other, _)| *other == key) {
+                return Err(PsbtError::DuplicateKey(encode_hex(&key)));
+            }
+            map.push((key, value));
+        }
+    }
+}
+
+fn write_compact_size(out: &mut Vec<u8>, value: usize) {
+    if value < 0xfd {
+        out.push(value as u8);
+    } else if value <= 0xffff {
+        out.push(0xfd);
+        out.write_u16::<LittleEndian>(value as u16).unwrap();
+    } else if value <= 0xffff_ffff {
+        out.push(0xfe);
+        out.write_u32::<LittleEndian>(value as u32).unwrap();
+    } else {
+        out.push(0xff);
+        out.write_u64::<LittleEndian>(value as u64).unwrap();
+    }
+}
+
+fn write_pair(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    write_compact_size(out, key.len());
+    out.extend_from_slice(key);
+    write_compact_size(out, value.len());
+    out.extend_from_slice(value);
+}
+
+// Writes the unknown pairs and the map separator.
+fn write_unknown(out: &mut Vec<u8>, unknown: &[KeyValue]) {
+    for &(ref key, ref value) in unknown {
+        write_pair(out, key, value);
+    }
+    out.push(0x00);
+}
+
+fn incorrect_value(key: &[u8]) -> PsbtError {
+    PsbtError::IncorrectValue(encode_hex(key))
+}
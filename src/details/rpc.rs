@@ -12,14 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::string::ToString;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use bitcoinrpc;
+use hyper;
+use jsonrpc;
+use serde::Deserialize;
+use serde_json;
+use serde_json::Value;
 
 use exonum::encoding::serialize::FromHex;
 
 use details::btc;
+use details::btc::chain::ChainParams;
 use details::btc::transactions::{BitcoinTx, FundingTx, TxKind};
+use details::cache::{CacheStats, CachedRelay, RelayCacheConfig};
+use details::spv::{SpvConfig, SpvRelay};
 
 pub use bitcoinrpc::Client as RpcClient;
 
@@ -32,7 +50,16 @@ pub type Error = bitcoinrpc::Error;
 /// which measures amounts in bitcoins (rather than satoshis).
 pub const SATOSHI_DIVISOR: f64 = 100_000_000.0;
 
+/// Name of the environment variable with the `bitcoind` rpc username.
+pub const RPC_USER_ENV: &str = "ANCHORING_RPC_USER";
+/// Name of the environment variable with the `bitcoind` rpc password.
+pub const RPC_PASSWORD_ENV: &str = "ANCHORING_RPC_PASSWORD";
+
 /// `Bitcoind` rpc configuration.
+///
+/// Credentials are resolved in the following order: explicit `username` and `password`,
+/// the `ANCHORING_RPC_USER` and `ANCHORING_RPC_PASSWORD` environment variables,
+/// the `credentials_file` and finally the `bitcoind` `cookie_file`.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AnchoringRpcConfig {
     /// Rpc url.
@@ -41,6 +68,126 @@ pub struct AnchoringRpcConfig {
     pub username: Option<String>,
     /// Rpc password.
     pub password: Option<String>,
+    /// Path to the file with credentials in the `username:password` format.
+    #[serde(default)]
+    pub credentials_file: Option<PathBuf>,
+    /// Path to the `.cookie` file that `bitcoind` creates in its data directory
+    /// when the `rpcpassword` option is not set.
+    #[serde(default)]
+    pub cookie_file: Option<PathBuf>,
+    /// The way to discover unspent outputs of the anchoring address.
+    #[serde(default)]
+    pub utxo_discovery: UtxoDiscovery,
+    /// Cache of the relay lookups.
+    #[serde(default)]
+    pub cache: RelayCacheConfig,
+    /// Verification of confirmations by the local header chain.
+    #[serde(default)]
+    pub spv: Option<SpvConfig>,
+}
+
+/// Methods to discover unspent outputs of the anchoring address.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UtxoDiscovery {
+    /// Watches the address via the legacy `importaddress` and lists outputs via
+    /// the wallet `listunspent` call.
+    Wallet,
+    /// Watches the address via `importdescriptors` with the `addr(...)` descriptor,
+    /// this method is suitable for the descriptor wallets.
+    Descriptors,
+    /// Scans the UTXO set via `scantxoutset`, this method does not need a wallet at all,
+    /// but it does not see unconfirmed outputs.
+    ScanTxOutSet,
+}
+
+impl Default for UtxoDiscovery {
+    fn default() -> UtxoDiscovery {
+        UtxoDiscovery::Wallet
+    }
+}
+
+impl FromStr for UtxoDiscovery {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<UtxoDiscovery, String> {
+        match s {
+            "wallet" => Ok(UtxoDiscovery::Wallet),
+            "descriptors" => Ok(UtxoDiscovery::Descriptors),
+            "scantxoutset" => Ok(UtxoDiscovery::ScanTxOutSet),
+            other => Err(format!("Unknown utxo discovery method: {}", other)),
+        }
+    }
+}
+
+impl Default for AnchoringRpcConfig {
+    fn default() -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            host: "http://127.0.0.1:18332".to_owned(),
+            username: None,
+            password: None,
+            credentials_file: None,
+            cookie_file: None,
+            utxo_discovery: UtxoDiscovery::default(),
+            cache: RelayCacheConfig::default(),
+            spv: None,
+        }
+    }
+}
+
+impl AnchoringRpcConfig {
+    /// Returns the rpc username and password according to the resolution order.
+    ///
+    /// Note that `bitcoind` regenerates the cookie file on each restart, so the credentials
+    /// should be resolved again every time a new client is created.
+    pub fn credentials(&self) -> io::Result<(Option<String>, Option<String>)> {
+        self.resolve_credentials(env::var(RPC_USER_ENV).ok(), env::var(RPC_PASSWORD_ENV).ok())
+    }
+
+    /// Resolves the credentials with the given values of the environment variables.
+    pub(crate) fn resolve_credentials(
+        &self,
+        env_username: Option<String>,
+        env_password: Option<String>,
+    ) -> io::Result<(Option<String>, Option<String>)> {
+        if self.username.is_some() || self.password.is_some() {
+            return Ok((self.username.clone(), self.password.clone()));
+        }
+
+        if let Some(username) = env_username {
+            return Ok((Some(username), env_password));
+        }
+
+        if let Some(ref path) = self.credentials_file {
+            return read_credentials_file(path).map(|(user, passwd)| (Some(user), Some(passwd)));
+        }
+
+        if let Some(ref path) = self.cookie_file {
+            return read_credentials_file(path).map(|(user, passwd)| (Some(user), Some(passwd)));
+        }
+
+        Ok((None, None))
+    }
+
+    /// Creates a client with the currently resolved credentials.
+    pub fn client(&self) -> io::Result<RpcClient> {
+        let (username, password) = self.credentials()?;
+        Ok(RpcClient::new(self.host.clone(), username, password))
+    }
+
+    /// Creates a relay that uses the configured UTXO discovery method, SPV verification
+    /// and cache. The addresses are passed to `bitcoind` in the format of the given `chain`.
+    pub fn into_relay(self, chain: ChainParams) -> Box<BitcoinRelay> {
+        let mut relay: Box<BitcoinRelay> = Box::new(RpcRelay::new(self.clone(), chain));
+        if let Some(ref spv) = self.spv {
+            let client = RpcConnection::new(self.clone());
+            relay = Box::new(SpvRelay::new(relay, client, spv.clone()));
+        }
+        if self.cache.enabled {
+            relay = Box::new(CachedRelay::new(relay, &self.cache));
+        }
+        relay
+    }
 }
 
 /// Client for the `Bitcoind` rpc api, for more information visit
@@ -48,12 +195,171 @@ pub struct AnchoringRpcConfig {
 #[derive(Debug)]
 pub struct AnchoringRpc(pub RpcClient);
 
-impl From<AnchoringRpcConfig> for RpcClient {
-    fn from(cfg: AnchoringRpcConfig) -> Self {
-        RpcClient::new(cfg.host, cfg.username, cfg.password)
+/// Connection to the `bitcoind` rpc api.
+///
+/// The credentials are resolved on the first request and again after each transport
+/// failure, such as the authorization error after a `bitcoind` restart that has
+/// regenerated the cookie file.
+pub struct RpcConnection {
+    config: AnchoringRpcConfig,
+    clients: Mutex<Option<Arc<RpcClients>>>,
+}
+
+// Typed and raw clients that share the same credentials.
+struct RpcClients {
+    typed: RpcClient,
+    raw: jsonrpc::client::Client,
+}
+
+impl RpcConnection {
+    /// Creates a connection for the given configuration.
+    pub fn new(config: AnchoringRpcConfig) -> RpcConnection {
+        RpcConnection {
+            config,
+            clients: Mutex::new(None),
+        }
+    }
+
+    /// Returns the configuration of the connection.
+    pub fn config(&self) -> &AnchoringRpcConfig {
+        &self.config
+    }
+
+    /// Performs the request by the typed client.
+    pub fn request<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&RpcClient) -> Result<T>,
+    {
+        self.with_clients(true, |clients| f(&clients.typed))
+    }
+
+    /// Performs the request by the typed client without retries.
+    ///
+    /// Should be used for the requests that change the `bitcoind` state, such as
+    /// `sendtoaddress` or `sendrawtransaction`, which must never be repeated.
+    pub fn request_once<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&RpcClient) -> Result<T>,
+    {
+        self.with_clients(false, |clients| f(&clients.typed))
+    }
+
+    /// Sends the request for the `bitcoind` rpc method that has no typed wrapper
+    /// in the `RpcClient`.
+    pub fn call<T>(&self, method: &str, params: Vec<Value>) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.with_clients(true, |clients| {
+            let request = clients.raw.build_request(method.to_owned(), params.clone());
+            let response = clients.raw.send_request(&request)?;
+            Ok(response.into_result::<T>()?)
+        })
+    }
+
+    // The request that has not reached `bitcoind` or has been rejected by its
+    // authorization is retried once with the credentials resolved again. Other errors
+    // may occur after `bitcoind` has already executed the request, so such requests
+    // are never retried, but the next request uses the new credentials anyway.
+    fn with_clients<T, F>(&self, retry: bool, f: F) -> Result<T>
+    where
+        F: Fn(&RpcClients) -> Result<T>,
+    {
+        match f(&*self.clients(false)?) {
+            Err(ref e) if retry && is_unexecuted_request_error(e) => {
+                warn!("Reconnecting to the bitcoind rpc after the error: {}", e);
+                f(&*self.clients(true)?)
+            }
+            Err(e) => {
+                if is_transport_error(&e) {
+                    self.clients.lock().unwrap().take();
+                }
+                Err(e)
+            }
+            result => result,
+        }
+    }
+
+    fn clients(&self, reconnect: bool) -> Result<Arc<RpcClients>> {
+        let mut clients = self.clients.lock().unwrap();
+        if reconnect || clients.is_none() {
+            let host = self.config.host.clone();
+            let (username, password) = self.config.credentials()?;
+            *clients = Some(Arc::new(RpcClients {
+                typed: RpcClient::new(host.clone(), username.clone(), password.clone()),
+                raw: jsonrpc::client::Client::new(host, username, password),
+            }));
+        }
+        Ok(Arc::clone(clients.as_ref().unwrap()))
     }
 }
 
+impl fmt::Debug for RpcConnection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RpcConnection")
+            .field("host", &self.config.host)
+            .finish()
+    }
+}
+
+fn is_transport_error(e: &Error) -> bool {
+    match *e {
+        Error::Rpc(jsonrpc::Error::Client(_))
+        | Error::Rpc(jsonrpc::Error::Json(_))
+        | Error::Other(_) => true,
+        _ => false,
+    }
+}
+
+/// Checks that the request has certainly not been executed by `bitcoind`: either the
+/// connection has failed or `bitcoind` has rejected the credentials. In the latter case
+/// `bitcoind` responds with the empty body, which cannot be parsed as json.
+fn is_unexecuted_request_error(e: &Error) -> bool {
+    let cause = match *e {
+        Error::Rpc(jsonrpc::Error::Client(ref e)) => e.get_ref(),
+        _ => return false,
+    };
+    match cause {
+        Some(cause) => {
+            if let Some(e) = cause.downcast_ref::<hyper::Error>() {
+                e.is_connect()
+            } else if let Some(e) = cause.downcast_ref::<serde_json::Error>() {
+                e.is_eof() && e.line() == 1 && e.column() == 0
+            } else {
+                false
+            }
+        }
+        None => false,
+    }
+}
+
+/// Reads credentials in the `username:password` format, which is used both by the `bitcoind`
+/// cookie file and by the standalone credentials file.
+fn read_credentials_file(path: &Path) -> io::Result<(String, String)> {
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    parse_credentials(&content).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Credentials file {} should contain `username:password`",
+                path.display()
+            ),
+        )
+    })
+}
+
+/// Parses credentials in the `username:password` format.
+pub fn parse_credentials(content: &str) -> Option<(String, String)> {
+    let content = content.trim();
+    let separator = content.find(':')?;
+    let (username, password) = (&content[..separator], &content[separator + 1..]);
+    if username.is_empty() {
+        return None;
+    }
+    Some((username.to_owned(), password.to_owned()))
+}
+
 /// Short information about bitcoin transaction.
 #[derive(Clone, Debug)]
 pub struct TxInfo {
@@ -100,6 +406,11 @@ pub trait BitcoinRelay: 'static + ::std::fmt::Debug + Send + Sync {
 
     /// Returns an actual relay configuration.
     fn config(&self) -> AnchoringRpcConfig;
+
+    /// Returns the statistics of the relay cache if the lookups are cached.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
 }
 
 impl BitcoinRelay for RpcClient {
@@ -122,7 +433,7 @@ impl BitcoinRelay for RpcClient {
     }
 
     fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
-        self.importaddress(&addr.to_string(), "multisig", false, rescan)
+        import_address(self, &addr.to_string(), rescan)
     }
 
     fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
@@ -141,12 +452,163 @@ impl BitcoinRelay for RpcClient {
     }
 
     fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
-        let unspent_txs = self.listunspent(0, 9_999_999, &[addr.to_string()])?;
+        list_unspent(self, &addr.to_string())
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            host: self.url().to_string(),
+            username: self.username().clone(),
+            password: self.password().clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn import_address(client: &RpcClient, addr: &str, rescan: bool) -> Result<()> {
+    client.importaddress(addr, "multisig", false, rescan)
+}
+
+fn list_unspent(client: &RpcClient, addr: &str) -> Result<Vec<TxInfo>> {
+    let unspent_txs = client.listunspent(0, 9_999_999, &[addr.to_owned()])?;
+    let mut txs = Vec::new();
+    for info in unspent_txs {
+        let txid = btc::TxId::from_hex(&info.txid).unwrap();
+        let confirmations = Some(info.confirmations);
+        if let Some(raw_tx) = client.get_transaction(txid)? {
+            match TxKind::from(raw_tx) {
+                TxKind::Anchoring(tx) => txs.push(TxInfo {
+                    body: tx.into(),
+                    confirmations,
+                }),
+                TxKind::FundingTx(tx) => txs.push(TxInfo {
+                    body: tx.into(),
+                    confirmations,
+                }),
+                TxKind::Other(_) => {}
+            }
+        }
+    }
+    Ok(txs)
+}
+
+/// Number of attempts to start the UTXO set scan while another scan is in progress.
+const SCAN_ATTEMPTS: u32 = 10;
+/// Delay in milliseconds between the attempts to start the UTXO set scan.
+const SCAN_RETRY_DELAY: u64 = 500;
+
+/// Relay that discovers the anchoring address outputs by the configured `UtxoDiscovery`
+/// method.
+#[derive(Debug)]
+pub struct RpcRelay {
+    client: RpcConnection,
+    chain: ChainParams,
+}
+
+#[derive(Deserialize, Debug)]
+struct DescriptorInfo {
+    descriptor: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportDescriptorResult {
+    success: bool,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScanTxOutSetResult {
+    height: u64,
+    unspents: Vec<ScanTxOutSetUnspent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScanTxOutSetUnspent {
+    txid: String,
+    height: u64,
+}
+
+impl RpcRelay {
+    /// Creates a relay for the given configuration and `chain`.
+    pub fn new(config: AnchoringRpcConfig, chain: ChainParams) -> RpcRelay {
+        RpcRelay {
+            client: RpcConnection::new(config),
+            chain,
+        }
+    }
+
+    fn address_string(&self, addr: &btc::Address) -> String {
+        addr.to_chain_string(&self.chain)
+    }
+
+    fn address_descriptor(&self, addr: &btc::Address) -> Result<String> {
+        let info: DescriptorInfo = self.client.call(
+            "getdescriptorinfo",
+            vec![Value::String(format!("addr({})", self.address_string(addr)))],
+        )?;
+        Ok(info.descriptor)
+    }
+
+    fn import_descriptor(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        let descriptor = self.address_descriptor(addr)?;
+        let timestamp = if rescan { json!(0) } else { json!("now") };
+        let request = json!([{
+            "desc": descriptor,
+            "timestamp": timestamp,
+            "label": "multisig",
+        }]);
+        let results: Vec<ImportDescriptorResult> =
+            self.client.call("importdescriptors", vec![request])?;
+        match results.into_iter().find(|result| !result.success) {
+            Some(result) => Err(Error::NoInformation(format!(
+                "Unable to import descriptor {}: {}",
+                descriptor,
+                result.error.unwrap_or(Value::Null)
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // `bitcoind` runs only one UTXO set scan at a time, so the scan is retried if it
+    // has been started by another client, e.g. by the observer of the same node.
+    fn scan_tx_out_set(&self, addr: &btc::Address) -> Result<ScanTxOutSetResult> {
+        let descriptor = format!("addr({})", self.address_string(addr));
+        let params = vec![json!("start"), json!([descriptor])];
+        let mut attempt = 1;
+        loop {
+            match self.client.call("scantxoutset", params.clone()) {
+                Err(ref e) if attempt < SCAN_ATTEMPTS && is_scan_in_progress(e) => {
+                    warn!(
+                        "UTXO set scan is already in progress, attempt={}/{}",
+                        attempt, SCAN_ATTEMPTS
+                    );
+                    thread::sleep(Duration::from_millis(SCAN_RETRY_DELAY));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn scan_unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let scan = self.scan_tx_out_set(addr)?;
+
+        let mut known_txids = HashSet::new();
         let mut txs = Vec::new();
-        for info in unspent_txs {
-            let txid = btc::TxId::from_hex(&info.txid).unwrap();
-            let confirmations = Some(info.confirmations);
-            if let Some(raw_tx) = self.get_transaction(txid)? {
+        for unspent in scan.unspents {
+            if !known_txids.insert(unspent.txid.clone()) {
+                continue;
+            }
+
+            let txid = btc::TxId::from_hex(&unspent.txid).map_err(|_| {
+                Error::Other(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Incorrect txid {} in the UTXO set scan", unspent.txid),
+                ))
+            })?;
+            let confirmations = Some(scan.height.saturating_sub(unspent.height) + 1);
+            if let Some(raw_tx) = self.client.request(|client| client.get_transaction(txid))? {
                 match TxKind::from(raw_tx) {
                     TxKind::Anchoring(tx) => txs.push(TxInfo {
                         body: tx.into(),
@@ -162,14 +624,80 @@ impl BitcoinRelay for RpcClient {
         }
         Ok(txs)
     }
+}
 
-    fn config(&self) -> AnchoringRpcConfig {
-        AnchoringRpcConfig {
-            host: self.url().to_string(),
-            username: self.username().clone(),
-            password: self.password().clone(),
+impl BitcoinRelay for RpcRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        self.client.request(|client| client.get_transaction(txid))
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        self.client
+            .request(|client| client.get_transaction_info(txid))
+    }
+
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        match self.client.config().utxo_discovery {
+            UtxoDiscovery::Wallet => {
+                let addr = self.address_string(addr);
+                self.client
+                    .request(|client| import_address(client, &addr, rescan))
+            }
+            UtxoDiscovery::Descriptors => self.import_descriptor(addr, rescan),
+            // The UTXO set scan does not need to know addresses in advance.
+            UtxoDiscovery::ScanTxOutSet => Ok(()),
         }
     }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.client
+            .request_once(|client| client.send_transaction(tx.clone()))
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        let addr = self.address_string(addr);
+        let funds_str = (satoshis as f64 / SATOSHI_DIVISOR).to_string();
+        let utxo_txid = self.client
+            .request_once(|client| client.sendtoaddress(&addr, &funds_str))?;
+        let utxo_txid = btc::TxId::from_hex(&utxo_txid).map_err(|_| {
+            Error::Other(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Incorrect txid {} of the funding transaction", utxo_txid),
+            ))
+        })?;
+        // The funds have been sent, so only the lookup of the transaction may be retried.
+        match self.client.request(|client| client.get_transaction(utxo_txid))? {
+            Some(tx) => Ok(FundingTx::from(tx)),
+            None => Err(Error::NoInformation(format!(
+                "Unable to find the funding transaction {}",
+                utxo_txid.to_string()
+            ))),
+        }
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        match self.client.config().utxo_discovery {
+            UtxoDiscovery::Wallet | UtxoDiscovery::Descriptors => {
+                let addr = self.address_string(addr);
+                self.client.request(|client| list_unspent(client, &addr))
+            }
+            UtxoDiscovery::ScanTxOutSet => self.scan_unspent_transactions(addr),
+        }
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        self.client.config().clone()
+    }
+}
+
+fn is_scan_in_progress(e: &Error) -> bool {
+    match *e {
+        Error::Rpc(jsonrpc::Error::Rpc(ref value)) => value
+            .pointer("/message")
+            .and_then(Value::as_str)
+            .map_or(false, |msg| msg.starts_with("Scan already in progress")),
+        _ => false,
+    }
 }
 
 impl<'a, T: BitcoinRelay + 'a> From<T> for Box<BitcoinRelay> {
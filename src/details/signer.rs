@@ -0,0 +1,603 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signers of the anchoring transaction inputs.
+//!
+//! The remote signer forwards the requests to the separate signer daemon, so the anchoring
+//! keys may be kept in an isolated process or host. The daemon reads the requests as json
+//! lines and answers each of them with a json line, see `serve_connection`. The daemon may
+//! wrap its keys into the `PolicySigner`, which signs only the regular anchoring transactions
+//! even if the node is compromised.
+//!
+//! The tcp endpoint requires the `auth_token`, which is sent as the first line of each
+//! connection, the unix socket is protected by the file permissions instead.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::blockdata::script::Script;
+use serde_json;
+
+use exonum::encoding::serialize::{encode_hex, FromHex};
+
+use details::btc;
+use details::btc::transactions::{parse_payload, AnchoringTx, BitcoinTx};
+use details::keystore::{KeystoreConfig, KeystoreError};
+use local_storage::{deserialize_vec_to_map, serialize_map_to_vec};
+use observer::Milliseconds;
+
+/// Remote signer configuration.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RemoteSignerConfig {
+    /// If this option enabled the anchoring inputs are signed by the remote signer
+    /// instead of the `private_keys` of the node.
+    pub enabled: bool,
+    /// Endpoint of the signer daemon, `tcp://host:port` or `unix:///path/to/socket`.
+    pub endpoint: String,
+    /// Timeout of the signing request.
+    pub timeout: Milliseconds,
+    /// Token that authorizes the node on the daemon, it is required by the tcp endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+}
+
+impl Default for RemoteSignerConfig {
+    fn default() -> RemoteSignerConfig {
+        RemoteSignerConfig {
+            enabled: false,
+            endpoint: "tcp://127.0.0.1:9100".to_owned(),
+            timeout: 5_000,
+            auth_token: None,
+        }
+    }
+}
+
+/// Configuration of the signer daemon.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SignerDaemonConfig {
+    /// Endpoint on which the daemon listens, `tcp://host:port` or `unix:///path/to/socket`.
+    pub endpoint: String,
+    /// Token that the clients must send before the requests, it is required by the tcp
+    /// endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+    /// Set of private keys for each anchoring address.
+    #[serde(default, serialize_with = "serialize_map_to_vec",
+            deserialize_with = "deserialize_vec_to_map")]
+    pub private_keys: BTreeMap<String, btc::PrivateKey>,
+    /// Private keys encrypted by the passphrase, which are unlocked at startup.
+    #[serde(default)]
+    pub keystore: KeystoreConfig,
+    /// Policy of the signed transactions, it is enabled if the section is omitted.
+    #[serde(default)]
+    pub policy: SignerPolicyConfig,
+}
+
+impl SignerDaemonConfig {
+    /// Decrypts the `keystore` by the passphrase from the configured source if it is not empty.
+    pub fn unlock_keys(&mut self) -> Result<(), KeystoreError> {
+        let keys = self.keystore.unlock()?;
+        self.private_keys.extend(keys);
+        self.keystore.keys.clear();
+        Ok(())
+    }
+}
+
+/// Policy of the transactions that the signer daemon agrees to sign.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SignerPolicyConfig {
+    /// If this option enabled the daemon signs only the anchoring transactions
+    /// that satisfy the policy, see `PolicySigner`.
+    pub enabled: bool,
+    /// Minimal fee of the anchoring transaction in satoshis.
+    pub min_fee: u64,
+    /// Maximal fee of the anchoring transaction in satoshis.
+    pub max_fee: u64,
+    /// The anchored heights must be the multiples of the frequency, which should be equal to
+    /// the `frequency` of the anchoring configuration. Zero allows any height, it is required
+    /// by the time-based anchoring schedule.
+    pub frequency: u64,
+    /// Also accept the heights that are not the multiples of the frequency. Such heights are
+    /// anchored if the configuration changes, the flagged heights or the anchoring requests
+    /// are anchored in addition to the schedule.
+    #[serde(default)]
+    pub accept_unscheduled: bool,
+    /// File that keeps the latest signed transaction between the restarts of the daemon.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+}
+
+impl Default for SignerPolicyConfig {
+    fn default() -> SignerPolicyConfig {
+        SignerPolicyConfig {
+            enabled: true,
+            min_fee: 0,
+            max_fee: 100_000,
+            frequency: 500,
+            accept_unscheduled: false,
+            state_file: None,
+        }
+    }
+}
+
+/// Request to sign the input of the anchoring transaction.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SignRequest {
+    /// Anchoring address whose private key signs the input.
+    pub address: String,
+    /// Transaction to sign.
+    pub tx: AnchoringTx,
+    /// Signed input.
+    pub input: u32,
+    /// Redeem script of the spent output, or the witness script of the segregated
+    /// witness output.
+    pub redeem_script: btc::RedeemScript,
+    /// Value of the spent segregated witness output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+    /// Transactions spent by the inputs, which let the signer check the fee.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spent_txs: Vec<BitcoinTx>,
+}
+
+/// Response of the signer daemon.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignResponse {
+    /// Hex of the signature with the sighash type byte.
+    Signature(String),
+    /// Reason why the request is rejected.
+    Error(String),
+}
+
+/// Reason why the input is not signed.
+#[derive(Debug, Display, Fail, Clone, PartialEq)]
+pub enum SignerError {
+    /// The signer does not have the private key for the address.
+    #[display(fmt = "No private key for the address {}", _0)]
+    UnknownAddress(String),
+    /// The signer daemon is unavailable.
+    #[display(fmt = "Unable to connect to the signer: {}", _0)]
+    Connection(String),
+    /// The signer refuses to sign the request.
+    #[display(fmt = "Signer rejected the request: {}", _0)]
+    Rejected(String),
+}
+
+/// Reason why the transaction violates the policy of the signer.
+#[derive(Debug, Display, Fail, Clone, PartialEq)]
+pub enum PolicyError {
+    /// The transaction does not have the shape of the anchoring transaction.
+    #[display(fmt = "Transaction is not an anchoring transaction")]
+    NotAnchoringTx,
+    /// The transaction pays to the address that is not known to the signer.
+    #[display(fmt = "Transaction pays to the unknown address")]
+    UnknownOutputAddress,
+    /// The transaction spent by the input is not provided.
+    #[display(fmt = "Transaction spent by the input {} is not provided", _0)]
+    UnknownSpentTx(u32),
+    /// The input spends the output of the address that is not known to the signer.
+    #[display(fmt = "Input {} spends the output of the unknown address", _0)]
+    UnknownInputAddress(u32),
+    /// The fee of the transaction is out of the policy bounds.
+    #[display(fmt = "Fee {} is out of the bounds [{}, {}]", _0, _1, _2)]
+    FeeOutOfBounds(u64, u64, u64),
+    /// The anchored height is not a multiple of the frequency and the unscheduled heights
+    /// are not accepted.
+    #[display(fmt = "Height {} is not an anchoring height", _0)]
+    IncorrectHeight(u64),
+    /// The anchored height is not above the latest signed height.
+    #[display(fmt = "Height {} is not above the latest signed height {}", _0, _1)]
+    HeightNotIncreased(u64, u64),
+    /// The transaction does not spend the previous anchoring transaction.
+    #[display(fmt = "Transaction does not spend the previous anchoring transaction")]
+    NotPreviousAnchor,
+    /// The latest signed transaction can not be saved.
+    #[display(fmt = "Unable to save the signer state: {}", _0)]
+    State(String),
+}
+
+/// Signs the inputs of the anchoring transactions by the anchoring keys.
+pub trait Signer {
+    /// Signs the input of the request by the private key of the request address.
+    /// The signature ends with the sighash type byte.
+    fn sign(&self, request: &SignRequest) -> Result<btc::Signature, SignerError>;
+}
+
+/// Signer that holds the private keys in memory.
+#[derive(Debug)]
+pub struct LocalSigner<'a> {
+    private_keys: &'a BTreeMap<String, btc::PrivateKey>,
+}
+
+impl<'a> LocalSigner<'a> {
+    /// Creates signer for the given private keys of the anchoring addresses.
+    pub fn new(private_keys: &'a BTreeMap<String, btc::PrivateKey>) -> LocalSigner<'a> {
+        LocalSigner { private_keys }
+    }
+}
+
+impl<'a> Signer for LocalSigner<'a> {
+    fn sign(&self, request: &SignRequest) -> Result<btc::Signature, SignerError> {
+        let priv_key = self.private_keys
+            .get(&request.address)
+            .ok_or_else(|| SignerError::UnknownAddress(request.address.clone()))?;
+        let signature = if let Some(value) = request.value {
+            request
+                .tx
+                .sign_witness_input(&request.redeem_script, request.input, value, priv_key)
+        } else {
+            request.tx.sign_input(&request.redeem_script, request.input, priv_key)
+        };
+        Ok(signature)
+    }
+}
+
+/// Signer that checks the transaction before it is signed by the underlying signer.
+///
+/// The transaction must have exactly the shape of the anchoring transaction, pay to one
+/// of the known anchoring addresses, spend only the outputs of these addresses and have
+/// the fee within the policy bounds. The anchored height must be an anchoring height above
+/// the height of the latest signed transaction, and the first input must spend either the
+/// latest signed transaction or the same output as it does, if the latest transaction
+/// has not been finalized.
+#[derive(Debug)]
+pub struct PolicySigner<T> {
+    signer: T,
+    addresses: Vec<btc::Address>,
+    policy: SignerPolicyConfig,
+    latest: RefCell<Option<AnchoringTx>>,
+}
+
+impl<T: Signer> PolicySigner<T> {
+    /// Creates signer that signs the transactions between the given anchoring `addresses`
+    /// by the underlying `signer`. The latest signed transaction is loaded from the
+    /// `state_file` of the policy if it exists.
+    pub fn new(
+        signer: T,
+        addresses: Vec<btc::Address>,
+        policy: SignerPolicyConfig,
+    ) -> io::Result<PolicySigner<T>> {
+        let latest = match policy.state_file {
+            Some(ref path) if path.exists() => {
+                let file = File::open(path)?;
+                let tx = serde_json::from_reader(file)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Some(tx)
+            }
+            _ => None,
+        };
+        Ok(PolicySigner {
+            signer,
+            addresses,
+            policy,
+            latest: RefCell::new(latest),
+        })
+    }
+
+    /// Returns the latest signed transaction.
+    pub fn latest(&self) -> Option<AnchoringTx> {
+        self.latest.borrow().clone()
+    }
+
+    /// Checks that the transaction of the request satisfies the policy.
+    pub fn check(&self, request: &SignRequest) -> Result<(), PolicyError> {
+        let tx = &request.tx;
+        let payload = parse_payload(&tx.0).map_err(|_| PolicyError::NotAnchoringTx)?;
+        let has_anchoring_shape = tx.0.version == 1 && tx.0.lock_time == 0
+            && tx.0.output.len() == 2 && tx.0.output[1].value == 0
+            && tx.0.output[0].script_pubkey.is_p2sh()
+            && tx.0.input.iter().all(|input| input.sequence == 0xFFFF_FFFF)
+            && request.input < tx.0.input.len() as u32;
+        if !has_anchoring_shape {
+            return Err(PolicyError::NotAnchoringTx);
+        }
+        if !self.is_known(&tx.0.output[0].script_pubkey) {
+            return Err(PolicyError::UnknownOutputAddress);
+        }
+
+        let mut funds = 0;
+        for (index, input) in tx.0.input.iter().enumerate() {
+            let index = index as u32;
+            let spent_output = request
+                .spent_txs
+                .iter()
+                .find(|spent_tx| spent_tx.0.txid() == input.prev_hash)
+                .and_then(|spent_tx| spent_tx.0.output.get(input.prev_index as usize))
+                .ok_or(PolicyError::UnknownSpentTx(index))?;
+            if !self.is_known(&spent_output.script_pubkey) {
+                return Err(PolicyError::UnknownInputAddress(index));
+            }
+            funds += spent_output.value;
+        }
+        let fee = funds.saturating_sub(tx.amount());
+        if fee < self.policy.min_fee || fee > self.policy.max_fee {
+            return Err(PolicyError::FeeOutOfBounds(
+                fee,
+                self.policy.min_fee,
+                self.policy.max_fee,
+            ));
+        }
+
+        let height = payload.block_height.0;
+        let is_scheduled = self.policy.frequency == 0 || height % self.policy.frequency == 0;
+        if !is_scheduled && !self.policy.accept_unscheduled {
+            return Err(PolicyError::IncorrectHeight(height));
+        }
+        if let Some(ref latest) = *self.latest.borrow() {
+            // The other inputs of the latest signed transaction.
+            if latest.id() == tx.id() {
+                return Ok(());
+            }
+            let latest_height = latest.payload().block_height.0;
+            if height <= latest_height {
+                return Err(PolicyError::HeightNotIncreased(height, latest_height));
+            }
+            let prev = &tx.0.input[0];
+            let spends_latest = prev.prev_hash == latest.0.txid() && prev.prev_index == 0;
+            let replaces_latest = prev.prev_hash == latest.0.input[0].prev_hash
+                && prev.prev_index == latest.0.input[0].prev_index;
+            if !spends_latest && !replaces_latest {
+                return Err(PolicyError::NotPreviousAnchor);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_known(&self, script_pubkey: &Script) -> bool {
+        self.addresses
+            .iter()
+            .any(|addr| addr.script_pubkey() == *script_pubkey)
+    }
+
+    fn save_latest(&self, tx: &AnchoringTx) -> Result<(), PolicyError> {
+        if let Some(ref path) = self.policy.state_file {
+            // Replaces the state atomically, so it is not lost if the daemon is killed.
+            let tmp_path = path.with_extension("tmp");
+            File::create(&tmp_path)
+                .and_then(|mut file| {
+                    serde_json::to_writer(&mut file, tx)?;
+                    file.sync_all()
+                })
+                .and_then(|_| fs::rename(&tmp_path, path))
+                .map_err(|e| PolicyError::State(e.to_string()))?;
+        }
+        *self.latest.borrow_mut() = Some(tx.clone());
+        Ok(())
+    }
+}
+
+impl<T: Signer> Signer for PolicySigner<T> {
+    fn sign(&self, request: &SignRequest) -> Result<btc::Signature, SignerError> {
+        self.check(request)
+            .map_err(|e| SignerError::Rejected(e.to_string()))?;
+        let signature = self.signer.sign(request)?;
+        self.save_latest(&request.tx)
+            .map_err(|e| SignerError::Rejected(e.to_string()))?;
+        Ok(signature)
+    }
+}
+
+/// Endpoint of the signer daemon.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignerEndpoint {
+    /// Tcp address.
+    Tcp(String),
+    /// Path to the unix socket.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for SignerEndpoint {
+    type Err = SignerError;
+
+    fn from_str(s: &str) -> Result<SignerEndpoint, SignerError> {
+        if s.starts_with("tcp://") {
+            Ok(SignerEndpoint::Tcp(s["tcp://".len()..].to_owned()))
+        } else if let Some(endpoint) = unix_endpoint(s) {
+            Ok(endpoint)
+        } else {
+            Err(SignerError::Connection(format!("Unsupported endpoint {}", s)))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unix_endpoint(s: &str) -> Option<SignerEndpoint> {
+    if s.starts_with("unix://") {
+        Some(SignerEndpoint::Unix(PathBuf::from(&s["unix://".len()..])))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_endpoint(_: &str) -> Option<SignerEndpoint> {
+    None
+}
+
+impl SignerEndpoint {
+    /// Checks that the tcp endpoint is protected by the auth token.
+    pub fn check_auth_token(&self, auth_token: Option<&str>) -> io::Result<()> {
+        match (self, auth_token) {
+            (&SignerEndpoint::Tcp(_), None) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The tcp signer endpoint requires the auth_token",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Signer that forwards the requests to the signer daemon.
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    endpoint: SignerEndpoint,
+    timeout: Duration,
+    auth_token: Option<String>,
+}
+
+impl RemoteSigner {
+    /// Creates signer from the given configuration.
+    pub fn new(config: &RemoteSignerConfig) -> Result<RemoteSigner, SignerError> {
+        let endpoint: SignerEndpoint = config.endpoint.parse()?;
+        endpoint
+            .check_auth_token(config.auth_token.as_ref().map(String::as_str))
+            .map_err(|e| SignerError::Connection(e.to_string()))?;
+        Ok(RemoteSigner {
+            endpoint,
+            timeout: Duration::from_millis(config.timeout),
+            auth_token: config.auth_token.clone(),
+        })
+    }
+
+    fn request(&self, line: &str) -> io::Result<String> {
+        match self.endpoint {
+            SignerEndpoint::Tcp(ref addr) => {
+                let mut stream = TcpStream::connect(addr.as_str())?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                if let Some(ref token) = self.auth_token {
+                    stream.write_all(token.as_bytes())?;
+                    stream.write_all(b"\n")?;
+                }
+                exchange(stream, line)
+            }
+            #[cfg(unix)]
+            SignerEndpoint::Unix(ref path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.set_write_timeout(Some(self.timeout))?;
+                exchange(stream, line)
+            }
+        }
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn sign(&self, request: &SignRequest) -> Result<btc::Signature, SignerError> {
+        let line = serde_json::to_string(request).unwrap();
+        let response = self.request(&line)
+            .map_err(|e| SignerError::Connection(e.to_string()))?;
+        let response: SignResponse = serde_json::from_str(&response)
+            .map_err(|e| SignerError::Connection(e.to_string()))?;
+        match response {
+            SignResponse::Signature(signature) => Vec::<u8>::from_hex(signature)
+                .map_err(|e| SignerError::Connection(e.to_string())),
+            SignResponse::Error(reason) => Err(SignerError::Rejected(reason)),
+        }
+    }
+}
+
+/// Serves the requests from the connection to the signer daemon by the given signer
+/// until the connection is closed.
+///
+/// If the `auth_token` is given, the first line of the connection must be equal to it,
+/// otherwise the connection is closed without serving any request.
+pub fn serve_connection<S, T>(stream: S, signer: &T, auth_token: Option<&str>) -> io::Result<()>
+where
+    S: io::Read + Write,
+    T: Signer,
+{
+    let mut reader = BufReader::new(stream);
+    if let Some(token) = auth_token {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !tokens_equal(line.trim_right_matches('\n').as_bytes(), token.as_bytes()) {
+            let stream = reader.get_mut();
+            serde_json::to_writer(&mut *stream, &SignResponse::Error("Unauthorized".to_owned()))?;
+            stream.write_all(b"\n")?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Incorrect auth token",
+            ));
+        }
+    }
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let response = match serde_json::from_str::<SignRequest>(&line) {
+            Ok(request) => match signer.sign(&request) {
+                Ok(signature) => SignResponse::Signature(encode_hex(&signature)),
+                Err(e) => SignResponse::Error(e.to_string()),
+            },
+            Err(e) => SignResponse::Error(format!("Incorrect request: {}", e)),
+        };
+
+        let stream = reader.get_mut();
+        serde_json::to_writer(&mut *stream, &response)?;
+        stream.write_all(b"\n")?;
+        stream.flush()?;
+    }
+}
+
+/// Listens on the given endpoint and serves the connections one by one by the given signer.
+/// The tcp endpoint is refused without the `auth_token`.
+pub fn listen<T: Signer>(
+    endpoint: &SignerEndpoint,
+    signer: &T,
+    auth_token: Option<&str>,
+) -> io::Result<()> {
+    endpoint.check_auth_token(auth_token)?;
+    match *endpoint {
+        SignerEndpoint::Tcp(ref addr) => {
+            let listener = TcpListener::bind(addr.as_str())?;
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| serve_connection(stream, signer, auth_token));
+                if let Err(e) = result {
+                    warn!("Signer connection error: {}", e);
+                }
+            }
+        }
+        #[cfg(unix)]
+        SignerEndpoint::Unix(ref path) => {
+            let listener = UnixListener::bind(path)?;
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| serve_connection(stream, signer, auth_token));
+                if let Err(e) = result {
+                    warn!("Signer connection error: {}", e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Compares the tokens in the time that does not depend on the position of the first
+// mismatched byte.
+fn tokens_equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Sends the request line and reads the response line.
+fn exchange<S: io::Read + Write>(mut stream: S, line: &str) -> io::Result<String> {
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\n")?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    Ok(response)
+}
@@ -0,0 +1,882 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simplified payment verification of the anchoring transactions confirmations.
+//!
+//! The relay keeps a bitcoin header chain which starts from the trusted checkpoint,
+//! verifies proof of work and difficulty transitions of each header and checks merkle
+//! inclusion proofs of the transactions. Thus confirmations are computed from headers
+//! verified by the node itself instead of trusting `bitcoind`.
+
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use byteorder::{ByteOrder, LittleEndian};
+use serde_json::Value;
+
+use exonum::crypto::hash;
+use exonum::encoding::serialize::{encode_hex, FromHex};
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use details::rpc::{AnchoringRpcConfig, BitcoinRelay, Error as RpcError, Result, RpcConnection,
+                   TxInfo};
+
+/// Number of blocks between difficulty adjustments.
+pub const RETARGET_INTERVAL: u64 = 2016;
+/// Expected duration of the difficulty adjustment period in seconds.
+const TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+/// Expected interval between the blocks in seconds.
+const TARGET_SPACING: u32 = 10 * 60;
+/// Compact representation of the maximum target for the bitcoin and testnet networks.
+const POW_LIMIT_BITS: u32 = 0x1d00_ffff;
+/// Length of the serialized block header.
+const HEADER_LEN: usize = 80;
+/// Maximum number of the headers downloaded at once, so the first sync from a distant
+/// checkpoint does not block the caller for a long time.
+const SYNC_BATCH: u64 = 1000;
+
+/// SPV errors.
+#[derive(Debug, Display, Fail)]
+pub enum SpvError {
+    /// Rpc error.
+    #[display(fmt = "{}", _0)]
+    Rpc(RpcError),
+    /// Data received from `bitcoind` cannot be decoded.
+    #[display(fmt = "Malformed data: {}", _0)]
+    Malformed(String),
+    /// Header does not satisfy the consensus rules.
+    #[display(fmt = "Invalid header chain: {}", _0)]
+    InvalidChain(String),
+    /// Merkle proof is not correct.
+    #[display(fmt = "Invalid merkle proof: {}", _0)]
+    InvalidProof(String),
+    /// Header chain has not reached the best block yet.
+    #[display(fmt = "Header chain is synced up to height {} of {}", _0, _1)]
+    NotSynced(u64, u64),
+}
+
+impl From<RpcError> for SpvError {
+    fn from(e: RpcError) -> SpvError {
+        SpvError::Rpc(e)
+    }
+}
+
+impl From<SpvError> for RpcError {
+    fn from(e: SpvError) -> RpcError {
+        match e {
+            SpvError::Rpc(e) => e,
+            e => RpcError::Other(io::Error::new(io::ErrorKind::Other, e.to_string())),
+        }
+    }
+}
+
+/// SPV configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpvConfig {
+    /// Height of the trusted block, it should be a multiple of the retarget interval (2016)
+    /// and lie below the funding transaction.
+    pub checkpoint_height: u64,
+    /// Hash of the trusted block.
+    pub checkpoint_hash: String,
+    /// The bitcoin network type.
+    #[serde(serialize_with = "::blockchain::consensus_storage::btc_network_to_str",
+            deserialize_with = "::blockchain::consensus_storage::btc_network_from_str")]
+    pub network: btc::Network,
+}
+
+/// Unsigned 256-bit integer, limbs are stored in the little-endian order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Uint256([u32; 8]);
+
+impl Uint256 {
+    fn from_le_bytes(bytes: &[u8]) -> Uint256 {
+        let mut limbs = [0_u32; 8];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = LittleEndian::read_u32(&bytes[i * 4..i * 4 + 4]);
+        }
+        Uint256(limbs)
+    }
+
+    fn to_le_bytes(self) -> [u8; 32] {
+        let mut bytes = [0_u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            LittleEndian::write_u32(&mut bytes[i * 4..i * 4 + 4], *limb);
+        }
+        bytes
+    }
+
+    /// Decodes the target from the compact `bits` representation.
+    fn from_compact(bits: u32) -> Option<Uint256> {
+        let exponent = (bits >> 24) as isize;
+        let mantissa = bits & 0x007f_ffff;
+        if mantissa == 0 {
+            return Some(Uint256([0; 8]));
+        }
+        // Negative targets are not allowed.
+        if bits & 0x0080_0000 != 0 {
+            return None;
+        }
+
+        let mut bytes = [0_u8; 32];
+        for i in 0..3 {
+            let byte = (mantissa >> (8 * i)) as u8;
+            let position = exponent - 3 + i as isize;
+            if byte == 0 || position < 0 {
+                continue;
+            }
+            if position >= 32 {
+                return None;
+            }
+            bytes[position as usize] = byte;
+        }
+        Some(Uint256::from_le_bytes(&bytes))
+    }
+
+    /// Encodes the target into the compact `bits` representation.
+    fn to_compact(self) -> u32 {
+        let bytes = self.to_le_bytes();
+        let mut size = bytes.iter().rposition(|byte| *byte != 0).map_or(0, |i| i + 1);
+        let mut compact = 0_u32;
+        for i in 0..cmp::min(size, 3) {
+            compact |= u32::from(bytes[size - 1 - i]) << (8 * (2 - i));
+        }
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | ((size as u32) << 24)
+    }
+
+    fn mul_u32(&self, other: u32) -> Uint256 {
+        let mut result = [0_u32; 8];
+        let mut carry = 0_u64;
+        for (i, limb) in self.0.iter().enumerate() {
+            let value = u64::from(*limb) * u64::from(other) + carry;
+            result[i] = value as u32;
+            carry = value >> 32;
+        }
+        Uint256(result)
+    }
+
+    fn div_u32(&self, other: u32) -> Uint256 {
+        let mut result = [0_u32; 8];
+        let mut remainder = 0_u64;
+        for (quotient, limb) in result.iter_mut().zip(self.0.iter()).rev() {
+            let value = (remainder << 32) | u64::from(*limb);
+            *quotient = (value / u64::from(other)) as u32;
+            remainder = value % u64::from(other);
+        }
+        Uint256(result)
+    }
+}
+
+impl Ord for Uint256 {
+    fn cmp(&self, other: &Uint256) -> Ordering {
+        for (a, b) in self.0.iter().zip(other.0.iter()).rev() {
+            match a.cmp(b) {
+                Ordering::Equal => continue,
+                other => return other,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Uint256 {
+    fn partial_cmp(&self, other: &Uint256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Double sha256 hash in the internal byte order.
+fn double_sha256(data: &[u8]) -> [u8; 32] {
+    let mut result = [0_u8; 32];
+    result.copy_from_slice(hash(hash(data).as_ref()).as_ref());
+    result
+}
+
+/// Converts the hash from the rpc hex representation to the internal byte order.
+fn hash_from_rpc_hex(hex: &str) -> ::std::result::Result<[u8; 32], SpvError> {
+    let bytes = Vec::<u8>::from_hex(hex)
+        .map_err(|e| SpvError::Malformed(format!("Unable to decode hash {}: {}", hex, e)))?;
+    if bytes.len() != 32 {
+        return Err(SpvError::Malformed(format!("Wrong hash length: {}", hex)));
+    }
+    let mut result = [0_u8; 32];
+    result.copy_from_slice(&bytes);
+    result.reverse();
+    Ok(result)
+}
+
+/// Converts the hash from the internal byte order to the rpc hex representation.
+fn hash_to_rpc_hex(hash: &[u8; 32]) -> String {
+    let reversed = hash.iter().rev().cloned().collect::<Vec<_>>();
+    encode_hex(&reversed)
+}
+
+/// Reader of the bitcoin consensus encoded data.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, position: 0 }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> ::std::result::Result<&'a [u8], SpvError> {
+        if self.data.len() - self.position < len {
+            return Err(SpvError::Malformed("Unexpected end of data".to_owned()));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> ::std::result::Result<u32, SpvError> {
+        self.read_bytes(4).map(LittleEndian::read_u32)
+    }
+
+    fn read_hash(&mut self) -> ::std::result::Result<[u8; 32], SpvError> {
+        let mut hash = [0_u8; 32];
+        hash.copy_from_slice(self.read_bytes(32)?);
+        Ok(hash)
+    }
+
+    fn read_compact_size(&mut self) -> ::std::result::Result<u64, SpvError> {
+        let first = self.read_bytes(1)?[0];
+        let value = match first {
+            0xfd => u64::from(LittleEndian::read_u16(self.read_bytes(2)?)),
+            0xfe => u64::from(LittleEndian::read_u32(self.read_bytes(4)?)),
+            0xff => LittleEndian::read_u64(self.read_bytes(8)?),
+            value => u64::from(value),
+        };
+        Ok(value)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+}
+
+/// Bitcoin block header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    /// Block version.
+    pub version: u32,
+    /// Hash of the previous block in the internal byte order.
+    pub prev_hash: [u8; 32],
+    /// Merkle root of the block transactions in the internal byte order.
+    pub merkle_root: [u8; 32],
+    /// Block timestamp.
+    pub time: u32,
+    /// Compact representation of the block target.
+    pub bits: u32,
+    /// Nonce.
+    pub nonce: u32,
+    /// Hash of the block in the internal byte order.
+    pub hash: [u8; 32],
+}
+
+impl BlockHeader {
+    /// Decodes the header from its consensus encoding.
+    pub fn from_bytes(bytes: &[u8]) -> ::std::result::Result<BlockHeader, SpvError> {
+        let mut reader = Reader::new(bytes);
+        let header = BlockHeader::read(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(SpvError::Malformed("Trailing bytes after header".to_owned()));
+        }
+        Ok(header)
+    }
+
+    fn read(reader: &mut Reader) -> ::std::result::Result<BlockHeader, SpvError> {
+        let raw = reader.read_bytes(HEADER_LEN)?;
+        Ok(BlockHeader {
+            version: LittleEndian::read_u32(&raw[0..4]),
+            prev_hash: Reader::new(&raw[4..36]).read_hash()?,
+            merkle_root: Reader::new(&raw[36..68]).read_hash()?,
+            time: LittleEndian::read_u32(&raw[68..72]),
+            bits: LittleEndian::read_u32(&raw[72..76]),
+            nonce: LittleEndian::read_u32(&raw[76..80]),
+            hash: double_sha256(raw),
+        })
+    }
+
+    /// Returns the block hash in the rpc hex representation.
+    pub fn block_hash(&self) -> String {
+        hash_to_rpc_hex(&self.hash)
+    }
+
+    /// Checks that the block hash satisfies its own target and the target does not
+    /// exceed the network limit.
+    pub fn check_proof_of_work(&self) -> ::std::result::Result<(), SpvError> {
+        let pow_limit = Uint256::from_compact(POW_LIMIT_BITS).unwrap();
+        let target = Uint256::from_compact(self.bits).ok_or_else(|| {
+            SpvError::InvalidChain(format!("Incorrect bits {:x}", self.bits))
+        })?;
+        if target > pow_limit {
+            return Err(SpvError::InvalidChain(format!(
+                "Target of the block {} is above the limit",
+                self.block_hash()
+            )));
+        }
+        if Uint256::from_le_bytes(&self.hash) > target {
+            return Err(SpvError::InvalidChain(format!(
+                "Block {} does not satisfy proof of work",
+                self.block_hash()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Calculates the compact target for the next difficulty adjustment period.
+pub fn next_work_required(first_time: u32, last_time: u32, last_bits: u32) -> u32 {
+    let timespan = last_time.saturating_sub(first_time);
+    let timespan = cmp::max(
+        cmp::min(timespan, TARGET_TIMESPAN * 4),
+        TARGET_TIMESPAN / 4,
+    );
+    let pow_limit = Uint256::from_compact(POW_LIMIT_BITS).unwrap();
+    let target = Uint256::from_compact(last_bits).unwrap_or(pow_limit);
+    let target = target.mul_u32(timespan).div_u32(TARGET_TIMESPAN);
+    cmp::min(target, pow_limit).to_compact()
+}
+
+/// Chain of the verified block headers that starts from the checkpoint.
+#[derive(Debug)]
+pub struct HeaderChain {
+    network: btc::Network,
+    checkpoint_height: u64,
+    headers: Vec<BlockHeader>,
+    heights: HashMap<[u8; 32], u64>,
+}
+
+impl HeaderChain {
+    /// Creates a chain from the trusted checkpoint header.
+    pub fn new(
+        network: btc::Network,
+        checkpoint_height: u64,
+        checkpoint: BlockHeader,
+    ) -> ::std::result::Result<HeaderChain, SpvError> {
+        if checkpoint_height % RETARGET_INTERVAL != 0 {
+            return Err(SpvError::InvalidChain(format!(
+                "Checkpoint height {} is not a multiple of {}",
+                checkpoint_height, RETARGET_INTERVAL
+            )));
+        }
+        let mut heights = HashMap::new();
+        heights.insert(checkpoint.hash, checkpoint_height);
+        Ok(HeaderChain {
+            network,
+            checkpoint_height,
+            headers: vec![checkpoint],
+            heights,
+        })
+    }
+
+    /// Returns the height of the checkpoint.
+    pub fn checkpoint_height(&self) -> u64 {
+        self.checkpoint_height
+    }
+
+    /// Returns the height of the latest verified header.
+    pub fn tip_height(&self) -> u64 {
+        self.checkpoint_height + self.headers.len() as u64 - 1
+    }
+
+    /// Returns the latest verified header.
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().unwrap()
+    }
+
+    /// Returns the header at the given height.
+    pub fn header(&self, height: u64) -> Option<&BlockHeader> {
+        if height < self.checkpoint_height {
+            return None;
+        }
+        self.headers.get((height - self.checkpoint_height) as usize)
+    }
+
+    /// Returns the height of the block with the given hash.
+    pub fn height_of(&self, hash: &[u8; 32]) -> Option<u64> {
+        self.heights.get(hash).cloned()
+    }
+
+    /// Verifies and appends the next header to the chain.
+    pub fn push(&mut self, header: BlockHeader) -> ::std::result::Result<(), SpvError> {
+        let height = self.tip_height() + 1;
+        if header.prev_hash != self.tip().hash {
+            return Err(SpvError::InvalidChain(format!(
+                "Block {} does not connect to the chain at height {}",
+                header.block_hash(),
+                height
+            )));
+        }
+        self.check_difficulty(height, &header)?;
+        header.check_proof_of_work()?;
+
+        self.heights.insert(header.hash, height);
+        self.headers.push(header);
+        Ok(())
+    }
+
+    /// Removes the latest header from the chain, the checkpoint cannot be removed.
+    pub fn pop(&mut self) -> Option<BlockHeader> {
+        if self.headers.len() == 1 {
+            return None;
+        }
+        let header = self.headers.pop().unwrap();
+        self.heights.remove(&header.hash);
+        Some(header)
+    }
+
+    fn check_difficulty(
+        &self,
+        height: u64,
+        header: &BlockHeader,
+    ) -> ::std::result::Result<(), SpvError> {
+        let prev = self.tip();
+        let expected_bits = if height % RETARGET_INTERVAL == 0 {
+            let first = self.header(height - RETARGET_INTERVAL).unwrap();
+            next_work_required(first.time, prev.time, prev.bits)
+        } else if self.network == btc::Network::Testnet {
+            self.testnet_work_required(height, header)
+        } else {
+            prev.bits
+        };
+
+        if header.bits != expected_bits {
+            return Err(SpvError::InvalidChain(format!(
+                "Block {} has bits {:x}, expected {:x}",
+                header.block_hash(),
+                header.bits,
+                expected_bits
+            )));
+        }
+        Ok(())
+    }
+
+    // Testnet allows the block with the minimum difficulty if it has been mined more than
+    // 20 minutes after the previous one. Otherwise the block should have the bits of the
+    // latest block with the regular difficulty in the current adjustment period.
+    fn testnet_work_required(&self, height: u64, header: &BlockHeader) -> u32 {
+        if header.time > self.tip().time.saturating_add(TARGET_SPACING * 2) {
+            return POW_LIMIT_BITS;
+        }
+
+        let mut height = height - 1;
+        loop {
+            let block = self.header(height).unwrap();
+            if height % RETARGET_INTERVAL == 0 || block.bits != POW_LIMIT_BITS {
+                return block.bits;
+            }
+            height -= 1;
+        }
+    }
+}
+
+/// Step of the merkle branch from the transaction to the block merkle root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MerkleStep {
+    /// Sibling hash in the internal byte order, which is concatenated on the left.
+    Left([u8; 32]),
+    /// Sibling hash in the internal byte order, which is concatenated on the right.
+    Right([u8; 32]),
+}
+
+/// Inclusion of the transaction into the bitcoin block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxInclusion {
+    /// Height of the block.
+    pub block_height: u64,
+    /// Merkle branch from the transaction to the block merkle root.
+    pub branch: Vec<MerkleStep>,
+}
+
+/// Merkle block, the result of the `gettxoutproof` call.
+#[derive(Debug)]
+pub struct MerkleBlock {
+    /// Block header.
+    pub header: BlockHeader,
+    transactions_count: u32,
+    hashes: Vec<[u8; 32]>,
+    flags: Vec<u8>,
+}
+
+impl MerkleBlock {
+    /// Decodes the merkle block from its consensus encoding.
+    pub fn from_bytes(bytes: &[u8]) -> ::std::result::Result<MerkleBlock, SpvError> {
+        let mut reader = Reader::new(bytes);
+        let header = BlockHeader::read(&mut reader)?;
+        let transactions_count = reader.read_u32()?;
+        let hashes_count = reader.read_compact_size()?;
+        if hashes_count > u64::from(transactions_count) {
+            return Err(SpvError::Malformed("Too many hashes in proof".to_owned()));
+        }
+        let mut hashes = Vec::new();
+        for _ in 0..hashes_count {
+            hashes.push(reader.read_hash()?);
+        }
+        let flags_count = reader.read_compact_size()?;
+        let flags = reader.read_bytes(flags_count as usize)?.to_vec();
+        if !reader.is_empty() {
+            return Err(SpvError::Malformed("Trailing bytes after proof".to_owned()));
+        }
+        Ok(MerkleBlock {
+            header,
+            transactions_count,
+            hashes,
+            flags,
+        })
+    }
+
+    /// Checks the partial merkle tree against the header merkle root and returns
+    /// the matched transaction ids.
+    pub fn extract_matches(&self) -> ::std::result::Result<Vec<btc::TxId>, SpvError> {
+        let state = self.check_tree(None)?;
+        Ok(state
+            .matches
+            .iter()
+            .filter_map(|hash| btc::TxId::from_slice(hash))
+            .collect())
+    }
+
+    /// Checks the partial merkle tree against the header merkle root and returns
+    /// the merkle branch of the given matched transaction.
+    pub fn merkle_branch(
+        &self,
+        txid: &btc::TxId,
+    ) -> ::std::result::Result<Vec<MerkleStep>, SpvError> {
+        let mut target = [0_u8; 32];
+        target.copy_from_slice(txid.as_bytes());
+        let state = self.check_tree(Some(target))?;
+        if !state.matches.contains(&target) {
+            return Err(SpvError::InvalidProof(format!(
+                "Proof does not contain transaction {}",
+                txid
+            )));
+        }
+        Ok(state.branch)
+    }
+
+    fn check_tree(
+        &self,
+        target: Option<[u8; 32]>,
+    ) -> ::std::result::Result<TraversalState, SpvError> {
+        if self.transactions_count == 0 {
+            return Err(SpvError::InvalidProof("Block without transactions".to_owned()));
+        }
+        let mut height = 0;
+        while self.tree_width(height) > 1 {
+            height += 1;
+        }
+
+        let mut state = TraversalState {
+            hash_index: 0,
+            bit_index: 0,
+            matches: Vec::new(),
+            target,
+            branch: Vec::new(),
+        };
+        let (root, _) = self.traverse(height, 0, &mut state)?;
+
+        if state.hash_index != self.hashes.len() {
+            return Err(SpvError::InvalidProof("Not all hashes were used".to_owned()));
+        }
+        if (state.bit_index + 7) / 8 != self.flags.len() {
+            return Err(SpvError::InvalidProof("Not all flags were used".to_owned()));
+        }
+        if root != self.header.merkle_root {
+            return Err(SpvError::InvalidProof(
+                "Merkle root does not match the header".to_owned(),
+            ));
+        }
+        Ok(state)
+    }
+
+    fn tree_width(&self, height: usize) -> u64 {
+        (u64::from(self.transactions_count) + (1 << height) - 1) >> height
+    }
+
+    // Returns the node hash and whether the target transaction is below the node.
+    fn traverse(
+        &self,
+        height: usize,
+        position: u64,
+        state: &mut TraversalState,
+    ) -> ::std::result::Result<([u8; 32], bool), SpvError> {
+        if state.bit_index >= self.flags.len() * 8 {
+            return Err(SpvError::InvalidProof("Not enough flags".to_owned()));
+        }
+        let flag = (self.flags[state.bit_index / 8] >> (state.bit_index % 8)) & 1 == 1;
+        state.bit_index += 1;
+
+        if height == 0 || !flag {
+            let hash = *self.hashes
+                .get(state.hash_index)
+                .ok_or_else(|| SpvError::InvalidProof("Not enough hashes".to_owned()))?;
+            state.hash_index += 1;
+            let is_target = height == 0 && flag && state.target == Some(hash);
+            if height == 0 && flag {
+                state.matches.push(hash);
+            }
+            return Ok((hash, is_target));
+        }
+
+        let (left, in_left) = self.traverse(height - 1, position * 2, state)?;
+        let (right, in_right) = if position * 2 + 1 < self.tree_width(height - 1) {
+            let (right, in_right) = self.traverse(height - 1, position * 2 + 1, state)?;
+            // Protection against the CVE-2012-2459 duplicate transactions attack.
+            if right == left {
+                return Err(SpvError::InvalidProof("Duplicate merkle nodes".to_owned()));
+            }
+            (right, in_right)
+        } else {
+            (left, false)
+        };
+        // Children are traversed before their parent, so the branch is built from the leaf.
+        if in_left {
+            state.branch.push(MerkleStep::Right(right));
+        } else if in_right {
+            state.branch.push(MerkleStep::Left(left));
+        }
+
+        let mut concat = [0_u8; 64];
+        concat[..32].copy_from_slice(&left);
+        concat[32..].copy_from_slice(&right);
+        Ok((double_sha256(&concat), in_left || in_right))
+    }
+}
+
+struct TraversalState {
+    hash_index: usize,
+    bit_index: usize,
+    matches: Vec<[u8; 32]>,
+    target: Option<[u8; 32]>,
+    branch: Vec<MerkleStep>,
+}
+
+/// Requests the inclusion of the confirmed transaction into the bitcoin block.
+///
+/// The merkle branch is checked against the block header, but the header itself
+/// is trusted to `bitcoind`.
+pub fn tx_inclusion(
+    client: &RpcConnection,
+    txid: btc::TxId,
+) -> ::std::result::Result<TxInclusion, SpvError> {
+    let proof: String = client.call("gettxoutproof", vec![json!([txid.to_string()])])?;
+    let bytes = Vec::<u8>::from_hex(&proof)
+        .map_err(|e| SpvError::Malformed(format!("Unable to decode proof: {}", e)))?;
+    let block = MerkleBlock::from_bytes(&bytes)?;
+    let branch = block.merkle_branch(&txid)?;
+
+    let header: Value = client.call(
+        "getblockheader",
+        vec![Value::from(block.header.block_hash()), Value::from(true)],
+    )?;
+    let block_height = header
+        .get("height")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| SpvError::Malformed("Block header without height".to_owned()))?;
+    Ok(TxInclusion {
+        block_height,
+        branch,
+    })
+}
+
+/// `BitcoinRelay` decorator that computes confirmations from the verified header chain.
+#[derive(Debug)]
+pub struct SpvRelay {
+    inner: Box<BitcoinRelay>,
+    client: RpcConnection,
+    config: SpvConfig,
+    chain: Mutex<Option<HeaderChain>>,
+}
+
+impl SpvRelay {
+    /// Wraps the given relay, the `client` is used to download headers and proofs.
+    pub fn new(inner: Box<BitcoinRelay>, client: RpcConnection, config: SpvConfig) -> SpvRelay {
+        SpvRelay {
+            inner,
+            client,
+            config,
+            chain: Mutex::new(None),
+        }
+    }
+
+    fn block_hash(&self, height: u64) -> ::std::result::Result<[u8; 32], SpvError> {
+        let hash: String = self.client.call("getblockhash", vec![Value::from(height)])?;
+        hash_from_rpc_hex(&hash)
+    }
+
+    fn block_header(&self, hash: &[u8; 32]) -> ::std::result::Result<BlockHeader, SpvError> {
+        let hex: String = self.client.call(
+            "getblockheader",
+            vec![Value::from(hash_to_rpc_hex(hash)), Value::from(false)],
+        )?;
+        let bytes = Vec::<u8>::from_hex(&hex)
+            .map_err(|e| SpvError::Malformed(format!("Unable to decode header: {}", e)))?;
+        let header = BlockHeader::from_bytes(&bytes)?;
+        if header.hash != *hash {
+            return Err(SpvError::InvalidChain(format!(
+                "Received header {} instead of {}",
+                header.block_hash(),
+                hash_to_rpc_hex(hash)
+            )));
+        }
+        Ok(header)
+    }
+
+    fn load_checkpoint(&self) -> ::std::result::Result<HeaderChain, SpvError> {
+        let hash = hash_from_rpc_hex(&self.config.checkpoint_hash)?;
+        let header = self.block_header(&hash)?;
+        HeaderChain::new(self.config.network, self.config.checkpoint_height, header)
+    }
+
+    /// Downloads and verifies at most `SYNC_BATCH` new headers, the headers that are not
+    /// in the best chain anymore are rolled back. Returns the height of the best block.
+    fn sync(&self, chain: &mut HeaderChain) -> ::std::result::Result<u64, SpvError> {
+        let best_height: u64 = self.client.call("getblockcount", vec![])?;
+
+        while chain.tip_height() > chain.checkpoint_height() {
+            let tip_height = chain.tip_height();
+            if tip_height <= best_height && self.block_hash(tip_height)? == chain.tip().hash {
+                break;
+            }
+            warn!(
+                "Bitcoin chain reorganization detected, rollback block {} at height {}",
+                chain.tip().block_hash(),
+                tip_height
+            );
+            chain.pop();
+        }
+
+        let end = cmp::min(best_height, chain.tip_height() + SYNC_BATCH);
+        for height in chain.tip_height() + 1..end + 1 {
+            let hash = self.block_hash(height)?;
+            let header = self.block_header(&hash)?;
+            chain.push(header)?;
+        }
+        Ok(best_height)
+    }
+
+    /// Returns the confirmations of the transaction computed from the verified header chain.
+    ///
+    /// Each call downloads the next batch of headers, until the chain reaches the best block
+    /// the `NotSynced` error is returned.
+    pub fn verified_confirmations(&self, txid: btc::TxId) -> ::std::result::Result<u64, SpvError> {
+        let proof: String = self.client
+            .call("gettxoutproof", vec![json!([txid.to_string()])])?;
+        let bytes = Vec::<u8>::from_hex(&proof)
+            .map_err(|e| SpvError::Malformed(format!("Unable to decode proof: {}", e)))?;
+        let block = MerkleBlock::from_bytes(&bytes)?;
+        if !block.extract_matches()?.contains(&txid) {
+            return Err(SpvError::InvalidProof(format!(
+                "Proof does not contain transaction {}",
+                txid
+            )));
+        }
+
+        let mut chain = self.chain.lock().unwrap();
+        if chain.is_none() {
+            *chain = Some(self.load_checkpoint()?);
+        }
+        let chain = chain.as_mut().unwrap();
+        let best_height = self.sync(chain)?;
+        if chain.tip_height() < best_height {
+            return Err(SpvError::NotSynced(chain.tip_height(), best_height));
+        }
+
+        match chain.height_of(&block.header.hash) {
+            Some(height) => Ok(chain.tip_height() - height + 1),
+            None => Err(SpvError::InvalidProof(format!(
+                "Block {} is not in the verified chain",
+                block.header.block_hash()
+            ))),
+        }
+    }
+
+    fn check_confirmations(&self, txid: btc::TxId, reported: Option<u64>) -> Result<Option<u64>> {
+        match reported {
+            Some(count) if count > 0 => {
+                let verified = self.verified_confirmations(txid)?;
+                if verified != count {
+                    warn!(
+                        "Bitcoind reported {} confirmations for tx={}, verified {}",
+                        count, txid, verified
+                    );
+                }
+                Ok(Some(verified))
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+impl BitcoinRelay for SpvRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        self.inner.get_transaction(txid)
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        match self.inner.get_transaction_info(txid)? {
+            Some(info) => Ok(Some(TxInfo {
+                confirmations: self.check_confirmations(txid, info.confirmations)?,
+                body: info.body,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        self.inner.watch_address(addr, rescan)
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.inner.send_transaction(tx)
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        self.inner.send_to_address(addr, satoshis)
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let txs = self.inner.unspent_transactions(addr)?;
+        txs.into_iter()
+            .map(|info| {
+                Ok(TxInfo {
+                    confirmations: self.check_confirmations(info.body.id(), info.confirmations)?,
+                    body: info.body,
+                })
+            })
+            .collect()
+    }
+
+    fn get_transaction_confirmations(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        let reported = self.inner.get_transaction_confirmations(txid)?;
+        self.check_confirmations(txid, reported)
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            spv: Some(self.config.clone()),
+            ..self.inner.config()
+        }
+    }
+}
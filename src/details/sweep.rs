@@ -0,0 +1,282 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sweeping of the anchoring funds.
+//!
+//! The sweep transaction spends the anchoring output of the lect to an arbitrary address.
+//! It is used to recover the funds when the network is decommissioned. The proposal is
+//! created from the anchoring configuration, each key holder signs it offline and then
+//! any party finalizes the transaction from the collected signatures.
+
+use bitcoin::blockdata::opcodes::All;
+use bitcoin::blockdata::script::{Builder, Script};
+use bitcoin::blockdata::transaction::{SigHashType, TxIn, TxOut};
+use secp256k1::Secp256k1;
+use serde::{Deserialize, Deserializer, Serializer};
+
+use exonum::encoding::serialize::{encode_hex, FromHex};
+
+use blockchain::consensus_storage::AnchoringConfig;
+use details::btc;
+use details::btc::transactions::{sign_tx_input, sign_witness_tx_input, verify_tx_input,
+                                 verify_witness_tx_input, BitcoinTx, RawBitcoinTx};
+
+const SEQUENCE_FINAL: u32 = 0xFFFF_FFFF;
+
+/// Unsigned transaction that sweeps the anchoring output of the lect
+/// with the data that the key holders need to sign it offline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SweepProposal {
+    /// The sweep transaction without signatures.
+    pub tx: BitcoinTx,
+    /// The redeem script of the anchoring output, or the witness script
+    /// for the chained `OP_CHECKSIG` script.
+    pub redeem_script: btc::RedeemScript,
+    /// The value of the anchoring output.
+    pub value: u64,
+    /// The keys that may sign the sweep transaction in the order of the script.
+    pub keys: Vec<btc::PublicKey>,
+    /// The number of the required signatures.
+    pub threshold: u8,
+    /// Whether the transaction spends the time-locked recovery path.
+    #[serde(default)]
+    pub recovery: bool,
+}
+
+/// Signature of the sweep transaction made by one of the key holders.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SweepSignature {
+    /// The public key of the key holder.
+    pub key: btc::PublicKey,
+    /// The signature with the sighash type byte.
+    #[serde(serialize_with = "signature_to_hex", deserialize_with = "signature_from_hex")]
+    pub signature: btc::Signature,
+}
+
+/// Reason why the sweep transaction can not be created or finalized.
+#[derive(Debug, Display, Fail, Clone, PartialEq)]
+pub enum SweepError {
+    /// The lect does not have the output to the anchoring address of the configuration.
+    #[display(fmt = "Lect does not pay to the anchoring address")]
+    NoAnchoringOutput,
+    /// The recovery path is requested but it is not configured.
+    #[display(fmt = "Anchoring configuration does not have the recovery path")]
+    NoRecoveryPath,
+    /// The value of the sweep output without the fee is zero or dust.
+    #[display(fmt = "Fee {} leaves dust from the anchoring output of {} satoshis", _0, _1)]
+    InsufficientFunds(u64, u64),
+    /// The key is not one of the keys of the sweep proposal.
+    #[display(fmt = "Key {} is not allowed to sign the sweep transaction", _0)]
+    UnknownKey(btc::PublicKey),
+    /// The signature does not correspond to the sweep transaction.
+    #[display(fmt = "Incorrect signature of the key {}", _0)]
+    IncorrectSignature(btc::PublicKey),
+    /// The number of the collected signatures is less than the threshold.
+    #[display(fmt = "Collected {} signatures, but {} are required", _0, _1)]
+    NotEnoughSignatures(usize, u8),
+}
+
+impl SweepProposal {
+    /// Creates the proposal that sends the anchoring output of the `lect` without `fee`
+    /// to the address `to`. If `recovery` is set, the transaction spends the time-locked
+    /// recovery path of the redeem script, so it is valid only after the timelock expires.
+    pub fn new(
+        cfg: &AnchoringConfig,
+        lect: &BitcoinTx,
+        to: &btc::Address,
+        fee: u64,
+        recovery: bool,
+    ) -> Result<SweepProposal, SweepError> {
+        let (redeem_script, addr) = cfg.redeem_script();
+        let script_pubkey = addr.script_pubkey();
+        let (prev_index, output) = lect.0
+            .output
+            .iter()
+            .enumerate()
+            .find(|&(_, output)| output.script_pubkey == script_pubkey)
+            .ok_or(SweepError::NoAnchoringOutput)?;
+
+        let value = output.value;
+        if value <= fee || cfg.chain_params().is_dust(value - fee) {
+            return Err(SweepError::InsufficientFunds(fee, value));
+        }
+
+        let (keys, threshold, version, sequence) = if recovery {
+            let recovery = cfg.recovery.as_ref().ok_or(SweepError::NoRecoveryPath)?;
+            // `OP_CHECKSEQUENCEVERIFY` requires the version 2 transaction.
+            let sequence = u32::from(recovery.timelock);
+            (recovery.keys.clone(), recovery.threshold, 2, sequence)
+        } else {
+            let mut keys = cfg.anchoring_keys.clone();
+            if cfg.sort_keys {
+                keys.sort_by(|a, b| a.serialize()[..].cmp(&b.serialize()[..]));
+            }
+            (keys, cfg.majority_count(), 1, SEQUENCE_FINAL)
+        };
+
+        let tx = RawBitcoinTx {
+            version,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    prev_hash: lect.0.txid(),
+                    prev_index: prev_index as u32,
+                    script_sig: Script::new(),
+                    sequence,
+                },
+            ],
+            output: vec![
+                TxOut {
+                    value: value - fee,
+                    script_pubkey: to.script_pubkey(),
+                },
+            ],
+            witness: vec![],
+        };
+
+        Ok(SweepProposal {
+            tx: tx.into(),
+            redeem_script,
+            value,
+            keys,
+            threshold,
+            recovery,
+        })
+    }
+
+    /// Signs the sweep transaction by the given private key.
+    pub fn sign(&self, priv_key: &btc::PrivateKey) -> Result<SweepSignature, SweepError> {
+        let context = Secp256k1::new();
+        let key = btc::PublicKey::from_secret_key(&context, priv_key.secret_key()).unwrap();
+        if !self.keys.contains(&key) {
+            return Err(SweepError::UnknownKey(key));
+        }
+
+        let mut signature = if self.redeem_script.is_witness_script() {
+            sign_witness_tx_input(
+                &self.tx,
+                0,
+                &self.redeem_script,
+                self.value,
+                priv_key.secret_key(),
+            )
+        } else {
+            sign_tx_input(&self.tx, 0, &self.redeem_script, priv_key.secret_key())
+        };
+        signature.push(SigHashType::All.as_u32() as u8);
+        Ok(SweepSignature { key, signature })
+    }
+
+    /// Returns `true` if the signature is made by one of the keys of the proposal.
+    pub fn verify(&self, signature: &SweepSignature) -> bool {
+        if !self.keys.contains(&signature.key) || signature.signature.is_empty() {
+            return false;
+        }
+        // Cuts off btc related sighash type byte
+        let sig = &signature.signature[0..signature.signature.len() - 1];
+        if self.redeem_script.is_witness_script() {
+            verify_witness_tx_input(
+                &self.tx,
+                0,
+                &self.redeem_script,
+                self.value,
+                &signature.key,
+                sig,
+            )
+        } else {
+            verify_tx_input(&self.tx, 0, &self.redeem_script, &signature.key, sig)
+        }
+    }
+
+    /// Creates the signed sweep transaction from the collected `signatures`.
+    /// The extra signatures above the threshold are ignored.
+    pub fn finalize(&self, signatures: &[SweepSignature]) -> Result<BitcoinTx, SweepError> {
+        // The signatures in the order of the keys in the script.
+        let mut ordered = vec![None; self.keys.len()];
+        for signature in signatures {
+            let position = self.keys
+                .iter()
+                .position(|key| *key == signature.key)
+                .ok_or(SweepError::UnknownKey(signature.key))?;
+            if !self.verify(signature) {
+                return Err(SweepError::IncorrectSignature(signature.key));
+            }
+            ordered[position] = Some(signature.signature.clone());
+        }
+
+        let threshold = self.threshold as usize;
+        let collected = ordered.iter().filter(|sig| sig.is_some()).count();
+        if collected < threshold {
+            return Err(SweepError::NotEnoughSignatures(collected, self.threshold));
+        }
+
+        // Keeps exactly `threshold` signatures.
+        let mut count = 0;
+        for sig in &mut ordered {
+            if sig.is_some() {
+                if count == threshold {
+                    *sig = None;
+                } else {
+                    count += 1;
+                }
+            }
+        }
+
+        let mut tx = self.tx.0.clone();
+        let redeem_script_bytes = self.redeem_script.0.clone().into_vec();
+        if self.redeem_script.is_witness_script() {
+            let witness_program_bytes = self.redeem_script.witness_program().0.into_vec();
+            tx.input[0].script_sig = Builder::new()
+                .push_slice(witness_program_bytes.as_ref())
+                .into_script();
+            // The first key is checked against the top item of the stack.
+            let mut witness = ordered
+                .into_iter()
+                .rev()
+                .map(Option::unwrap_or_default)
+                .collect::<Vec<_>>();
+            witness.push(redeem_script_bytes);
+            tx.witness = vec![witness];
+        } else {
+            let mut builder = Builder::new().push_opcode(All::OP_PUSHBYTES_0);
+            for sig in ordered.into_iter().filter_map(|sig| sig) {
+                builder = builder.push_slice(sig.as_ref());
+            }
+            // Selects the main or the recovery path of the script.
+            if self.redeem_script.has_recovery_path() {
+                let path = if self.recovery { 0 } else { 1 };
+                builder = builder.push_int(path);
+            }
+            tx.input[0].script_sig = builder
+                .push_slice(redeem_script_bytes.as_ref())
+                .into_script();
+        }
+        Ok(tx.into())
+    }
+}
+
+fn signature_to_hex<S>(signature: &btc::Signature, ser: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    ser.serialize_str(&encode_hex(signature))
+}
+
+fn signature_from_hex<'de, D>(deserializer: D) -> Result<btc::Signature, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    Vec::<u8>::from_hex(s).map_err(|_| ::serde::de::Error::custom("Wrong hex"))
+}
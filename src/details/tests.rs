@@ -14,27 +14,58 @@
 
 extern crate rand;
 
-use std::collections::HashMap;
-
-use serde_json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use base64;
+use serde_json::{self, Value};
 use rand::Rng;
+use tempdir::TempDir;
 use bitcoin::network::constants::Network;
 use bitcoin::util::base58::{FromBase58, ToBase58};
 use bitcoin::util::address::Privkey as RawPrivateKey;
 use bitcoin::blockdata::transaction::SigHashType;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::blockdata::opcodes::All;
 use secp256k1::key::PublicKey as RawPublicKey;
 use secp256k1::Secp256k1;
 
 use exonum::helpers::{self, Height};
-use exonum::crypto::Hash;
+use exonum::crypto::{hash, Hash};
 use exonum::storage::StorageValue;
 use exonum::encoding::serialize::{encode_hex, FromHex};
 use exonum::encoding::Field;
 
-use details::btc::transactions::{sign_tx_input, verify_tx_input, AnchoringTx, BitcoinTx,
-                                 FundingTx, TransactionBuilder, TxKind};
+use blockchain::consensus_storage::{AnchoringConfig, AnchoringRecovery, AnchoringScript,
+                                    ConfigError};
+use details::btc::transactions::{sign_tx_input, verify_tx_input, witness_signature_hash,
+                                 AnchoringTx, BitcoinTx, FundingTx, TransactionBuilder, TxKind};
 use details::btc;
+use details::btc::chain::{self, ChainParams};
 use details::btc::HexValueEx;
+use details::rpc::{parse_credentials, AnchoringRpcConfig, BitcoinRelay, Result as RpcResult,
+                   RpcConnection, TxInfo, UtxoDiscovery};
+use details::cache::{CacheStats, CachedRelay, RelayCacheConfig};
+use details::spv::{next_work_required, BlockHeader, HeaderChain, MerkleBlock, MerkleStep, SpvConfig,
+                   SpvRelay, TxInclusion};
+use details::mmr::MerkleMountainRange;
+use details::keystore::{read_secret, EncryptedKey, KdfParams, KeystoreConfig, KeystoreError};
+use details::ots;
+use details::psbt::{Psbt, PsbtError};
+use details::signer::{serve_connection, LocalSigner, PolicyError, PolicySigner, RemoteSigner,
+                      RemoteSignerConfig, SignRequest, Signer, SignerDaemonConfig, SignerEndpoint,
+                      SignerError, SignerPolicyConfig};
+use details::sweep::{SweepError, SweepProposal, SweepSignature};
+use details::notifier::{ChainEvent, ChainNotifier, ChainUpdates, ChannelNotifier};
+use cmd::SweepCommand;
+use handler::AnchoringHandler;
+use local_storage::AnchoringNodeConfig;
 
 pub fn dummy_anchoring_tx(redeem_script: &btc::RedeemScript) -> AnchoringTx {
     let addr = btc::Address::from_script(redeem_script, Network::Testnet);
@@ -377,6 +408,557 @@ fn test_redeem_script_from_pubkeys() {
     );
 }
 
+#[test]
+fn test_redeem_script_sorted_keys() {
+    let keys = [
+        "03e2bc790a6e32bf5a766919ff55b1f9e9914e13aed84f502c0e4171976e19deb0",
+        "027db7837e51888e94c094703030d162c682c8dba312210f44ff440fbd5e5c2473",
+        "03280883dc31ccaee34218819aaa245480c35a33acd91283586ff6d1284ed681e5",
+        "02bdd272891c9e4dfc3962b1fdffd5a59732019816f9db4833634dbdaf01a401a5",
+    ].iter()
+        .map(|x| btc::PublicKey::from_hex(x).unwrap())
+        .collect::<Vec<_>>();
+
+    let redeem_script = btc::RedeemScript::from_pubkeys_sorted(&keys, 3);
+    assert_eq!(
+        redeem_script.to_address(Network::Testnet).to_string(),
+        "2N1mHzwKTmjnC7JjqeGFBRKYE4WDTjTfop1"
+    );
+    assert_ne!(btc::RedeemScript::from_pubkeys(&keys, 3), redeem_script);
+
+    let mut cfg = AnchoringConfig::new(Network::Testnet, keys.clone());
+    assert_eq!(cfg.script_key_position(0), Some(0));
+    assert_eq!(cfg.script_key_position(4), None);
+
+    cfg.sort_keys = true;
+    assert_eq!(cfg.redeem_script().0, redeem_script.compressed(Network::Testnet));
+    let positions = (0..keys.len())
+        .map(|validator| cfg.script_key_position(validator).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(positions, vec![3, 0, 2, 1]);
+}
+
+#[test]
+fn test_redeem_script_with_recovery() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let (recovery_keys, _) = gen_anchoring_keys(3);
+
+    let redeem_script =
+        btc::RedeemScript::from_pubkeys_with_recovery(&pub_keys, 3, &recovery_keys, 2, 4320);
+    assert!(redeem_script.has_recovery_path());
+    assert!(!btc::RedeemScript::from_pubkeys(&pub_keys, 3).has_recovery_path());
+    // The timelock push is kept as is.
+    assert_eq!(redeem_script.compressed(Network::Testnet), redeem_script);
+
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys.clone());
+    cfg.recovery = Some(AnchoringRecovery {
+        keys: recovery_keys.clone(),
+        threshold: 2,
+        timelock: 4320,
+    });
+    assert_eq!(cfg.validate(), Ok(()));
+    assert_eq!(cfg.redeem_script().0, redeem_script);
+    assert_eq!(cfg.recovery.as_ref().unwrap().refresh_confirmations(), 2160);
+
+    cfg.recovery.as_mut().unwrap().threshold = 4;
+    assert!(cfg.validate().is_err());
+    cfg.recovery.as_mut().unwrap().threshold = 2;
+    cfg.recovery.as_mut().unwrap().timelock = 0;
+    assert!(cfg.validate().is_err());
+    cfg.recovery.as_mut().unwrap().timelock = 4320;
+    cfg.script = AnchoringScript::ChainedChecksig;
+    assert!(cfg.validate().is_err());
+
+    let (pub_keys, _) = gen_anchoring_keys(15);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    assert_eq!(cfg.validate(), Ok(()));
+    cfg.recovery = Some(AnchoringRecovery {
+        keys: recovery_keys,
+        threshold: 2,
+        timelock: 4320,
+    });
+    match cfg.validate() {
+        Err(ConfigError::ScriptTooLarge(_)) => {}
+        other => panic!("Unexpected validation result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_sweep_transaction() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let (recovery_keys, recovery_priv_keys) = gen_anchoring_keys(2);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys.clone());
+    cfg.recovery = Some(AnchoringRecovery {
+        keys: recovery_keys.clone(),
+        threshold: 1,
+        timelock: 4320,
+    });
+    let (redeem_script, _) = cfg.redeem_script();
+    let lect = BitcoinTx::from(dummy_anchoring_tx(&redeem_script).0);
+    let to = btc::Address::from_script(
+        &btc::RedeemScript::from_pubkeys(&recovery_keys, 1),
+        Network::Testnet,
+    );
+
+    assert_eq!(
+        SweepProposal::new(&cfg, &lect, &to, 1900, false).unwrap_err(),
+        SweepError::InsufficientFunds(1900, 2000)
+    );
+    let proposal = SweepProposal::new(&cfg, &lect, &to, 1000, false).unwrap();
+    assert_eq!(proposal.tx.input[0].prev_hash, lect.0.txid());
+    assert_eq!(proposal.tx.output[0].value, 1000);
+    assert_eq!(proposal.threshold, 3);
+
+    // The proposal survives the round trip through the file.
+    let proposal: SweepProposal =
+        serde_json::from_str(&serde_json::to_string(&proposal).unwrap()).unwrap();
+    let signatures = priv_keys
+        .iter()
+        .map(|key| proposal.sign(key).unwrap())
+        .collect::<Vec<_>>();
+    assert!(signatures.iter().all(|sig| proposal.verify(sig)));
+    assert_eq!(
+        proposal.sign(&recovery_priv_keys[0]).unwrap_err(),
+        SweepError::UnknownKey(recovery_keys[0])
+    );
+    assert_eq!(
+        proposal.finalize(&signatures[0..2]).unwrap_err(),
+        SweepError::NotEnoughSignatures(2, 3)
+    );
+
+    let tx = proposal.finalize(&signatures[1..]).unwrap();
+    let mut expected_script_sig = Builder::new().push_opcode(All::OP_PUSHBYTES_0);
+    for sig in &signatures[1..] {
+        expected_script_sig = expected_script_sig.push_slice(sig.signature.as_ref());
+    }
+    let expected_script_sig = expected_script_sig
+        .push_int(1)
+        .push_slice(redeem_script.0.clone().into_vec().as_ref())
+        .into_script();
+    assert_eq!(tx.input[0].script_sig, expected_script_sig);
+
+    // The recovery path is signed by the recovery keys.
+    let proposal = SweepProposal::new(&cfg, &lect, &to, 1000, true).unwrap();
+    assert_eq!(proposal.tx.version, 2);
+    assert_eq!(proposal.tx.input[0].sequence, 4320);
+    let signature = proposal.sign(&recovery_priv_keys[1]).unwrap();
+    let tx = proposal.finalize(&[signature.clone()]).unwrap();
+    let expected_script_sig = Builder::new()
+        .push_opcode(All::OP_PUSHBYTES_0)
+        .push_slice(signature.signature.as_ref())
+        .push_int(0)
+        .push_slice(redeem_script.0.clone().into_vec().as_ref())
+        .into_script();
+    assert_eq!(tx.input[0].script_sig, expected_script_sig);
+
+    let cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    assert_eq!(
+        SweepProposal::new(&cfg, &lect, &to, 1000, false).unwrap_err(),
+        SweepError::NoAnchoringOutput
+    );
+    let lect = BitcoinTx::from(dummy_anchoring_tx(&cfg.redeem_script().0).0);
+    assert_eq!(
+        SweepProposal::new(&cfg, &lect, &to, 1000, true).unwrap_err(),
+        SweepError::NoRecoveryPath
+    );
+}
+
+#[test]
+fn test_sweep_command_private_key_file() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    let (redeem_script, _) = cfg.redeem_script();
+    let lect = BitcoinTx::from(dummy_anchoring_tx(&redeem_script).0);
+    let to = btc::Address::from_script(&redeem_script, Network::Testnet);
+    let proposal = SweepProposal::new(&cfg, &lect, &to, 1000, false).unwrap();
+
+    let dir = TempDir::new("anchoring_sweep").unwrap();
+    let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+    File::create(path("sweep.json"))
+        .unwrap()
+        .write_all(serde_json::to_string(&proposal).unwrap().as_bytes())
+        .unwrap();
+    File::create(path("key"))
+        .unwrap()
+        .write_all(format!("{}\n", priv_keys[0].to_base58check()).as_bytes())
+        .unwrap();
+
+    let matches = SweepCommand::app().get_matches_from(vec![
+        "anchoring-sweep",
+        "sign",
+        "--proposal",
+        &path("sweep.json"),
+        "--private-key-file",
+        &path("key"),
+        "--output",
+        &path("signature.json"),
+    ]);
+    SweepCommand::execute(&matches).unwrap();
+    let signature: SweepSignature =
+        serde_json::from_reader(File::open(path("signature.json")).unwrap()).unwrap();
+    assert!(proposal.verify(&signature));
+}
+
+#[test]
+fn test_psbt_roundtrip() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let tx = dummy_anchoring_tx(&redeem_script);
+
+    let mut psbt = Psbt::from_unsigned_tx(tx.0.clone());
+    assert_eq!(psbt.inputs.len(), 1);
+    assert_eq!(psbt.outputs.len(), 2);
+    psbt.inputs[0].redeem_script = Some(redeem_script.clone());
+    psbt.inputs[0].sighash_type = Some(SigHashType::All.as_u32());
+    psbt.outputs[0].redeem_script = Some(redeem_script.clone());
+
+    let signature = tx.sign_input(&redeem_script, 0, &priv_keys[1]);
+    psbt.inputs[0].partial_sigs.push((pub_keys[1], signature.clone()));
+    psbt.inputs[0].unknown.push((vec![0xfc, 0x01], vec![0x02]));
+
+    let decoded = Psbt::from_base64(&psbt.to_base64()).unwrap();
+    assert_eq!(decoded, psbt);
+    assert_eq!(decoded.partial_sig(0, &pub_keys[1]), Some(&signature));
+    assert_eq!(decoded.partial_sig(0, &pub_keys[0]), None);
+    assert!(tx.verify_input(&redeem_script, 0, &pub_keys[1], &signature));
+
+    let data = psbt.serialize();
+    assert_eq!(&data[0..5], b"psbt\xff");
+    assert_eq!(Psbt::deserialize(&data[1..]), Err(PsbtError::IncorrectMagic));
+    assert_eq!(
+        Psbt::deserialize(&data[0..data.len() - 1]),
+        Err(PsbtError::UnexpectedEnd)
+    );
+    assert_eq!(Psbt::from_base64("not a psbt!"), Err(PsbtError::IncorrectBase64));
+}
+
+#[test]
+fn test_remote_signer() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let addr = btc::Address::from_script(&redeem_script, Network::Testnet);
+    let tx = dummy_anchoring_tx(&redeem_script);
+
+    let mut private_keys = BTreeMap::new();
+    private_keys.insert(addr.to_base58check(), priv_keys[0].clone());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("tcp://{}", listener.local_addr().unwrap());
+    let daemon = thread::spawn(move || {
+        let signer = LocalSigner::new(&private_keys);
+        let mut streams = listener.incoming();
+        for stream in streams.by_ref().take(2) {
+            serve_connection(stream.unwrap(), &signer, Some("secret")).unwrap();
+        }
+        // The connection with the wrong token is refused.
+        let stream = streams.next().unwrap().unwrap();
+        assert!(serve_connection(stream, &signer, Some("secret")).is_err());
+    });
+
+    let mut config = RemoteSignerConfig {
+        enabled: true,
+        endpoint,
+        timeout: 5_000,
+        auth_token: None,
+    };
+    // The tcp endpoint is not allowed without the token.
+    assert!(RemoteSigner::new(&config).is_err());
+    config.auth_token = Some("secret".to_owned());
+    let signer = RemoteSigner::new(&config).unwrap();
+    let mut request = SignRequest {
+        address: addr.to_base58check(),
+        tx: tx.clone(),
+        input: 0,
+        redeem_script: redeem_script.clone(),
+        value: None,
+        spent_txs: vec![],
+    };
+    let signature = signer.sign(&request).unwrap();
+    assert_eq!(signature, tx.sign_input(&redeem_script, 0, &priv_keys[0]));
+    assert!(tx.verify_input(&redeem_script, 0, &pub_keys[0], &signature));
+
+    request.address = "2NCJYWui4LGNZguUw41xBANbcHoKxSVxyzr".to_owned();
+    match signer.sign(&request) {
+        Err(SignerError::Rejected(_)) => {}
+        other => panic!("Unexpected signer result: {:?}", other),
+    }
+    config.auth_token = Some("wrong".to_owned());
+    assert!(RemoteSigner::new(&config).unwrap().sign(&request).is_err());
+    daemon.join().unwrap();
+
+    assert!("udp://127.0.0.1:9100".parse::<SignerEndpoint>().is_err());
+}
+
+#[test]
+fn test_policy_signer() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let addr = btc::Address::from_script(&redeem_script, Network::Testnet);
+    let (other_keys, _) = gen_anchoring_keys(4);
+    let other_script =
+        btc::RedeemScript::from_pubkeys(&other_keys, 3).compressed(Network::Testnet);
+    let other_addr = btc::Address::from_script(&other_script, Network::Testnet);
+
+    let anchor = |prev: &AnchoringTx, height: u64, fee: u64, to: &btc::Address| {
+        TransactionBuilder::with_prev_tx(&prev.0, 0)
+            .fee(fee)
+            .payload(Height(height), Hash::zero())
+            .send_to(to.clone())
+            .into_transaction()
+            .unwrap()
+    };
+    let request = |tx: &AnchoringTx, prev: &AnchoringTx| SignRequest {
+        address: addr.to_base58check(),
+        tx: tx.clone(),
+        input: 0,
+        redeem_script: redeem_script.clone(),
+        value: None,
+        spent_txs: vec![BitcoinTx::from(prev.0.clone())],
+    };
+
+    let mut private_keys = BTreeMap::new();
+    private_keys.insert(addr.to_base58check(), priv_keys[0].clone());
+    let dir = TempDir::new("policy_signer").unwrap();
+    let policy = SignerPolicyConfig {
+        enabled: true,
+        min_fee: 0,
+        max_fee: 500,
+        frequency: 10,
+        accept_unscheduled: false,
+        state_file: Some(dir.path().join("state.json")),
+    };
+    let local_signer = LocalSigner::new(&private_keys);
+    let signer = PolicySigner::new(local_signer, vec![addr.clone()], policy.clone()).unwrap();
+
+    let tx0 = dummy_anchoring_tx(&redeem_script);
+    let tx1 = anchor(&tx0, 10, 100, &addr);
+    let signature = signer.sign(&request(&tx1, &tx0)).unwrap();
+    assert!(tx1.verify_input(&redeem_script, 0, &pub_keys[0], &signature));
+    // The same transaction may be signed again.
+    assert!(signer.sign(&request(&tx1, &tx0)).is_ok());
+    assert_eq!(signer.latest(), Some(tx1.clone()));
+
+    let mut unknown_spent = request(&tx1, &tx0);
+    unknown_spent.spent_txs.clear();
+    assert_eq!(signer.check(&unknown_spent), Err(PolicyError::UnknownSpentTx(0)));
+    let mut wrong_shape = tx1.clone();
+    wrong_shape.0.lock_time = 1;
+    assert_eq!(
+        signer.check(&request(&wrong_shape, &tx0)),
+        Err(PolicyError::NotAnchoringTx)
+    );
+    assert_eq!(
+        signer.check(&request(&anchor(&tx1, 20, 100, &other_addr), &tx1)),
+        Err(PolicyError::UnknownOutputAddress)
+    );
+    assert_eq!(
+        signer.check(&request(&anchor(&tx1, 20, 1000, &addr), &tx1)),
+        Err(PolicyError::FeeOutOfBounds(1000, 0, 500))
+    );
+    assert_eq!(
+        signer.check(&request(&anchor(&tx1, 15, 100, &addr), &tx1)),
+        Err(PolicyError::IncorrectHeight(15))
+    );
+    assert_eq!(
+        signer.check(&request(&anchor(&tx1, 10, 200, &addr), &tx1)),
+        Err(PolicyError::HeightNotIncreased(10, 10))
+    );
+    match signer.sign(&request(&anchor(&tx1, 20, 1000, &addr), &tx1)) {
+        Err(SignerError::Rejected(_)) => {}
+        other => panic!("Unexpected signer result: {:?}", other),
+    }
+
+    // The proposal that replaces the unfinished latest transaction is allowed.
+    assert!(signer.check(&request(&anchor(&tx0, 20, 100, &addr), &tx0)).is_ok());
+    let tx2 = anchor(&tx1, 20, 100, &addr);
+    assert!(signer.sign(&request(&tx2, &tx1)).is_ok());
+    assert_eq!(
+        signer.check(&request(&anchor(&tx0, 30, 100, &addr), &tx0)),
+        Err(PolicyError::NotPreviousAnchor)
+    );
+
+    // The latest signed transaction is restored from the state file.
+    let signer = PolicySigner::new(
+        LocalSigner::new(&private_keys),
+        vec![addr.clone()],
+        policy.clone(),
+    ).unwrap();
+    assert_eq!(signer.latest(), Some(tx2.clone()));
+
+    // The out-of-schedule heights are accepted only if it is allowed by the policy.
+    let unscheduled = anchor(&tx2, 25, 100, &addr);
+    assert_eq!(
+        signer.check(&request(&unscheduled, &tx2)),
+        Err(PolicyError::IncorrectHeight(25))
+    );
+    let policy = SignerPolicyConfig {
+        accept_unscheduled: true,
+        ..policy
+    };
+    let signer =
+        PolicySigner::new(LocalSigner::new(&private_keys), vec![addr.clone()], policy).unwrap();
+    assert!(signer.check(&request(&unscheduled, &tx2)).is_ok());
+    assert_eq!(
+        signer.check(&request(&anchor(&tx2, 15, 100, &addr), &tx2)),
+        Err(PolicyError::HeightNotIncreased(15, 20))
+    );
+}
+
+#[test]
+fn test_encrypted_keystore() {
+    let (_, priv_keys) = gen_anchoring_keys(2);
+    let kdf = KdfParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+    let address = "2NCJYWui4LGNZguUw41xBANbcHoKxSVxyzr";
+    let key = EncryptedKey::encrypt(address, &priv_keys[0], "passphrase", kdf).unwrap();
+    assert_eq!(key.decrypt("passphrase"), Ok(priv_keys[0].clone()));
+    assert_eq!(
+        key.decrypt("wrong passphrase"),
+        Err(KeystoreError::IncorrectPassphrase(address.to_owned()))
+    );
+    // The address is authenticated along with the key.
+    let mut swapped = key.clone();
+    swapped.address = "2N1mHzwKTmjnC7JjqeGFBRKYE4WDTjTfop1".to_owned();
+    assert!(swapped.decrypt("passphrase").is_err());
+
+    let mut node = AnchoringNodeConfig::default();
+    node.private_keys.insert(address.to_owned(), priv_keys[0].clone());
+    node.private_keys.insert(swapped.address.clone(), priv_keys[1].clone());
+    let private_keys = node.private_keys.clone();
+    node.encrypt_keys("passphrase", kdf).unwrap();
+    assert!(node.private_keys.is_empty());
+    assert_eq!(node.keystore.keys.len(), 2);
+
+    let toml = ::toml::to_string(&::toml::Value::try_from(&node).unwrap()).unwrap();
+    let mut node: AnchoringNodeConfig = ::toml::from_str(&toml).unwrap();
+    assert!(node.decrypt_keys("wrong passphrase").is_err());
+    assert_eq!(node.keystore.keys.len(), 2);
+    node.decrypt_keys("passphrase").unwrap();
+    assert_eq!(node.private_keys, private_keys);
+    assert!(node.keystore.keys.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_signer_daemon_keystore() {
+    use std::os::unix::io::IntoRawFd;
+
+    let (_, priv_keys) = gen_anchoring_keys(1);
+    let kdf = KdfParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+    let address = "2NCJYWui4LGNZguUw41xBANbcHoKxSVxyzr";
+    let dir = TempDir::new("anchoring_signer").unwrap();
+    let passphrase_file = dir.path().join("passphrase");
+    File::create(&passphrase_file)
+        .unwrap()
+        .write_all(b"passphrase\n")
+        .unwrap();
+
+    let mut config: SignerDaemonConfig =
+        ::toml::from_str("endpoint = \"tcp://127.0.0.1:9100\"").unwrap();
+    assert!(config.private_keys.is_empty());
+    assert!(config.policy.enabled);
+    config.keystore = KeystoreConfig {
+        passphrase_fd: Some(File::open(&passphrase_file).unwrap().into_raw_fd()),
+        keys: vec![EncryptedKey::encrypt(address, &priv_keys[0], "passphrase", kdf).unwrap()],
+    };
+    config.unlock_keys().unwrap();
+    assert_eq!(config.private_keys.get(address), Some(&priv_keys[0]));
+    assert!(config.keystore.keys.is_empty());
+    // The standard streams are not closed by the secret reading.
+    for fd in 0..3 {
+        assert!(read_secret(Some(fd), "").is_err());
+    }
+}
+
+#[test]
+fn test_witness_signature_hash() {
+    // The P2SH-P2WPKH example from BIP143.
+    let unsigned_tx = BitcoinTx::from_hex(
+        "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a5477010000\
+         0000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af\
+         2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000",
+    ).unwrap();
+    let script_code =
+        btc::RedeemScript::from_hex("76a91479091972186c449eb1ded22b78e40d009bdf008988ac").unwrap();
+
+    let sighash = witness_signature_hash(&unsigned_tx, 0, &script_code, 1_000_000_000);
+    assert_eq!(
+        encode_hex(&sighash[..]),
+        "64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6"
+    );
+}
+
+#[test]
+fn test_chained_checksig_script() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(20);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys.clone());
+    assert_eq!(cfg.validate(), Err(ConfigError::TooManyKeys(20, 15)));
+
+    cfg.script = AnchoringScript::ChainedChecksig;
+    assert_eq!(cfg.validate(), Ok(()));
+    let (witness_script, addr) = cfg.redeem_script();
+    assert!(witness_script.is_witness_script());
+    assert_eq!(
+        witness_script,
+        btc::RedeemScript::chained_checksig_from_pubkeys(&pub_keys, 14)
+    );
+    assert_eq!(
+        addr,
+        btc::Address::from_script(&witness_script.witness_program(), Network::Testnet)
+    );
+    let program = witness_script.witness_program().0.into_vec();
+    assert_eq!(program.len(), 34);
+    assert_eq!(&program[0..2], &[0x00, 0x20]);
+
+    // Sign the input by the first keys.
+    let tx = dummy_anchoring_tx(&btc::RedeemScript::from_pubkeys(&pub_keys[0..3], 2));
+    let value = 10_000;
+    let signatures = (0..pub_keys.len())
+        .map(|id| {
+            if id < 14 {
+                let signature = tx.sign_witness_input(&witness_script, 0, value, &priv_keys[id]);
+                assert!(tx.verify_witness_input(
+                    &witness_script,
+                    0,
+                    value,
+                    &pub_keys[id],
+                    &signature
+                ));
+                assert!(!tx.verify_witness_input(
+                    &witness_script,
+                    0,
+                    value + 1,
+                    &pub_keys[id],
+                    &signature
+                ));
+                signature
+            } else {
+                Vec::new()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut signatures_by_input = HashMap::new();
+    signatures_by_input.insert(0, signatures.clone());
+    let signed_tx = tx.finalize(&witness_script, signatures_by_input);
+    let witness = &signed_tx.0.witness[0];
+    assert_eq!(witness.len(), pub_keys.len() + 1);
+    assert_eq!(witness[pub_keys.len() - 1], signatures[0]);
+    assert_eq!(witness[0], signatures[pub_keys.len() - 1]);
+    assert_eq!(witness[pub_keys.len()], witness_script.0.into_vec());
+
+    let (pub_keys, _) = gen_anchoring_keys(68);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    cfg.script = AnchoringScript::ChainedChecksig;
+    assert_eq!(cfg.validate(), Err(ConfigError::TooManyKeys(68, 67)));
+}
+
 #[test]
 fn test_sign_raw_transaction() {
     let unsigned_tx = BitcoinTx::from_hex(
@@ -680,6 +1262,1006 @@ fn test_tx_verify_sighash_type_wrong() {
     assert!(tx.verify_input(&redeem_script, 0, &pub_key, &btc_signature));
 }
 
+// Request that the fake `bitcoind` has received.
+struct FakeRpcRequest {
+    authorization: Option<String>,
+    method: String,
+    params: Vec<Value>,
+}
+
+// Starts the fake `bitcoind` json-rpc server that answers by the given handler
+// and returns its url. `None` from the handler is answered as unauthorized.
+fn fake_bitcoind<F>(handler: F) -> String
+where
+    F: Fn(&FakeRpcRequest) -> Option<Value> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut authorization = None;
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_right();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_at(line.find(':').unwrap_or(0));
+                let value = value.trim_left_matches(':').trim();
+                match name.to_lowercase().as_str() {
+                    "content-length" => content_length = value.parse().unwrap(),
+                    "authorization" => authorization = Some(value.to_owned()),
+                    _ => {}
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            let fake_request = FakeRpcRequest {
+                authorization,
+                method: request["method"].as_str().unwrap().to_owned(),
+                params: request["params"].as_array().cloned().unwrap_or_default(),
+            };
+            let response = match handler(&fake_request) {
+                Some(mut response) => {
+                    response["id"] = request["id"].clone();
+                    let body = response.to_string();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                None => "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\
+                         Connection: close\r\n\r\n"
+                    .to_owned(),
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    host
+}
+
+fn rpc_result(result: Value) -> Option<Value> {
+    Some(json!({ "result": result, "error": null }))
+}
+
+fn rpc_error(code: i64, message: &str) -> Option<Value> {
+    Some(json!({ "result": null, "error": { "code": code, "message": message } }))
+}
+
+// Anchoring transaction that the fake `bitcoind` returns.
+const FAKE_ANCHORING_TX: &str = "01000000019aaf09d7e73a5f9ab394f1358bfb3dbde7b15b983d715f\
+     5c98f369a3f0a288a70000000000ffffffff02b80b00000000000017a914f18eb74087f751109cc9052befd417\
+     7a52c9a30a8700000000000000002c6a2a012800000000000000007fab6f66a0f7a747c820cd01fa30d7bdebd2\
+     6b91c6e03f742abac0b3108134d900000000";
+
+#[test]
+fn test_rpc_relay_scan_tx_out_set() {
+    let tx = AnchoringTx::from_hex(FAKE_ANCHORING_TX).unwrap();
+    let txid = tx.id().to_string();
+    let scans = Arc::new(AtomicUsize::new(0));
+
+    let host = {
+        let scans = Arc::clone(&scans);
+        let txid = txid.clone();
+        fake_bitcoind(move |request| match request.method.as_str() {
+            // The first scan collides with the scan of another client.
+            "scantxoutset" => if scans.fetch_add(1, Ordering::SeqCst) == 0 {
+                rpc_error(-8, "Scan already in progress, use action \"abort\" or \"status\"")
+            } else {
+                rpc_result(json!({
+                    "success": true,
+                    "height": 110,
+                    "unspents": [
+                        { "txid": txid, "vout": 0, "height": 101 },
+                        { "txid": txid, "vout": 1, "height": 101 },
+                    ],
+                }))
+            },
+            "getrawtransaction" if request.params[0] == json!(txid) => {
+                rpc_result(json!(FAKE_ANCHORING_TX))
+            }
+            _ => rpc_error(-32601, "Method not found"),
+        })
+    };
+
+    let relay = AnchoringRpcConfig {
+        host,
+        utxo_discovery: UtxoDiscovery::ScanTxOutSet,
+        ..Default::default()
+    }.into_relay(chain::BITCOIN_TESTNET);
+    let txs = relay
+        .unspent_transactions(&tx.output_address(Network::Testnet))
+        .unwrap();
+    assert_eq!(scans.load(Ordering::SeqCst), 2);
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].body.id(), tx.id());
+    assert_eq!(txs[0].confirmations, Some(10));
+}
+
+#[test]
+fn test_rpc_relay_scan_tx_out_set_incorrect_txid() {
+    let host = fake_bitcoind(|request| match request.method.as_str() {
+        "scantxoutset" => rpc_result(json!({
+            "success": true,
+            "height": 110,
+            "unspents": [{ "txid": "not a txid", "vout": 0, "height": 101 }],
+        })),
+        _ => rpc_error(-32601, "Method not found"),
+    });
+
+    let relay = AnchoringRpcConfig {
+        host,
+        utxo_discovery: UtxoDiscovery::ScanTxOutSet,
+        ..Default::default()
+    }.into_relay(chain::BITCOIN_TESTNET);
+    let tx = AnchoringTx::from_hex(FAKE_ANCHORING_TX).unwrap();
+    assert!(
+        relay
+            .unspent_transactions(&tx.output_address(Network::Testnet))
+            .is_err()
+    );
+}
+
+#[test]
+fn test_rpc_relay_watch_address() {
+    let methods = Arc::new(Mutex::new(Vec::new()));
+    let host = {
+        let methods = Arc::clone(&methods);
+        fake_bitcoind(move |request| {
+            methods.lock().unwrap().push(request.method.clone());
+            match request.method.as_str() {
+                "importaddress" => rpc_result(Value::Null),
+                "getdescriptorinfo" => rpc_result(json!({ "descriptor": "addr(2N)#checksum" })),
+                "importdescriptors" => rpc_result(json!([{ "success": true }])),
+                _ => rpc_error(-32601, "Method not found"),
+            }
+        })
+    };
+
+    let addr = AnchoringTx::from_hex(FAKE_ANCHORING_TX)
+        .unwrap()
+        .output_address(Network::Testnet);
+    let cases = [
+        (UtxoDiscovery::Wallet, vec!["importaddress"]),
+        (
+            UtxoDiscovery::Descriptors,
+            vec!["getdescriptorinfo", "importdescriptors"],
+        ),
+        (UtxoDiscovery::ScanTxOutSet, vec![]),
+    ];
+    for &(utxo_discovery, ref expected) in &cases {
+        let relay = AnchoringRpcConfig {
+            host: host.clone(),
+            utxo_discovery,
+            ..Default::default()
+        }.into_relay(chain::BITCOIN_TESTNET);
+        relay.watch_address(&addr, false).unwrap();
+        assert_eq!(
+            methods.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            *expected
+        );
+    }
+}
+
+#[test]
+fn test_rpc_relay_uses_chain_address_format() {
+    let params = Arc::new(Mutex::new(Vec::new()));
+    let host = {
+        let params = Arc::clone(&params);
+        fake_bitcoind(move |request| match request.method.as_str() {
+            "importaddress" => {
+                params.lock().unwrap().push(request.params[0].clone());
+                rpc_result(Value::Null)
+            }
+            "listunspent" => {
+                params.lock().unwrap().push(request.params[2].clone());
+                rpc_result(json!([]))
+            }
+            _ => rpc_error(-32601, "Method not found"),
+        })
+    };
+
+    let addr = AnchoringTx::from_hex(FAKE_ANCHORING_TX)
+        .unwrap()
+        .output_address(Network::Testnet);
+    let relay = AnchoringRpcConfig {
+        host,
+        ..Default::default()
+    }.into_relay(chain::LITECOIN_TESTNET);
+    relay.watch_address(&addr, false).unwrap();
+    assert!(relay.unspent_transactions(&addr).unwrap().is_empty());
+
+    let expected = addr.to_chain_string(&chain::LITECOIN_TESTNET);
+    assert_ne!(expected, addr.to_base58check());
+    assert_eq!(
+        params.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![json!(expected), json!([expected])]
+    );
+}
+
+#[test]
+fn test_rpc_parse_credentials() {
+    assert_eq!(
+        parse_credentials("__cookie__:1a2b3c\n"),
+        Some(("__cookie__".to_owned(), "1a2b3c".to_owned()))
+    );
+    assert_eq!(
+        parse_credentials("user:pass:word"),
+        Some(("user".to_owned(), "pass:word".to_owned()))
+    );
+    assert_eq!(parse_credentials("user"), None);
+    assert_eq!(parse_credentials(":password"), None);
+}
+
+#[test]
+fn test_rpc_credentials_explicit_have_priority() {
+    let dir = TempDir::new("anchoring_rpc").unwrap();
+    let cookie_file = dir.path().join(".cookie");
+    File::create(&cookie_file)
+        .unwrap()
+        .write_all(b"__cookie__:secret")
+        .unwrap();
+
+    let rpc = AnchoringRpcConfig {
+        username: Some("user".to_owned()),
+        password: Some("password".to_owned()),
+        cookie_file: Some(cookie_file),
+        ..Default::default()
+    };
+    assert_eq!(
+        rpc.resolve_credentials(None, None).unwrap(),
+        (Some("user".to_owned()), Some("password".to_owned()))
+    );
+}
+
+#[test]
+fn test_rpc_credentials_from_files() {
+    let dir = TempDir::new("anchoring_rpc").unwrap();
+    let credentials_file = dir.path().join("credentials");
+    let cookie_file = dir.path().join(".cookie");
+    File::create(&credentials_file)
+        .unwrap()
+        .write_all(b"user:password\n")
+        .unwrap();
+    File::create(&cookie_file)
+        .unwrap()
+        .write_all(b"__cookie__:secret")
+        .unwrap();
+
+    let mut rpc = AnchoringRpcConfig {
+        credentials_file: Some(credentials_file),
+        cookie_file: Some(cookie_file),
+        ..Default::default()
+    };
+    assert_eq!(
+        rpc.resolve_credentials(None, None).unwrap(),
+        (Some("user".to_owned()), Some("password".to_owned()))
+    );
+    assert_eq!(
+        rpc.resolve_credentials(Some("env_user".to_owned()), None).unwrap(),
+        (Some("env_user".to_owned()), None)
+    );
+
+    rpc.credentials_file = None;
+    assert_eq!(
+        rpc.resolve_credentials(None, None).unwrap(),
+        (Some("__cookie__".to_owned()), Some("secret".to_owned()))
+    );
+
+    rpc.cookie_file = Some(dir.path().join("nonexistent"));
+    assert!(rpc.resolve_credentials(None, None).is_err());
+}
+
+#[test]
+fn test_rpc_connection_rereads_cookie() {
+    let dir = TempDir::new("anchoring_rpc").unwrap();
+    let cookie_file = dir.path().join(".cookie");
+    let write_cookie = |password: &str| {
+        File::create(&cookie_file)
+            .unwrap()
+            .write_all(format!("__cookie__:{}", password).as_bytes())
+            .unwrap();
+    };
+    let cookie = Arc::new(Mutex::new("first".to_owned()));
+
+    let host = {
+        let cookie = Arc::clone(&cookie);
+        fake_bitcoind(move |request| {
+            let credentials = format!("__cookie__:{}", cookie.lock().unwrap());
+            let expected = format!("Basic {}", base64::encode(credentials.as_bytes()));
+            if request.authorization.as_ref() != Some(&expected) {
+                return None;
+            }
+            rpc_result(json!(100))
+        })
+    };
+    let connection = RpcConnection::new(AnchoringRpcConfig {
+        host,
+        cookie_file: Some(cookie_file.clone()),
+        ..Default::default()
+    });
+
+    // The cookie file does not exist yet.
+    assert!(connection.call::<u64>("getblockcount", vec![]).is_err());
+
+    write_cookie("first");
+    assert_eq!(connection.call::<u64>("getblockcount", vec![]).unwrap(), 100);
+
+    // `bitcoind` has been restarted with the new cookie.
+    *cookie.lock().unwrap() = "second".to_owned();
+    write_cookie("second");
+    assert_eq!(connection.call::<u64>("getblockcount", vec![]).unwrap(), 100);
+    assert_eq!(connection.request(|client| client.getblockcount()).unwrap(), 100);
+}
+
+#[test]
+fn test_rpc_send_transaction_is_not_retried() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let host = {
+        let attempts = Arc::clone(&attempts);
+        fake_bitcoind(move |request| {
+            if request.method == "sendrawtransaction" {
+                attempts.fetch_add(1, Ordering::SeqCst);
+            }
+            None
+        })
+    };
+
+    let relay = AnchoringRpcConfig {
+        host,
+        username: Some("user".to_owned()),
+        ..Default::default()
+    }.into_relay(chain::BITCOIN_TESTNET);
+    let tx = AnchoringTx::from_hex(FAKE_ANCHORING_TX).unwrap();
+    assert!(relay.send_transaction(tx.into()).is_err());
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_rpc_utxo_discovery_serde() {
+    let rpc: AnchoringRpcConfig = serde_json::from_value(json!({
+        "host": "http://127.0.0.1:18332",
+        "username": null,
+        "password": null,
+    })).unwrap();
+    assert_eq!(rpc.utxo_discovery, UtxoDiscovery::Wallet);
+
+    let rpc = AnchoringRpcConfig {
+        utxo_discovery: UtxoDiscovery::ScanTxOutSet,
+        ..Default::default()
+    };
+    let json = serde_json::to_value(&rpc).unwrap();
+    assert_eq!(json["utxo_discovery"], json!("scantxoutset"));
+    assert_eq!(
+        serde_json::from_value::<AnchoringRpcConfig>(json).unwrap(),
+        rpc
+    );
+
+    assert_eq!(
+        "descriptors".parse::<UtxoDiscovery>(),
+        Ok(UtxoDiscovery::Descriptors)
+    );
+    assert!("importmulti".parse::<UtxoDiscovery>().is_err());
+}
+
+#[test]
+fn test_channel_notifier_wakes_up_listener() {
+    let (sender, notifier) = ChannelNotifier::channel();
+    let updates = ChainUpdates::default();
+    updates.listen(Box::new(notifier), 10);
+    assert!(!updates.take());
+
+    sender
+        .send(ChainEvent {
+            hash: "00000000000000000001".to_owned(),
+            height: 1,
+        })
+        .unwrap();
+    let mut notified = false;
+    for _ in 0..100 {
+        if updates.take() {
+            notified = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(notified);
+    assert!(!updates.take());
+}
+
+#[test]
+fn test_listener_stops_after_updates_dropped() {
+    let (_sender, notifier) = ChannelNotifier::channel();
+    let updates = ChainUpdates::default();
+    let listener = updates.listen(Box::new(notifier), 10);
+
+    drop(updates);
+    listener.join().unwrap();
+}
+
+#[test]
+fn test_channel_notifier_timeout() {
+    let (_sender, mut notifier) = ChannelNotifier::channel();
+    assert_eq!(
+        notifier.wait_event(Duration::from_millis(10)).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_handler_checks_lect_after_notification() {
+    let handler = AnchoringHandler::new(None, AnchoringNodeConfig::default());
+    let frequency = handler.node.check_lect_frequency;
+
+    assert!(!handler.need_check_lect(Height(1)));
+    handler.chain_updates.notify();
+    assert!(handler.need_check_lect(Height(1)));
+    assert!(!handler.need_check_lect(Height(2)));
+    assert!(handler.need_check_lect(Height(frequency)));
+}
+
+#[derive(Debug)]
+struct CountingRelay {
+    tx: BitcoinTx,
+    confirmations: Option<u64>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl BitcoinRelay for CountingRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> RpcResult<Option<BitcoinTx>> {
+        Ok(self.get_transaction_info(txid)?.map(|info| info.body))
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> RpcResult<Option<TxInfo>> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        if txid == self.tx.id() {
+            Ok(Some(TxInfo {
+                body: self.tx.clone(),
+                confirmations: self.confirmations,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn watch_address(&self, _addr: &btc::Address, _rescan: bool) -> RpcResult<()> {
+        Ok(())
+    }
+
+    fn send_transaction(&self, _tx: BitcoinTx) -> RpcResult<()> {
+        Ok(())
+    }
+
+    fn send_to_address(&self, _addr: &btc::Address, _satoshis: u64) -> RpcResult<FundingTx> {
+        unimplemented!()
+    }
+
+    fn unspent_transactions(&self, _addr: &btc::Address) -> RpcResult<Vec<TxInfo>> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        Ok(vec![
+            TxInfo {
+                body: self.tx.clone(),
+                confirmations: self.confirmations,
+            },
+        ])
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig::default()
+    }
+}
+
+fn cached_relay(
+    confirmations: Option<u64>,
+    confirmations_ttl: u64,
+) -> (CachedRelay, BitcoinTx, Arc<AtomicUsize>) {
+    let tx = BitcoinTx::from_hex(
+        "01000000019aaf09d7e73a5f9ab394f1358bfb3dbde7b15b983d715f5c98f3\
+         69a3f0a288a70000000000ffffffff02b80b00000000000017a914f18eb74087f751109cc9052befd4177a52c9\
+         a30a8700000000000000002c6a2a012800000000000000007fab6f66a0f7a747c820cd01fa30d7bdebd26b91c6\
+         e03f742abac0b3108134d900000000",
+    ).unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let inner = CountingRelay {
+        tx: tx.clone(),
+        confirmations,
+        requests: Arc::clone(&requests),
+    };
+    let config = RelayCacheConfig {
+        enabled: true,
+        capacity: 16,
+        confirmations_ttl,
+    };
+    (CachedRelay::new(Box::new(inner), &config), tx, requests)
+}
+
+#[test]
+fn test_cached_relay_confirmed_body() {
+    let (relay, tx, requests) = cached_relay(Some(10), 0);
+
+    for _ in 0..3 {
+        assert_eq!(relay.get_transaction(tx.id()).unwrap(), Some(tx.clone()));
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert_eq!(relay.stats(), CacheStats { hits: 2, misses: 1 });
+    let relay: Box<BitcoinRelay> = Box::new(relay);
+    assert_eq!(relay.cache_stats(), Some(CacheStats { hits: 2, misses: 1 }));
+
+    // Confirmations with the zero ttl are always requested from the inner relay.
+    assert_eq!(relay.get_transaction_confirmations(tx.id()).unwrap(), Some(10));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_cached_relay_unconfirmed_body() {
+    let (relay, tx, requests) = cached_relay(None, 0);
+
+    assert_eq!(relay.get_transaction(tx.id()).unwrap(), Some(tx.clone()));
+    assert_eq!(relay.get_transaction(tx.id()).unwrap(), Some(tx.clone()));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert_eq!(relay.stats(), CacheStats { hits: 0, misses: 2 });
+}
+
+#[test]
+fn test_cached_relay_confirmations_ttl() {
+    let (relay, tx, requests) = cached_relay(Some(1), 60_000);
+    let addr = btc::Address::from_base58check("2NFGToas8B6sXqsmtGwL1H4kC5fGWSpTcYA").unwrap();
+
+    // Unspent transactions fill the cache.
+    relay.unspent_transactions(&addr).unwrap();
+    assert_eq!(relay.get_transaction_confirmations(tx.id()).unwrap(), Some(1));
+    let info = relay.get_transaction_info(tx.id()).unwrap().unwrap();
+    assert_eq!(info.body, tx);
+    assert_eq!(info.confirmations, Some(1));
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert_eq!(relay.stats(), CacheStats { hits: 2, misses: 0 });
+
+    // The sent transaction confirmations should be requested again.
+    relay.send_transaction(tx.clone()).unwrap();
+    assert_eq!(relay.get_transaction_confirmations(tx.id()).unwrap(), Some(1));
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000\
+                              000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa\
+                              4b1e5e4a29ab5f49ffff001d1dac2b7c";
+const FIRST_BLOCK_HEADER: &str = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d619\
+                                  0000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb6\
+                                  06e857233e0e61bc6649ffff001d01e36299";
+
+fn block_header(hex: &str) -> BlockHeader {
+    BlockHeader::from_bytes(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+}
+
+#[test]
+fn test_spv_genesis_header() {
+    let genesis = block_header(GENESIS_HEADER);
+    assert_eq!(
+        genesis.block_hash(),
+        "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+    );
+    assert_eq!(genesis.bits, 0x1d00_ffff);
+    assert!(genesis.check_proof_of_work().is_ok());
+
+    let mut header = genesis.clone();
+    header.hash[31] = 0xff;
+    assert!(header.check_proof_of_work().is_err());
+}
+
+#[test]
+fn test_spv_header_chain() {
+    let genesis = block_header(GENESIS_HEADER);
+    let first = block_header(FIRST_BLOCK_HEADER);
+
+    let mut chain = HeaderChain::new(Network::Bitcoin, 0, genesis.clone()).unwrap();
+    // Header that does not connect to the chain.
+    assert!(chain.push(genesis.clone()).is_err());
+    // Header with the wrong difficulty.
+    let mut wrong_bits = first.clone();
+    wrong_bits.bits = 0x1c00_ffff;
+    assert!(chain.push(wrong_bits).is_err());
+
+    chain.push(first.clone()).unwrap();
+    assert_eq!(chain.tip_height(), 1);
+    assert_eq!(chain.height_of(&first.hash), Some(1));
+
+    assert_eq!(chain.pop(), Some(first.clone()));
+    assert_eq!(chain.height_of(&first.hash), None);
+    assert_eq!(chain.pop(), None);
+
+    assert!(HeaderChain::new(Network::Bitcoin, 1, genesis).is_err());
+}
+
+#[test]
+fn test_spv_testnet_min_difficulty() {
+    let mut checkpoint = block_header(GENESIS_HEADER);
+    checkpoint.bits = 0x1c00_ffff;
+    let first = block_header(FIRST_BLOCK_HEADER);
+    let error_of = |header: BlockHeader| {
+        let mut chain = HeaderChain::new(Network::Testnet, 0, checkpoint.clone()).unwrap();
+        chain.push(header).unwrap_err().to_string()
+    };
+
+    // The block with the minimum difficulty is allowed after 20 minutes.
+    let mut chain = HeaderChain::new(Network::Testnet, 0, checkpoint.clone()).unwrap();
+    chain.push(first.clone()).unwrap();
+
+    // Otherwise the block should keep the regular difficulty.
+    let mut early = first.clone();
+    early.time = checkpoint.time + 20 * 60;
+    assert!(error_of(early.clone()).contains("has bits 1d00ffff, expected 1c00ffff"));
+    early.bits = checkpoint.bits;
+    assert!(error_of(early).contains("does not satisfy proof of work"));
+}
+
+#[test]
+fn test_spv_relay_verifies_unspent_confirmations() {
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    const FIRST_BLOCK_HASH: &str =
+        "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+    // Coinbase transaction of the first block.
+    let tx = BitcoinTx::from_hex(
+        "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff\
+         0704ffff001d0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec11600ae1390\
+         813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781e62294721166bf621e73a82cbf2342c8\
+         58eeac00000000",
+    ).unwrap();
+    // Merkle block with the single transaction, its hash is the merkle root.
+    let proof = format!(
+        "{}0100000001{}0101",
+        FIRST_BLOCK_HEADER,
+        &FIRST_BLOCK_HEADER[72..136]
+    );
+
+    let host = fake_bitcoind(move |request| match request.method.as_str() {
+        "getblockcount" => rpc_result(json!(1)),
+        "getblockhash" => match request.params[0].as_u64() {
+            Some(0) => rpc_result(json!(GENESIS_HASH)),
+            Some(1) => rpc_result(json!(FIRST_BLOCK_HASH)),
+            _ => rpc_error(-8, "Block height out of range"),
+        },
+        "getblockheader" => match request.params[0].as_str() {
+            Some(GENESIS_HASH) => rpc_result(json!(GENESIS_HEADER)),
+            Some(FIRST_BLOCK_HASH) => rpc_result(json!(FIRST_BLOCK_HEADER)),
+            _ => rpc_error(-5, "Block not found"),
+        },
+        "gettxoutproof" => rpc_result(json!(proof)),
+        _ => rpc_error(-32601, "Method not found"),
+    });
+
+    // `bitcoind` inflates the confirmations of the transaction.
+    let inner = CountingRelay {
+        tx: tx.clone(),
+        confirmations: Some(100),
+        requests: Arc::new(AtomicUsize::new(0)),
+    };
+    let spv = SpvConfig {
+        checkpoint_height: 0,
+        checkpoint_hash: GENESIS_HASH.to_owned(),
+        network: Network::Bitcoin,
+    };
+    let cache = RelayCacheConfig {
+        enabled: true,
+        capacity: 16,
+        confirmations_ttl: 60_000,
+    };
+    let connection = RpcConnection::new(AnchoringRpcConfig {
+        host,
+        ..Default::default()
+    });
+    let relay = CachedRelay::new(
+        Box::new(SpvRelay::new(Box::new(inner), connection, spv)),
+        &cache,
+    );
+
+    let addr = btc::Address::from_base58check("2NFGToas8B6sXqsmtGwL1H4kC5fGWSpTcYA").unwrap();
+    let txs = relay.unspent_transactions(&addr).unwrap();
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].body.id(), tx.id());
+    assert_eq!(txs[0].confirmations, Some(1));
+    // The cache holds the verified confirmations.
+    assert_eq!(relay.get_transaction_confirmations(tx.id()).unwrap(), Some(1));
+    assert_eq!(relay.stats(), CacheStats { hits: 1, misses: 0 });
+}
+
+#[test]
+fn test_spv_relay_reports_unverified_confirmations() {
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    let tx = BitcoinTx::from_hex(
+        "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff\
+         0704ffff001d0104ffffffff0100f2052a0100000043410496b538e853519c726a2c91e61ec11600ae1390\
+         813a627c66fb8be7947be63c52da7589379515d4e0a604f8141781e62294721166bf621e73a82cbf2342c8\
+         58eeac00000000",
+    ).unwrap();
+
+    let host = fake_bitcoind(move |request| match request.method.as_str() {
+        "gettxoutproof" => rpc_error(-5, "Transaction not yet in block"),
+        _ => rpc_error(-32601, "Method not found"),
+    });
+    let inner = CountingRelay {
+        tx: tx.clone(),
+        confirmations: Some(3),
+        requests: Arc::new(AtomicUsize::new(0)),
+    };
+    let spv = SpvConfig {
+        checkpoint_height: 0,
+        checkpoint_hash: GENESIS_HASH.to_owned(),
+        network: Network::Bitcoin,
+    };
+    let connection = RpcConnection::new(AnchoringRpcConfig {
+        host,
+        ..Default::default()
+    });
+    let relay = SpvRelay::new(Box::new(inner), connection, spv);
+
+    assert!(relay.get_transaction_confirmations(tx.id()).is_err());
+    assert!(relay.get_transaction_info(tx.id()).is_err());
+}
+
+#[test]
+fn test_spv_next_work_required() {
+    // Test vectors are taken from the bitcoin core `pow_tests`.
+    assert_eq!(
+        next_work_required(1_261_130_161, 1_262_152_739, 0x1d00_ffff),
+        0x1d00_d86a
+    );
+    assert_eq!(
+        next_work_required(1_231_006_505, 1_233_061_996, 0x1d00_ffff),
+        0x1d00_ffff
+    );
+    assert_eq!(
+        next_work_required(1_279_008_237, 1_279_297_671, 0x1c05_a3f4),
+        0x1c01_68fd
+    );
+    assert_eq!(
+        next_work_required(1_263_163_443, 1_269_211_443, 0x1c38_7f6f),
+        0x1d00_e1fd
+    );
+}
+
+#[test]
+fn test_spv_merkle_block() {
+    let proof = Vec::<u8>::from_hex(
+        "010000000000000000000000000000000000000000000000000000000000000000000000e129dfe02f\
+         567fc612d126596d43406144f40a771810ac7143421d2df3e5c1d0002f6859ffff001d000000000300\
+         0000031406e05881e299367766d313e26c05564ec91bf721d31726bd6e46e60689539a9c12cfdc04c7\
+         4584d787ac3d23772132c18524bc7ab28dec4219b8fc5b425f7002a429965de5e45f5b42f3e8decbd4\
+         f19ccc58e2ac86a3c0b66dbb443937283d010b",
+    ).unwrap();
+    let block = MerkleBlock::from_bytes(&proof).unwrap();
+    let matched =
+        btc::TxId::from_hex("705f425bfcb81942ec8db27abc2485c1322177233dac87d78445c704dccf129c")
+            .unwrap();
+    assert_eq!(block.extract_matches().unwrap(), vec![matched]);
+
+    let mut left = [0_u8; 32];
+    left.copy_from_slice(&proof[85..117]);
+    let mut right = [0_u8; 32];
+    right.copy_from_slice(&proof[149..181]);
+    assert_eq!(
+        block.merkle_branch(&matched).unwrap(),
+        vec![MerkleStep::Left(left), MerkleStep::Right(right)]
+    );
+    // Unmatched transaction.
+    assert!(block.merkle_branch(&btc::TxId::from_slice(&left).unwrap()).is_err());
+
+    // Merkle root mismatch.
+    let mut wrong_root = proof.clone();
+    wrong_root[36] ^= 1;
+    let block = MerkleBlock::from_bytes(&wrong_root).unwrap();
+    assert!(block.extract_matches().is_err());
+
+    // Truncated proof.
+    assert!(MerkleBlock::from_bytes(&proof[..proof.len() - 1]).is_err());
+}
+
+fn read_ots_varuint(data: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn read_ots_varbytes<'a>(data: &'a [u8], pos: &mut usize) -> &'a [u8] {
+    let len = read_ots_varuint(data, pos) as usize;
+    *pos += len;
+    &data[*pos - len..*pos]
+}
+
+fn ots_anchoring_tx(block_hash: Hash) -> AnchoringTx {
+    let (keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&keys, 3).compressed(Network::Testnet);
+    let prev_tx = dummy_anchoring_tx(&redeem_script);
+    TransactionBuilder::with_prev_tx(&prev_tx, 0)
+        .fee(1000)
+        .payload(Height(10), block_hash)
+        .send_to(btc::Address::from_script(&redeem_script, Network::Testnet))
+        .into_transaction()
+        .unwrap()
+}
+
+#[test]
+fn test_ots_timestamp() {
+    let block_hash = hash(&[1, 2, 3]);
+    check_ots_timestamp(&ots_anchoring_tx(block_hash), block_hash);
+}
+
+#[test]
+fn test_ots_timestamp_witness_tx() {
+    let block_hash = hash(&[1, 2, 3]);
+    let mut tx = ots_anchoring_tx(block_hash);
+    tx.0.witness = vec![vec![vec![1; 72], vec![2; 33]]];
+    assert_ne!(tx.id(), tx.wid());
+    check_ots_timestamp(&tx, block_hash);
+}
+
+fn check_ots_timestamp(tx: &AnchoringTx, block_hash: Hash) {
+    let inclusion = TxInclusion {
+        block_height: 500_000,
+        branch: vec![MerkleStep::Left([1; 32]), MerkleStep::Right([2; 32])],
+    };
+    let data = ots::timestamp(tx, &tx.payload(), &inclusion).unwrap();
+
+    // Header, version and the file hash.
+    let mut pos = 31;
+    assert_eq!(
+        &data[..pos],
+        &b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94"[..]
+    );
+    assert_eq!(read_ots_varuint(&data, &mut pos), 1);
+    assert_eq!(data[pos], 0x08);
+    pos += 1;
+    let mut msg = data[pos..pos + 32].to_vec();
+    assert_eq!(msg, block_hash.as_ref());
+    pos += 32;
+
+    // Operations.
+    while data[pos] != 0x00 {
+        pos += 1;
+        msg = match data[pos - 1] {
+            0x08 => hash(&msg).as_ref().to_vec(),
+            0xf0 => [&msg[..], read_ots_varbytes(&data, &mut pos)].concat(),
+            0xf1 => [read_ots_varbytes(&data, &mut pos), &msg[..]].concat(),
+            op => panic!("Unexpected operation {}", op),
+        };
+    }
+    let double_sha256 = |data: &[u8]| hash(hash(data).as_ref());
+    let root = double_sha256(&[&[1; 32][..], tx.id().as_bytes()].concat());
+    let root = double_sha256(&[root.as_ref(), &[2; 32][..]].concat());
+    assert_eq!(msg, root.as_ref());
+
+    // Bitcoin attestation.
+    pos += 1;
+    assert_eq!(&data[pos..pos + 8], &b"\x05\x88\x96\x0d\x73\xd7\x19\x01"[..]);
+    pos += 8;
+    let attestation = read_ots_varbytes(&data, &mut pos);
+    assert_eq!(read_ots_varuint(attestation, &mut 0), 500_000);
+    assert_eq!(pos, data.len());
+}
+
+fn prefixed_hash(prefix: u8, hashes: &[&Hash]) -> Hash {
+    let mut buf = vec![prefix];
+    for item in hashes {
+        buf.extend_from_slice(item.as_ref());
+    }
+    hash(&buf)
+}
+
+#[test]
+fn test_mmr_roots() {
+    let data = (0..3).map(|i| hash(&[i])).collect::<Vec<_>>();
+    let mut mmr = MerkleMountainRange::new(Vec::new());
+    for item in &data {
+        mmr.push(item);
+    }
+    assert_eq!(mmr.leaves(), 3);
+
+    let leaves = data.iter().map(|item| prefixed_hash(0, &[item])).collect::<Vec<_>>();
+    let node = prefixed_hash(1, &[&leaves[0], &leaves[1]]);
+    assert_eq!(mmr.root(0), None);
+    assert_eq!(mmr.root(1), Some(leaves[0]));
+    assert_eq!(mmr.root(2), Some(node));
+    assert_eq!(mmr.root(3), Some(prefixed_hash(2, &[&node, &leaves[2]])));
+    assert_eq!(mmr.root(4), None);
+}
+
+#[test]
+fn test_mmr_proofs() {
+    let data = (0..33).map(|i| hash(&[i])).collect::<Vec<_>>();
+    let mut mmr = MerkleMountainRange::new(Vec::new());
+    for item in &data {
+        mmr.push(item);
+    }
+
+    for leaves in 1..data.len() as u64 + 1 {
+        let root = mmr.root(leaves).unwrap();
+        // Prefix of the range has the same root as the range built from scratch.
+        let mut prefix = MerkleMountainRange::new(Vec::new());
+        for item in &data[..leaves as usize] {
+            prefix.push(item);
+        }
+        assert_eq!(prefix.root(leaves), Some(root));
+
+        for index in 0..leaves {
+            let proof = mmr.proof(index, leaves).unwrap();
+            assert!(proof.verify(&data[index as usize], &root));
+            assert!(!proof.verify(&hash(&[100]), &root));
+        }
+        assert_eq!(mmr.proof(leaves, leaves), None);
+    }
+
+    let mut proof = mmr.proof(5, 33).unwrap();
+    proof.leaf_index = 6;
+    assert_eq!(proof.root(&data[5]), None);
+    assert_eq!(proof.root(&data[6]), None);
+}
+
+#[test]
+fn test_chain_params() {
+    assert_eq!(ChainParams::by_name("litecoin"), Some(chain::LITECOIN));
+    assert_eq!(ChainParams::by_name("dogecoin"), None);
+    assert_eq!(ChainParams::bitcoin(Network::Testnet), chain::BITCOIN_TESTNET);
+
+    let addr = btc::Address::from_base58check("2NFGToas8B6sXqsmtGwL1H4kC5fGWSpTcYA").unwrap();
+    assert_eq!(
+        addr.to_chain_string(&chain::BITCOIN_TESTNET),
+        "2NFGToas8B6sXqsmtGwL1H4kC5fGWSpTcYA"
+    );
+    assert_eq!(
+        addr.to_chain_string(&chain::BITCOIN),
+        "3PiFjqw6ZeNBe69Lboi8f7kvsK4LecQPrL"
+    );
+    assert_eq!(
+        addr.to_chain_string(&chain::LITECOIN),
+        "MVvQ3jM4WmDcSbREhghUUm1LC1enebMmsn"
+    );
+    assert_eq!(
+        addr.to_chain_string(&chain::LITECOIN_TESTNET),
+        "QidDvbjNCCvcz4Xvu3N2MmBdE3iLGCapgZ"
+    );
+
+    assert!(chain::BITCOIN.is_dust(545));
+    assert!(!chain::BITCOIN.is_dust(546));
+    assert_eq!(chain::BITCOIN.min_fee(250), 250);
+    assert_eq!(chain::LITECOIN.min_fee(250), 25_000);
+}
+
+#[test]
+fn test_transaction_builder_dust_limit() {
+    let input_tx = AnchoringTx::from_hex(
+        "01000000019aaf09d7e73a5f9ab394f1358bfb3dbde7b15b983d715f\
+         5c98f369a3f0a288a70000000000ffffffff02b80b00000000000017a914f18eb74087f751109cc9052befd417\
+         7a52c9a30a8700000000000000002c6a2a012800000000000000007fab6f66a0f7a747c820cd01fa30d7bdebd2\
+         6b91c6e03f742abac0b3108134d900000000",
+    ).unwrap();
+    let addr = input_tx.output_address(Network::Testnet);
+    let builder = || {
+        TransactionBuilder::with_prev_tx(&input_tx, 0)
+            .fee(1000)
+            .payload(Height::zero(), Hash::zero())
+            .send_to(addr.clone())
+    };
+
+    let tx = builder().dust_limit(2000).into_transaction().unwrap();
+    assert_eq!(tx.amount(), 2000);
+    assert!(builder().dust_limit(2001).into_transaction().is_err());
+}
+
 // rpc tests. Works through `rpc` by given env variables.
 // See the `anchoring_client` method on top of this file.
 #[cfg(feature = "rpc_tests")]
@@ -706,9 +2288,10 @@ mod rpc {
                 .unwrap(),
             username: env::var("ANCHORING_USER").ok(),
             password: env::var("ANCHORING_PASSWORD").ok(),
+            ..Default::default()
         };
 
-        RpcClient::from(rpc)
+        rpc.client().unwrap()
     }
 
     pub fn create_multisig_address<'a, I>(
@@ -17,6 +17,7 @@ use std::io;
 pub use details::error::Error as InternalError;
 pub use handler::error::Error as HandlerError;
 use bitcoinrpc::Error as RpcError;
+use details::signer::SignerError;
 
 /// Anchoring btc service Error type.
 #[derive(Debug, Fail, Display)]
@@ -41,6 +42,12 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<SignerError> for Error {
+    fn from(err: SignerError) -> Error {
+        Error::Internal(InternalError::Signer(err))
+    }
+}
+
 impl From<InternalError> for Error {
     fn from(e: InternalError) -> Self {
         Error::Internal(e)
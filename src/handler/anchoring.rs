@@ -12,19 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use bitcoin::network::serialize::serialize;
 use bitcoin::util::base58::ToBase58;
 
 use exonum::blockchain::{Schema, ServiceContext};
+use exonum::crypto::Hash;
 use exonum::helpers::Height;
 use exonum::encoding::serialize::encode_hex;
 
 use error::Error as ServiceError;
 use details::btc;
 use details::btc::HexValueEx;
+use details::btc::payload::NetworkId;
 use details::btc::transactions::{AnchoringTx, TransactionBuilder};
-use blockchain::consensus_storage::AnchoringConfig;
+use details::signer::{SignRequest, SignerError};
+use blockchain::consensus_storage::{AnchoringConfig, AnchoringSchedule, PayloadCommitment};
 use blockchain::schema::AnchoringSchema;
-use blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use blockchain::dto::{MsgAnchoringBlocksMmr, MsgAnchoringSignature, MsgAnchoringTimeMark,
+                      MsgAnchoringUpdateLatest};
 
 use super::{collect_signatures, AnchoringHandler, LectKind, MultisigAddress};
 
@@ -38,10 +43,11 @@ impl AnchoringHandler {
         let multisig = self.multisig_address(cfg);
         trace!("Anchoring state, addr={}", multisig.addr.to_base58check());
 
-        if context.height().0 % self.node.check_lect_frequency == 0 {
+        if self.need_check_lect(context.height()) {
             // First of all we try to update our lect and actual configuration
             self.update_our_lect(&multisig, context)?;
         }
+        self.try_mark_time(cfg, context)?;
         // Now if we have anchoring tx proposal we must try to finalize it
         if let Some(proposal) = self.proposal_tx.clone() {
             self.try_finalize_proposal_tx(proposal, &multisig, context)?;
@@ -66,11 +72,30 @@ impl AnchoringHandler {
             LectKind::Funding(_) => self.try_create_anchoring_tx_chain(multisig, None, context),
             LectKind::Anchoring(tx) => {
                 let anchored_height = tx.payload().block_height;
-                let latest_anchored_height =
-                    multisig.common.latest_anchoring_height(context.height());
+                let latest_anchored_height = AnchoringSchema::new(context.snapshot())
+                    .latest_anchoring_height(multisig.common, context.height());
                 if latest_anchored_height > anchored_height {
+                    // Anchor at most once per bitcoin block.
+                    if multisig.common.schedule == AnchoringSchedule::BitcoinBlocks {
+                        let confirmations = self.client().get_transaction_confirmations(tx.id())?;
+                        if confirmations.unwrap_or(0) == 0 {
+                            return Ok(());
+                        }
+                    }
                     return self.create_proposal_tx(&tx, multisig, latest_anchored_height, context);
                 }
+                // Refresh the anchoring output well before the recovery path is unlocked.
+                if let Some(ref recovery) = multisig.common.recovery {
+                    let confirmations = self.client().get_transaction_confirmations(tx.id())?;
+                    if confirmations.unwrap_or(0) >= recovery.refresh_confirmations() {
+                        info!(
+                            "Refresh anchoring tx={}, confirmations={:?}",
+                            tx.id(),
+                            confirmations
+                        );
+                        return self.create_proposal_tx(&tx, multisig, anchored_height, context);
+                    }
+                }
                 Ok(())
             }
             LectKind::None => {
@@ -80,6 +105,88 @@ impl AnchoringHandler {
         }
     }
 
+    // Send the time mark if the next interval of the time-based schedule has begun
+    pub fn try_mark_time(
+        &mut self,
+        cfg: &AnchoringConfig,
+        context: &ServiceContext,
+    ) -> Result<(), ServiceError> {
+        // The time marks are sent only by the validators.
+        let validator_id = match context.validator_id() {
+            Some(validator_id) => validator_id,
+            None => return Ok(()),
+        };
+        let anchoring_schema = AnchoringSchema::new(context.snapshot());
+        let time = match anchoring_schema.consensus_time() {
+            Some(time) => time,
+            None => return Ok(()),
+        };
+        let epoch = match cfg.schedule.time_epoch(time) {
+            Some(epoch) => epoch,
+            None => return Ok(()),
+        };
+        let marked_epoch = anchoring_schema
+            .time_marks()
+            .last()
+            .and_then(|mark| cfg.schedule.time_epoch(mark.time()));
+        if marked_epoch >= Some(epoch) {
+            return Ok(());
+        }
+
+        // The validators take turns to mark the interval, so the mark is sent by a single
+        // validator at a time and is resent by the next one after the timeout.
+        let turn = match cfg.schedule.time_mark_turn(time, context.validators().len()) {
+            Some((turn, id)) if id == validator_id => turn,
+            _ => return Ok(()),
+        };
+        if self.time_mark_turn == Some((epoch, turn)) {
+            return Ok(());
+        }
+
+        let mark_msg = MsgAnchoringTimeMark::new(
+            context.public_key(),
+            validator_id,
+            epoch,
+            context.secret_key(),
+        );
+        trace!("Mark time msg={:#?}", mark_msg);
+        context.transaction_sender().send(Box::new(mark_msg))?;
+        self.time_mark_turn = Some((epoch, turn));
+        Ok(())
+    }
+
+    // Send the message that appends the committed block hashes to the blocks range
+    pub fn try_update_blocks_mmr(&mut self, context: &ServiceContext) -> Result<(), ServiceError> {
+        // The blocks range is updated only by the validators.
+        let validator_id = match context.validator_id() {
+            Some(validator_id) => validator_id,
+            None => return Ok(()),
+        };
+        let anchoring_schema = AnchoringSchema::new(context.snapshot());
+        let cfg = anchoring_schema.actual_anchoring_config();
+        if cfg.payload_commitment != PayloadCommitment::BlocksRoot
+            || anchoring_schema.blocks_mmr_leaves() > context.height().0
+        {
+            return Ok(());
+        }
+
+        // The validators take turns to send the message, so it is sent once per block.
+        let validators_count = context.validators().len() as u64;
+        if context.height().0 % validators_count != u64::from(validator_id.0) {
+            return Ok(());
+        }
+
+        let mmr_msg = MsgAnchoringBlocksMmr::new(
+            context.public_key(),
+            validator_id,
+            context.height().0,
+            context.secret_key(),
+        );
+        trace!("Update blocks mmr msg={:#?}", mmr_msg);
+        context.transaction_sender().send(Box::new(mmr_msg))?;
+        Ok(())
+    }
+
     // Create first anchoring tx proposal from funding tx in AnchoringNodeConfig
     pub fn try_create_anchoring_tx_chain(
         &mut self,
@@ -90,16 +197,21 @@ impl AnchoringHandler {
         trace!("Create tx chain");
         if let Some(funding_tx) = self.available_funding_tx(multisig)? {
             // Create anchoring proposal
-            let height = multisig.common.latest_anchoring_height(context.height());
+            let height = AnchoringSchema::new(context.snapshot())
+                .latest_anchoring_height(multisig.common, context.height());
             let hash = Schema::new(context.snapshot())
                 .block_hashes_by_height()
                 .get(height.0)
                 .unwrap();
 
             let out = funding_tx.find_out(&multisig.addr).unwrap();
-            let proposal = TransactionBuilder::with_prev_tx(&funding_tx, out)
-                .fee(multisig.common.fee)
-                .payload(height, hash)
+            let builder = TransactionBuilder::with_prev_tx(&funding_tx, out)
+                .fee(multisig.common.fee);
+            let builder = match with_payload(builder, multisig.common, context, height, hash) {
+                Some(builder) => builder,
+                None => return Ok(()),
+            };
+            let proposal = builder
                 .prev_tx_chain(prev_tx_chain)
                 .send_to(multisig.addr.clone())
                 .into_transaction()?;
@@ -131,10 +243,11 @@ impl AnchoringHandler {
             .unwrap();
 
         let proposal = {
-            let mut builder = TransactionBuilder::with_prev_tx(lect, 0)
-                .fee(multisig.common.fee)
-                .payload(height, hash)
-                .send_to(multisig.addr.clone());
+            let builder = TransactionBuilder::with_prev_tx(lect, 0).fee(multisig.common.fee);
+            let mut builder = match with_payload(builder, multisig.common, context, height, hash) {
+                Some(builder) => builder.send_to(multisig.addr.clone()),
+                None => return Ok(()),
+            };
             if let Some(funds) = self.available_funding_tx(multisig)? {
                 let out = funds.find_out(&multisig.addr).expect(
                     "Funding tx has proper \
@@ -161,12 +274,78 @@ impl AnchoringHandler {
         multisig: &MultisigAddress,
         context: &ServiceContext,
     ) -> Result<(), ServiceError> {
-        for input in proposal.inputs() {
-            let signature = proposal.sign_input(&multisig.redeem_script, input, &multisig.priv_key);
+        let chain = multisig.common.chain_params();
+        let size = serialize(&proposal.0).map(|bytes| bytes.len()).unwrap_or(0);
+        if multisig.common.fee < chain.min_fee(size) {
+            warn!(
+                "Anchoring fee={} is below the minimum relay fee={} of the {} chain",
+                multisig.common.fee,
+                chain.min_fee(size),
+                chain.name
+            );
+        }
+        let value = proposal.0.output[0].value;
+        if chain.is_dust(value) {
+            warn!(
+                "Anchoring output value={} is below the dust limit={} of the {} chain",
+                value, chain.dust_limit, chain.name
+            );
+        }
 
+        // The values of the spent outputs are signed only by the segregated witness inputs.
+        let spent_values = if multisig.redeem_script.is_witness_script() {
+            let values = AnchoringSchema::new(context.snapshot()).spent_values(&proposal);
+            if values.is_none() {
+                warn!("Unable to find the transactions spent by proposal={:#?}", proposal);
+                return Ok(());
+            }
+            values
+        } else {
+            None
+        };
+
+        // The spent transactions let the remote signer check the fee of the proposal.
+        let spent_txs = AnchoringSchema::new(context.snapshot())
+            .spent_txs(&proposal)
+            .unwrap_or_default();
+        // The inputs spend the outputs of the actual configuration, while `multisig.addr`
+        // is the address of the following configuration during the transition.
+        let spent_addr = multisig.common.redeem_script().1.to_base58check();
+        let signatures = {
+            let signer = self.signer()?;
+            proposal
+                .inputs()
+                .map(|input| {
+                    let request = SignRequest {
+                        address: spent_addr.clone(),
+                        tx: proposal.clone(),
+                        input,
+                        redeem_script: multisig.redeem_script.clone(),
+                        value: spent_values.as_ref().map(|values| values[input as usize]),
+                        spent_txs: spent_txs.clone(),
+                    };
+                    signer.sign(&request).map(|signature| (input, signature))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let signatures = match signatures {
+            Ok(signatures) => signatures,
+            // Without the private key the proposal is signed externally, see `PrivateApi`.
+            Err(SignerError::UnknownAddress(addr)) => {
+                info!(
+                    "No private key for addr={}, the proposal awaits the external signatures",
+                    addr
+                );
+                self.proposal_tx = Some(proposal);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for (input, signature) in signatures {
             let sign_msg = MsgAnchoringSignature::new(
                 context.public_key(),
-                self.validator_id(context),
+                self.signer_id(multisig.common, context),
                 proposal.clone(),
                 input,
                 &signature,
@@ -194,8 +373,9 @@ impl AnchoringHandler {
         let txid = proposal.id();
 
         let proposal_height = proposal.payload().block_height;
-        if multisig.common.latest_anchoring_height(context.height())
-            != multisig.common.latest_anchoring_height(proposal_height)
+        let anchoring_schema = AnchoringSchema::new(context.snapshot());
+        if anchoring_schema.latest_anchoring_height(multisig.common, context.height())
+            != anchoring_schema.latest_anchoring_height(multisig.common, proposal_height)
         {
             warn!(
                 "Unable to finalize anchoring tx for height={}",
@@ -206,7 +386,6 @@ impl AnchoringHandler {
         }
 
         let collected_signatures = {
-            let anchoring_schema = AnchoringSchema::new(context.snapshot());
             let signatures = anchoring_schema.signatures(&txid);
             collect_signatures(&proposal, multisig.common, &signatures)
         };
@@ -246,7 +425,7 @@ impl AnchoringHandler {
                 .len();
             let lect_msg = MsgAnchoringUpdateLatest::new(
                 context.public_key(),
-                self.validator_id(context),
+                self.signer_id(multisig.common, context),
                 new_lect.into(),
                 lects_count,
                 context.secret_key(),
@@ -258,3 +437,37 @@ impl AnchoringHandler {
         Ok(())
     }
 }
+
+// Sets the payload of the anchoring transaction for the given block. Returns `None`
+// if the blocks root at the given height is not available yet.
+fn with_payload(
+    builder: TransactionBuilder,
+    cfg: &AnchoringConfig,
+    context: &ServiceContext,
+    height: Height,
+    hash: Hash,
+) -> Option<TransactionBuilder> {
+    let schema = Schema::new(context.snapshot());
+    let state_hash = *schema.blocks().get(&hash).unwrap().state_hash();
+    let genesis_hash = schema.block_hash_by_height(Height::zero()).unwrap();
+    let builder = builder
+        .payload(height, hash)
+        .payload_prefix(&cfg.payload_prefix)
+        .payload_version(cfg.payload_version)
+        .state_hash(state_hash)
+        .network_id(NetworkId::from_genesis_hash(&genesis_hash));
+    let anchoring_schema = AnchoringSchema::new(context.snapshot());
+    match cfg.payload_commitment {
+        PayloadCommitment::StateHash => Some(builder),
+        PayloadCommitment::BlocksRoot => match anchoring_schema.blocks_root(height) {
+            Some(blocks_root) => Some(builder.blocks_root(blocks_root)),
+            None => {
+                info!("Blocks root at height={} is not available yet", height);
+                None
+            }
+        },
+        PayloadCommitment::NotaryRoot => {
+            Some(builder.notary_root(anchoring_schema.notary_root(height)))
+        }
+    }
+}
@@ -32,13 +32,14 @@ impl AnchoringHandler {
         state: &ServiceContext,
     ) -> Result<(), ServiceError> {
         trace!("Auditing state");
-        if state.height().0 % self.node.check_lect_frequency == 0 {
+        if self.need_check_lect(state.height()) {
             let r = match self.collect_lects(state)? {
                 LectKind::Funding(tx) => self.check_funding_lect(tx, state),
                 LectKind::Anchoring(tx) => self.check_anchoring_lect(&tx),
                 LectKind::None => {
                     let e = HandlerError::LectNotFound {
-                        height: cfg.latest_anchoring_height(state.height()),
+                        height: AnchoringSchema::new(state.snapshot())
+                            .latest_anchoring_height(cfg, state.height()),
                     };
                     Err(e.into())
                 }
@@ -25,6 +25,8 @@ use error::Error as ServiceError;
 use handler::error::Error as HandlerError;
 use details::rpc::BitcoinRelay;
 use details::btc;
+use details::notifier::ChainUpdates;
+use details::signer::{LocalSigner, RemoteSigner, Signer};
 use details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx, TxKind};
 use local_storage::AnchoringNodeConfig;
 use blockchain::consensus_storage::AnchoringConfig;
@@ -42,9 +44,20 @@ impl AnchoringHandler {
             proposal_tx: None,
             known_addresses: HashSet::new(),
             errors_sink: None,
+            chain_updates: ChainUpdates::default(),
+            time_mark_turn: None,
         }
     }
 
+    /// Returns `true` if the `lect` should be checked at the given height.
+    /// Besides the regular checks each `check_lect_frequency` blocks, the `lect` is checked
+    /// immediately after the new bitcoin block has been received.
+    #[doc(hidden)]
+    pub fn need_check_lect(&self, height: Height) -> bool {
+        let updated = self.chain_updates.take();
+        updated || height.0 % self.node.check_lect_frequency == 0
+    }
+
     #[doc(hidden)]
     pub fn validator_id(&self, context: &ServiceContext) -> ValidatorId {
         context
@@ -52,14 +65,20 @@ impl AnchoringHandler {
             .expect("Request `validator_id` only from validator node.")
     }
 
+    #[doc(hidden)]
+    pub fn signer_id(&self, cfg: &AnchoringConfig, context: &ServiceContext) -> ValidatorId {
+        cfg.signer_id(context.public_key(), context.validators())
+            .expect("Request `signer_id` only from anchoring signer node.")
+    }
+
     #[doc(hidden)]
     pub fn anchoring_key<'a>(
         &self,
         cfg: &'a AnchoringConfig,
         state: &ServiceContext,
     ) -> &'a btc::PublicKey {
-        let validator_id = self.validator_id(state);
-        &cfg.anchoring_keys[validator_id.0 as usize]
+        let signer_id = self.signer_id(cfg, state);
+        &cfg.anchoring_keys[signer_id.0 as usize]
     }
 
     #[doc(hidden)]
@@ -73,18 +92,21 @@ impl AnchoringHandler {
             .as_ref()
     }
 
+    /// Returns the signer of the anchoring inputs, which is the remote signer if it is
+    /// enabled or the `private_keys` of the node otherwise.
+    pub fn signer<'a>(&'a self) -> Result<Box<Signer + 'a>, ServiceError> {
+        if self.node.signer.enabled {
+            Ok(Box::new(RemoteSigner::new(&self.node.signer)?))
+        } else {
+            Ok(Box::new(LocalSigner::new(&self.node.private_keys)))
+        }
+    }
+
     #[doc(hidden)]
     pub fn multisig_address<'a>(&self, common: &'a AnchoringConfig) -> MultisigAddress<'a> {
         let (redeem_script, addr) = common.redeem_script();
-        let addr_str = addr.to_base58check();
-        let priv_key = self.node
-            .private_keys
-            .get(&addr_str)
-            .expect(&format!("Expected private key for address={}", addr_str))
-            .clone();
         MultisigAddress {
             common,
-            priv_key,
             redeem_script,
             addr,
         }
@@ -157,7 +179,7 @@ impl AnchoringHandler {
         // Ensure that bitcoind watching for the current addr
         self.import_address(&actual_addr)?;
 
-        if state.validator_id().is_none() {
+        if actual.signer_id(state.public_key(), state.validators()).is_none() {
             return Ok(AnchoringState::Auditing { cfg: actual });
         }
 
@@ -278,6 +300,8 @@ impl AnchoringHandler {
 
     #[doc(hidden)]
     pub fn handle_commit(&mut self, state: &ServiceContext) -> Result<(), ServiceError> {
+        self.log_rejected_config(state);
+        self.try_update_blocks_mmr(state)?;
         match self.current_state(state)? {
             AnchoringState::Anchoring { cfg } => self.handle_anchoring_state(&cfg, state),
             AnchoringState::Transition { from, to } => {
@@ -296,6 +320,22 @@ impl AnchoringHandler {
         }
     }
 
+    // The rejected configuration is skipped by the schema, so it is reported once
+    // at the height from which it should have been actual.
+    fn log_rejected_config(&self, state: &ServiceContext) {
+        let height = state.height().next();
+        let schema = AnchoringSchema::new(state.snapshot());
+        if let Some((cfg_hash, e)) = schema.rejected_config_at(height) {
+            error!(
+                "Anchoring config {} actual from height {} is rejected: {}, the previous \
+                 config remains actual",
+                cfg_hash.to_hex(),
+                height,
+                e
+            );
+        }
+    }
+
     #[doc(hidden)]
     pub fn collect_lects_for_validator(
         &self,
@@ -312,8 +352,6 @@ impl AnchoringHandler {
         };
 
         let mut count = 0;
-
-        let validators_count = state.validators().len() as u32;
         for key in &anchoring_cfg.anchoring_keys {
             let validators_lect = anchoring_schema.lect(key);
             if Some(&our_lect) == validators_lect.as_ref() {
@@ -321,7 +359,7 @@ impl AnchoringHandler {
             }
         }
 
-        if count >= ::majority_count(validators_count as u8) {
+        if count >= anchoring_cfg.majority_count() {
             match TxKind::from(our_lect) {
                 TxKind::Anchoring(tx) => LectKind::Anchoring(tx),
                 TxKind::FundingTx(tx) => LectKind::Funding(tx),
@@ -381,7 +419,8 @@ impl AnchoringHandler {
         multisig: &MultisigAddress,
         state: &ServiceContext,
     ) -> Result<Option<BitcoinTx>, ServiceError> {
-        let key = self.anchoring_key(multisig.common, state);
+        let signer_id = self.signer_id(multisig.common, state);
+        let key = &multisig.common.anchoring_keys[signer_id.0 as usize];
         trace!("Update our lect");
         if let Some(lect) = self.find_lect(multisig, state)? {
             // New lect with different signatures set.
@@ -393,7 +432,7 @@ impl AnchoringHandler {
             };
 
             if Some(&lect) != our_lect.as_ref() {
-                self.send_updated_lect(&lect, lects_count, state);
+                self.send_updated_lect(&lect, lects_count, signer_id, state);
             }
 
             Ok(Some(lect))
@@ -489,7 +528,13 @@ impl AnchoringHandler {
     }
 
     #[doc(hidden)]
-    fn send_updated_lect(&mut self, lect: &BitcoinTx, lects_count: u64, state: &ServiceContext) {
+    fn send_updated_lect(
+        &mut self,
+        lect: &BitcoinTx,
+        lects_count: u64,
+        signer_id: ValidatorId,
+        state: &ServiceContext,
+    ) {
         if self.proposal_tx.is_some() {
             self.proposal_tx = None;
         }
@@ -502,7 +547,7 @@ impl AnchoringHandler {
 
         let lect_msg = MsgAnchoringUpdateLatest::new(
             state.public_key(),
-            self.validator_id(state),
+            signer_id,
             lect.clone(),
             lects_count,
             state.secret_key(),
@@ -26,9 +26,10 @@ use std::sync::mpsc;
 
 use details::rpc::BitcoinRelay;
 use details::btc;
+use details::notifier::ChainUpdates;
 use details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx};
 use local_storage::AnchoringNodeConfig;
-use blockchain::consensus_storage::AnchoringConfig;
+use blockchain::consensus_storage::{AnchoringConfig, AnchoringScript};
 use blockchain::dto::MsgAnchoringSignature;
 
 /// Internal anchoring service handler. Can be used to manage the service.
@@ -44,13 +45,16 @@ pub struct AnchoringHandler {
     pub errors_sink: Option<mpsc::Sender<error::Error>>,
     #[doc(hidden)]
     pub known_addresses: HashSet<String>,
+    #[doc(hidden)]
+    pub chain_updates: ChainUpdates,
+    #[doc(hidden)]
+    pub time_mark_turn: Option<(u64, u64)>,
 }
 
 #[doc(hidden)]
 #[derive(Debug)]
 pub struct MultisigAddress<'a> {
     pub common: &'a AnchoringConfig,
-    pub priv_key: btc::PrivateKey,
     pub addr: btc::Address,
     pub redeem_script: btc::RedeemScript,
 }
@@ -105,31 +109,49 @@ where
     for msg in msgs {
         let input = msg.input();
         let validator = msg.validator().0 as usize;
+        // CHECKMULTISIG requires the signatures in the order of the keys in the redeem script.
+        let position = common.script_key_position(validator)?;
 
         let signatures_by_input = signatures.get_mut(&input).unwrap();
-        signatures_by_input[validator] = Some(msg.signature().to_vec());
+        signatures_by_input[position] = Some(msg.signature().to_vec());
     }
 
     let majority_count = common.majority_count() as usize;
 
-    // remove "holes" from signatures preserve order
     let mut actual_signatures = HashMap::new();
     for (input, signatures) in signatures {
-        let signatures = signatures
-            .into_iter()
-            .filter_map(|x| x)
-            .take(majority_count)
-            .collect::<Vec<_>>();
-
+        let count = signatures.iter().filter(|x| x.is_some()).count();
         trace!(
             "signatures for input={}, count={}, majority_count={}",
             input,
-            signatures.len(),
+            count,
             majority_count
         );
-        if signatures.len() < majority_count {
+        if count < majority_count {
             return None;
         }
+
+        let signatures = if common.script == AnchoringScript::ChainedChecksig {
+            // keep exactly `majority_count` signatures and fill the "holes" by empty items
+            let mut taken = 0;
+            signatures
+                .into_iter()
+                .map(|x| match x {
+                    Some(signature) if taken < majority_count => {
+                        taken += 1;
+                        signature
+                    }
+                    _ => Vec::new(),
+                })
+                .collect::<Vec<_>>()
+        } else {
+            // remove "holes" from signatures preserve order
+            signatures
+                .into_iter()
+                .filter_map(|x| x)
+                .take(majority_count)
+                .collect::<Vec<_>>()
+        };
         actual_signatures.insert(input, signatures);
     }
     Some(actual_signatures)
@@ -43,7 +43,7 @@ impl AnchoringHandler {
         );
 
         // Similar we update lect each n blocks
-        if state.height().0 % self.node.check_lect_frequency == 0 {
+        if self.need_check_lect(state.height()) {
             // First of all we try to update our lect and actual configuration
             self.update_our_lect(&multisig, state)?;
         }
@@ -67,7 +67,8 @@ impl AnchoringHandler {
                         .get_transaction_confirmations(lect.id())?
                         .unwrap_or_else(|| 0);
                     if confirmations >= multisig.common.utxo_confirmations {
-                        let height = multisig.common.latest_anchoring_height(state.height());
+                        let height = AnchoringSchema::new(state.snapshot())
+                            .latest_anchoring_height(multisig.common, state.height());
                         self.create_proposal_tx(&lect, &multisig, height, state)?;
                     } else {
                         warn!(
@@ -111,7 +112,7 @@ impl AnchoringHandler {
     ) -> Result<(), ServiceError> {
         let multisig: MultisigAddress = self.multisig_address(actual_cfg);
 
-        if state.height().0 % self.node.check_lect_frequency == 0 {
+        if self.need_check_lect(state.height()) {
             // First of all we try to update our lect and actual configuration
             self.update_our_lect(&multisig, state)?;
         }
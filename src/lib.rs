@@ -57,16 +57,23 @@
 
 #![deny(missing_docs, missing_debug_implementations)]
 
+extern crate base64;
 extern crate bitcoin;
 extern crate byteorder;
+extern crate chacha20poly1305;
+extern crate clap;
 #[macro_use]
 extern crate display_derive;
 extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate exonum_jsonrpc as jsonrpc;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+extern crate hyper;
 #[macro_use]
 extern crate log;
+extern crate rpassword;
+extern crate scrypt;
 extern crate secp256k1;
 extern crate serde;
 #[macro_use]
@@ -77,6 +84,7 @@ extern crate toml;
 
 #[macro_use]
 extern crate exonum;
+extern crate exonum_time;
 extern crate iron;
 extern crate rand;
 extern crate router;
@@ -84,6 +92,8 @@ extern crate router;
 #[cfg(test)]
 #[macro_use]
 extern crate pretty_assertions;
+#[cfg(test)]
+extern crate tempdir;
 
 #[doc(hidden)]
 pub mod details;
@@ -101,8 +111,9 @@ pub mod observer;
 pub mod cmd;
 
 pub use details::btc::{gen_btc_keypair, gen_btc_keypair_with_rng, Network as BitcoinNetwork};
-pub use details::rpc::{AnchoringRpcConfig, BitcoinRelay, RpcClient};
-pub use blockchain::consensus_storage::AnchoringConfig;
+pub use details::rpc::{AnchoringRpcConfig, BitcoinRelay, RpcClient, RpcConnection,
+                       UtxoDiscovery};
+pub use blockchain::consensus_storage::{AnchoringConfig, ConfigError, PayloadCommitment};
 pub use local_storage::AnchoringNodeConfig;
 pub use service::{gen_anchoring_testnet_config, gen_anchoring_testnet_config_with_rng,
                   AnchoringService, ANCHORING_SERVICE_ID, ANCHORING_SERVICE_NAME};
@@ -112,5 +123,5 @@ pub use error::Error;
 
 #[doc(hidden)]
 pub fn majority_count(cnt: u8) -> u8 {
-    cnt * 2 / 3 + 1
+    (u16::from(cnt) * 2 / 3 + 1) as u8
 }
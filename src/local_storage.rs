@@ -19,6 +19,9 @@ use std::collections::BTreeMap;
 
 use details::rpc::AnchoringRpcConfig;
 use details::btc;
+use details::keystore::{EncryptedKey, KdfParams, KeystoreConfig, KeystoreError};
+use details::notifier::ChainNotifierConfig;
+use details::signer::RemoteSignerConfig;
 use observer::AnchoringObserverConfig;
 
 /// Private part of anchoring service configuration stored on a local machine.
@@ -27,13 +30,23 @@ pub struct AnchoringNodeConfig {
     /// Rpc configuration. Must exist if node is validator.
     /// Otherwise node can only check `lect` payload without any checks with `bitcoind`.
     pub rpc: Option<AnchoringRpcConfig>,
-    #[serde(serialize_with = "serialize_map_to_vec", deserialize_with = "deserialize_vec_to_map")]
+    #[serde(default, serialize_with = "serialize_map_to_vec",
+            deserialize_with = "deserialize_vec_to_map")]
     /// Set of private keys for each anchoring address.
     pub private_keys: BTreeMap<String, btc::PrivateKey>,
+    /// Private keys encrypted by the passphrase, which are unlocked at startup.
+    #[serde(default)]
+    pub keystore: KeystoreConfig,
     /// Frequency of lect check in blocks.
     pub check_lect_frequency: u64,
     /// Anchoring observer config.
     pub observer: AnchoringObserverConfig,
+    /// Bitcoin blocks notifier config.
+    #[serde(default)]
+    pub notifier: ChainNotifierConfig,
+    /// Remote signer config.
+    #[serde(default)]
+    pub signer: RemoteSignerConfig,
 }
 
 impl AnchoringNodeConfig {
@@ -44,6 +57,34 @@ impl AnchoringNodeConfig {
             ..Default::default()
         }
     }
+
+    /// Encrypts the `private_keys` by the passphrase and moves them into the `keystore`.
+    pub fn encrypt_keys(&mut self, passphrase: &str, kdf: KdfParams) -> Result<(), KeystoreError> {
+        for (address, priv_key) in &self.private_keys {
+            let key = EncryptedKey::encrypt(address, priv_key, passphrase, kdf)?;
+            self.keystore.keys.retain(|other| other.address != *address);
+            self.keystore.keys.push(key);
+        }
+        self.private_keys.clear();
+        Ok(())
+    }
+
+    /// Decrypts the `keystore` by the passphrase and moves the keys into the `private_keys`.
+    /// Nothing is changed if any key can not be decrypted.
+    pub fn decrypt_keys(&mut self, passphrase: &str) -> Result<(), KeystoreError> {
+        let keys = self.keystore.decrypt(passphrase)?;
+        self.private_keys.extend(keys);
+        self.keystore.keys.clear();
+        Ok(())
+    }
+
+    /// Decrypts the `keystore` by the passphrase from the configured source if it is not empty.
+    pub fn unlock_keys(&mut self) -> Result<(), KeystoreError> {
+        let keys = self.keystore.unlock()?;
+        self.private_keys.extend(keys);
+        self.keystore.keys.clear();
+        Ok(())
+    }
 }
 
 impl Default for AnchoringNodeConfig {
@@ -51,7 +92,10 @@ impl Default for AnchoringNodeConfig {
         AnchoringNodeConfig {
             rpc: None,
             observer: AnchoringObserverConfig::default(),
+            notifier: ChainNotifierConfig::default(),
+            signer: RemoteSignerConfig::default(),
             private_keys: BTreeMap::new(),
+            keystore: KeystoreConfig::default(),
             check_lect_frequency: 30,
         }
     }
@@ -67,7 +111,7 @@ struct AnchoringKeypair {
     private_key: btc::PrivateKey,
 }
 
-fn serialize_map_to_vec<S>(
+pub(crate) fn serialize_map_to_vec<S>(
     map: &BTreeMap<String, btc::PrivateKey>,
     serializer: S,
 ) -> Result<S::Ok, S::Error>
@@ -84,7 +128,7 @@ where
     keypairs.serialize(serializer)
 }
 
-fn deserialize_vec_to_map<'de, D>(
+pub(crate) fn deserialize_vec_to_map<'de, D>(
     deserializer: D,
 ) -> Result<BTreeMap<String, btc::PrivateKey>, D::Error>
 where
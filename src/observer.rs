@@ -22,10 +22,13 @@ use bitcoin::util::base58::ToBase58;
 use exonum::blockchain::{Blockchain, Schema};
 use exonum::storage::Fork;
 
-use details::rpc::{AnchoringRpcConfig, BitcoinRelay, RpcClient};
+use details::rpc::{AnchoringRpcConfig, BitcoinRelay};
+use details::notifier::ChainNotifier;
+use details::btc::chain::ChainParams;
 use details::btc::transactions::{AnchoringTx, BitcoinTx, TxKind};
 use blockchain::schema::AnchoringSchema;
 use blockchain::consensus_storage::AnchoringConfig;
+use blockchain::transactions::payload_is_correct;
 use error::Error as ServiceError;
 
 /// Type alias for milliseconds.
@@ -58,19 +61,22 @@ pub struct AnchoringChainObserver {
     blockchain: Blockchain,
     client: Box<BitcoinRelay>,
     check_interval: Milliseconds,
+    notifier: Option<Box<ChainNotifier>>,
 }
 
 impl AnchoringChainObserver {
-    /// Constructs observer for the given `blockchain`.
+    /// Constructs observer for the given `blockchain` that anchors to the given `chain`.
     pub fn new(
         blockchain: Blockchain,
         rpc: AnchoringRpcConfig,
+        chain: ChainParams,
         observer: &AnchoringObserverConfig,
     ) -> AnchoringChainObserver {
         AnchoringChainObserver {
             blockchain,
-            client: Box::new(RpcClient::from(rpc)),
+            client: rpc.into_relay(chain),
             check_interval: observer.check_interval,
+            notifier: None,
         }
     }
 
@@ -84,9 +90,17 @@ impl AnchoringChainObserver {
             blockchain,
             client,
             check_interval,
+            notifier: None,
         }
     }
 
+    /// Wakes up the observer by the `notifier` events, in this case the `check_interval`
+    /// becomes the maximum delay between checks.
+    pub fn with_notifier(mut self, notifier: Box<ChainNotifier>) -> AnchoringChainObserver {
+        self.notifier = Some(notifier);
+        self
+    }
+
     /// Runs observer in infinity loop.
     pub fn run(&mut self) -> Result<(), ServiceError> {
         info!(
@@ -101,6 +115,24 @@ impl AnchoringChainObserver {
                     e
                 );
             }
+            self.wait_next_check(duration);
+        }
+    }
+
+    fn wait_next_check(&mut self, duration: Duration) {
+        if let Some(notifier) = self.notifier.as_mut() {
+            match notifier.wait_event(duration) {
+                Ok(Some(event)) => trace!("Received a new bitcoin block, event={:?}", event),
+                Ok(None) => {}
+                Err(e) => {
+                    error!(
+                        "An error during waiting for a new block occurred, msg={:?}",
+                        e
+                    );
+                    sleep(duration);
+                }
+            }
+        } else {
             sleep(duration);
         }
     }
@@ -227,10 +259,7 @@ impl AnchoringChainObserver {
     }
 
     fn lect_payload_is_correct(&self, fork: &Fork, lect: &AnchoringTx) -> bool {
-        let core_schema = Schema::new(fork);
-        let payload = lect.payload();
-        let block_hash = core_schema.block_hash_by_height(payload.block_height);
-        block_hash == Some(payload.block_hash)
+        payload_is_correct(&lect.payload(), fork)
     }
 
     fn is_blockchain_inited(&self, fork: &Fork) -> bool {
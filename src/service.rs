@@ -27,13 +27,16 @@ use router::Router;
 use exonum::blockchain::{ApiContext, Blockchain, Service, ServiceContext, Transaction};
 use exonum::crypto::Hash;
 use exonum::messages::RawTransaction;
+use exonum::node::ApiSender;
 use exonum::encoding::Error as StreamStructError;
 use exonum::storage::{Fork, Snapshot};
 use exonum::api::Api;
 
-use api::PublicApi;
+use api::{PrivateApi, PublicApi};
 use details::btc;
-use details::rpc::{BitcoinRelay, RpcClient};
+use details::btc::chain::ChainParams;
+use details::rpc::BitcoinRelay;
+use details::notifier::LongPollNotifier;
 use local_storage::AnchoringNodeConfig;
 use handler::AnchoringHandler;
 use blockchain::consensus_storage::AnchoringConfig;
@@ -58,10 +61,18 @@ pub struct AnchoringService {
 impl AnchoringService {
     /// Creates a new service instance with the given `consensus` and `local` configurations.
     pub fn new(consensus: AnchoringConfig, local: AnchoringNodeConfig) -> AnchoringService {
-        let client = local.rpc.clone().map(RpcClient::from).map(Into::into);
+        let chain = consensus.chain_params();
+        let client = local.rpc.clone().map(|rpc| rpc.into_relay(chain));
+        let handler = AnchoringHandler::new(client, local);
+        if let (true, Some(rpc)) = (handler.node.notifier.enabled, handler.node.rpc.clone()) {
+            let notifier = LongPollNotifier::new(rpc);
+            handler
+                .chain_updates
+                .listen(Box::new(notifier), handler.node.notifier.timeout);
+        }
         AnchoringService {
             genesis: consensus,
-            handler: Arc::new(Mutex::new(AnchoringHandler::new(client, local))),
+            handler: Arc::new(Mutex::new(handler)),
         }
     }
 
@@ -104,6 +115,9 @@ impl Service for AnchoringService {
     fn initialize(&self, fork: &mut Fork) -> Value {
         let mut handler = self.handler.lock().unwrap();
         let cfg = self.genesis.clone();
+        if let Err(e) = cfg.validate() {
+            panic!("Incorrect genesis anchoring config: {}", e);
+        }
         let (_, addr) = cfg.redeem_script();
         if handler.client.is_some() {
             handler.import_address(&addr).unwrap();
@@ -138,7 +152,27 @@ impl Service for AnchoringService {
     /// See [`PublicApi`](api/struct.PublicApi.html) for details.
     fn public_api_handler(&self, context: &ApiContext) -> Option<Box<Handler>> {
         let handler = self.handler.lock().unwrap();
-        let router = PublicApiHandler::new(context.blockchain(), &handler.node);
+        let router = PublicApiHandler::new(
+            context.blockchain(),
+            context.node_channel().clone(),
+            &handler.node,
+            self.genesis.chain_params(),
+        );
+        Some(Box::new(router))
+    }
+
+    /// Private api implementation.
+    /// See [`PrivateApi`](api/struct.PrivateApi.html) for details.
+    fn private_api_handler(&self, context: &ApiContext) -> Option<Box<Handler>> {
+        let mut router = Router::new();
+        let api = PrivateApi {
+            blockchain: context.blockchain().clone(),
+            sender: context.node_channel().clone(),
+            handler: self.handler(),
+            public_key: *context.public_key(),
+            secret_key: context.secret_key().clone(),
+        };
+        api.wire(&mut router);
         Some(Box::new(router))
     }
 }
@@ -209,17 +243,31 @@ struct PublicApiHandler {
 impl PublicApiHandler {
     /// Creates public api handler instance for the given `blockchain`
     /// and anchoring node `config`.
-    pub fn new(blockchain: &Blockchain, config: &AnchoringNodeConfig) -> PublicApiHandler {
+    pub fn new(
+        blockchain: &Blockchain,
+        sender: ApiSender,
+        config: &AnchoringNodeConfig,
+        chain: ChainParams,
+    ) -> PublicApiHandler {
         let mut router = Router::new();
         let api = PublicApi {
             blockchain: blockchain.clone(),
+            sender,
+            rpc: config.rpc.clone(),
         };
         api.wire(&mut router);
 
         let observer = if config.observer.enabled {
             let rpc_cfg = config.rpc.clone().expect("Rpc config is not setted");
-            let mut observer =
-                AnchoringChainObserver::new(blockchain.clone(), rpc_cfg, &config.observer);
+            let mut observer = AnchoringChainObserver::new(
+                blockchain.clone(),
+                rpc_cfg.clone(),
+                chain,
+                &config.observer,
+            );
+            if config.notifier.enabled {
+                observer = observer.with_notifier(Box::new(LongPollNotifier::new(rpc_cfg)));
+            }
 
             Some(thread::spawn(move || {
                 observer.run().unwrap();
@@ -39,12 +39,14 @@ use bitcoin::blockdata::transaction::SigHashType;
 use bitcoin::network::constants::Network;
 use bitcoin::blockdata::script::Script;
 
-use exonum::blockchain::Transaction;
-use exonum::crypto::{CryptoHash, Hash};
+use exonum::blockchain::{Schema, Transaction};
+use exonum::crypto::{gen_keypair, hash, CryptoHash, Hash};
 use exonum::encoding::serialize::FromHex;
 use exonum::helpers::{Height, ValidatorId};
 
-use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use exonum_btc_anchoring::blockchain::AnchoringSchema;
+use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringNotarize, MsgAnchoringRequest,
+                                            MsgAnchoringSignature, MsgAnchoringUpdateLatest};
 use exonum_btc_anchoring::details::btc::transactions::{verify_tx_input, AnchoringTx, FundingTx,
                                                        TransactionBuilder};
 use testkit_extras::AnchoringTestKit;
@@ -543,6 +545,48 @@ fn test_anchoring_signature_incorrect_payload() {
     assert!(signatures_after.is_empty());
 }
 
+// We received correct signature message for the payload with the foreign prefix
+// problems: None
+// result: we ignore it
+#[test]
+fn test_anchoring_signature_foreign_payload_prefix() {
+    let mut testkit = AnchoringTestKit::default();
+
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+
+    let genesis_hash = Schema::new(&testkit.snapshot())
+        .block_hash_by_height(Height::zero())
+        .unwrap();
+    let (redeem_script, addr) = testkit.current_cfg().redeem_script();
+    let tx = TransactionBuilder::with_prev_tx(&testkit.latest_anchored_tx(), 0)
+        .fee(100)
+        .payload(Height::zero(), genesis_hash)
+        .payload_prefix("OTHER")
+        .send_to(addr.clone())
+        .into_transaction()
+        .unwrap();
+    assert_eq!(tx.payload().prefix, "OTHER");
+    let signature = tx.sign_input(&redeem_script, 0, &testkit.priv_keys(&addr)[1]);
+    let validator_1 = ValidatorId(1);
+    let msg_sign = {
+        let keypair = testkit.validator(validator_1).service_keypair();
+        MsgAnchoringSignature::new(
+            keypair.0,
+            validator_1,
+            tx.clone(),
+            0,
+            signature.as_ref(),
+            keypair.1,
+        )
+    };
+
+    // Commit `msg_sign` into blockchain
+    testkit.create_block_with_transactions(txvec![msg_sign.clone()]);
+    // Ensure that service ignores it
+    assert!(dump_signatures(&testkit, &tx.id()).is_empty());
+}
+
 // We received correct lect with the current funding_tx
 // problems: None
 // result: we add it
@@ -842,3 +886,86 @@ fn test_anchoring_signature_unknown_output_address() {
     let signs_after = dump_signatures(&testkit, &tx.id());
     assert_eq!(signs_before, signs_after);
 }
+
+// Notarize a few digests
+// result: digests are included into the notary root, duplicates and digests
+// from the unauthorized keys are ignored
+#[test]
+fn test_anchoring_notary_digests() {
+    let mut testkit = AnchoringTestKit::default();
+    anchor_first_block(&mut testkit);
+
+    let (pub_key, sec_key) = {
+        let keypair = testkit.validator(ValidatorId(0)).service_keypair();
+        (*keypair.0, keypair.1.clone())
+    };
+    let digests = (0..3).map(|i| hash(&[i])).collect::<Vec<_>>();
+    let txs = digests
+        .iter()
+        .map(|digest| MsgAnchoringNotarize::new(&pub_key, digest, &sec_key))
+        .collect::<Vec<_>>();
+
+    testkit.create_block_with_transactions(txvec![txs[0].clone(), txs[1].clone()]);
+    let first_height = testkit.height();
+    let duplicate = {
+        let keypair = testkit.validator(ValidatorId(1)).service_keypair();
+        MsgAnchoringNotarize::new(keypair.0, &digests[0], keypair.1)
+    };
+    let unauthorized_digest = hash(&[3]);
+    let unauthorized = {
+        let (pub_key, sec_key) = gen_keypair();
+        MsgAnchoringNotarize::new(&pub_key, &unauthorized_digest, &sec_key)
+    };
+    testkit.create_block_with_transactions(txvec![txs[2].clone(), duplicate, unauthorized]);
+    let second_height = testkit.height();
+
+    let snapshot = testkit.snapshot();
+    let schema = AnchoringSchema::new(&snapshot);
+    assert!(!schema.notary_indexes().contains(&unauthorized_digest));
+    assert_eq!(schema.notary_count(first_height.previous()), 0);
+    assert_eq!(schema.notary_count(first_height), 2);
+    assert_eq!(schema.notary_count(second_height), 3);
+    assert_eq!(schema.notary_root(first_height.previous()), Hash::zero());
+
+    let first_root = schema.notary_root(first_height);
+    let second_root = schema.notary_root(second_height);
+    assert_ne!(first_root, second_root);
+    for digest in &digests[0..2] {
+        let proof = schema.notary_proof(digest, first_height).unwrap();
+        assert!(proof.verify(digest, &first_root));
+    }
+    assert_eq!(schema.notary_proof(&digests[2], first_height), None);
+    for digest in &digests {
+        let proof = schema.notary_proof(digest, second_height).unwrap();
+        assert!(proof.verify(digest, &second_root));
+    }
+}
+
+// Request the out-of-schedule anchoring from the unauthorized key and from the validator
+// with the zero fee budget
+// result: requests are ignored
+#[test]
+fn test_anchoring_request_rejected() {
+    let mut testkit = AnchoringTestKit::default();
+    anchor_first_block(&mut testkit);
+
+    let height = testkit.height().0;
+    let unauthorized = {
+        let (pub_key, sec_key) = gen_keypair();
+        MsgAnchoringRequest::new(&pub_key, height, &sec_key)
+    };
+    let out_of_budget = {
+        let keypair = testkit.validator(ValidatorId(0)).service_keypair();
+        MsgAnchoringRequest::new(keypair.0, height, keypair.1)
+    };
+    assert_eq!(testkit.current_cfg().request_fee_budget, 0);
+
+    testkit.create_block_with_transactions(txvec![unauthorized, out_of_budget]);
+    let snapshot = testkit.snapshot();
+    let schema = AnchoringSchema::new(&snapshot);
+    assert_eq!(schema.anchoring_requests().len(), 0);
+    assert_eq!(
+        schema.latest_anchoring_height(&testkit.current_cfg(), testkit.height()),
+        Height::zero()
+    );
+}
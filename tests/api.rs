@@ -32,15 +32,17 @@ extern crate serde_json;
 #[macro_use]
 pub mod testkit_extras;
 
+use exonum::crypto::{gen_keypair, hash, CryptoHash};
 use exonum::messages::Message;
 use exonum::helpers::{Height, ValidatorId};
 use exonum::encoding::serialize::FromHex;
 use exonum_testkit::{ApiKind, TestKitApi};
 
 use exonum_btc_anchoring::ANCHORING_SERVICE_NAME;
-use exonum_btc_anchoring::api::{AnchoringInfo, LectInfo};
+use exonum_btc_anchoring::api::{AnchoringInfo, DecodedTransaction, DecodedTransactionKind,
+                                LectInfo, NotarizeResponse};
 use exonum_btc_anchoring::observer::AnchoringChainObserver;
-use exonum_btc_anchoring::blockchain::dto::MsgAnchoringUpdateLatest;
+use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringNotarize, MsgAnchoringUpdateLatest};
 use exonum_btc_anchoring::details::btc;
 use exonum_btc_anchoring::details::btc::transactions::{AnchoringTx, BitcoinTx};
 use testkit_extras::{AnchoringTestKit, TestClient};
@@ -56,6 +58,8 @@ trait AnchoringApi {
     fn following_address(&self) -> Option<btc::Address>;
 
     fn nearest_lect(&self, height: u64) -> Option<AnchoringTx>;
+
+    fn decode_transaction(&self, hex: &str) -> DecodedTransaction;
 }
 
 impl AnchoringApi for TestKitApi {
@@ -90,6 +94,13 @@ impl AnchoringApi for TestKitApi {
             &format!("/v1/nearest_lect/{}", height),
         )
     }
+
+    fn decode_transaction(&self, hex: &str) -> DecodedTransaction {
+        self.get(
+            ApiKind::Service(ANCHORING_SERVICE_NAME),
+            &format!("/v1/decode_transaction/{}", hex),
+        )
+    }
 }
 
 // Test normal api usage
@@ -163,6 +174,63 @@ fn test_api_public_get_lect_unavailable() {
     assert_eq!(api.actual_lect(), None);
 }
 
+// Decode the anchoring and funding transactions
+// result: Transactions are classified with the payload details
+#[test]
+fn test_api_public_decode_transaction() {
+    let mut testkit = AnchoringTestKit::default();
+    anchor_first_block(&mut testkit);
+
+    let api = testkit.api();
+    let anchored_tx = testkit.latest_anchored_tx();
+    let decoded = api.decode_transaction(&anchored_tx.to_hex());
+    assert_eq!(decoded.txid, anchored_tx.id());
+    assert_eq!(decoded.kind, DecodedTransactionKind::Anchoring);
+    assert_eq!(decoded.payload, Some(anchored_tx.payload()));
+    assert_eq!(decoded.payload_error, None);
+    assert_eq!(decoded.actual_prefix, Some(true));
+
+    let funding_tx = testkit.current_funding_tx();
+    let decoded = api.decode_transaction(&funding_tx.to_hex());
+    assert_eq!(decoded.kind, DecodedTransactionKind::Funding);
+    assert_eq!(decoded.payload, None);
+    assert_eq!(
+        decoded.payload_error,
+        Some("Script does not contain the anchoring payload".to_owned())
+    );
+    assert_eq!(decoded.actual_prefix, None);
+}
+
+// Submit the notarization from the validator
+// result: The notarization is accepted
+#[test]
+fn test_api_public_notarize() {
+    let testkit = AnchoringTestKit::default();
+    let api = testkit.api();
+
+    let tx = {
+        let keypair = testkit.validator(ValidatorId(0)).service_keypair();
+        MsgAnchoringNotarize::new(keypair.0, &hash(&[0]), keypair.1)
+    };
+    let response: NotarizeResponse =
+        api.post(ApiKind::Service(ANCHORING_SERVICE_NAME), "/v1/notary", &tx);
+    assert_eq!(response.tx_hash, tx.hash());
+}
+
+// Submit the notarization from the unauthorized key
+// result: Panic with the bad request error
+#[test]
+#[should_panic(expected = "Received notarization from the unauthorized key")]
+fn test_api_public_notarize_unauthorized() {
+    let testkit = AnchoringTestKit::default();
+    let api = testkit.api();
+
+    let (pub_key, sec_key) = gen_keypair();
+    let tx = MsgAnchoringNotarize::new(&pub_key, &hash(&[0]), &sec_key);
+    let _: NotarizeResponse =
+        api.post(ApiKind::Service(ANCHORING_SERVICE_NAME), "/v1/notary", &tx);
+}
+
 // Try to get actual anchoring address
 #[test]
 fn test_api_public_get_current_address() {
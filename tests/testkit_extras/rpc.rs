@@ -238,8 +238,7 @@ impl Default for TestClient {
             requests: TestRequests::default(),
             rpc: AnchoringRpcConfig {
                 host: "127.0.0.1:1024".into(),
-                username: None,
-                password: None,
+                ..Default::default()
             },
         }
     }